@@ -0,0 +1,404 @@
+//! Async client for the MooseDB HTTP API.
+//!
+//! Requests run on the blocking thread pool of the tokio runtime that awaits them, actix-web and
+//! `#[tokio::main]` applications can use the client as is.
+
+pub use moosedb_types::*;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server could not be reached or the connection broke
+    Transport(String),
+    /// The server answered with an error status
+    Api { status: u16, body: ErrorBody },
+    /// The request body could not be built
+    Encode(String),
+    /// The response was not what the endpoint returns
+    Decode(String),
+    /// The endpoint needs a refresh token and the client has none
+    NotLoggedIn,
+}
+
+impl Error {
+    /// Stable error code of the server, like `not_found` or `conflict`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { body, .. } => Some(&body.code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "Request failed: {}", err),
+            Error::Api { status, body } => {
+                write!(f, "{} ({}): {}", status, body.code, body.message)
+            }
+            Error::Encode(err) => write!(f, "Request could not be encoded: {}", err),
+            Error::Decode(err) => write!(f, "Response could not be decoded: {}", err),
+            Error::NotLoggedIn => write!(f, "Log in first"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What `/auth/login` answered with.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    /// Pass the challenge token and a code to [`Client::login_two_factor`]
+    TwoFactorRequired(ChallengeResponse),
+    Session(LoginResponse),
+}
+
+#[derive(Default)]
+struct Session {
+    token: Option<String>,
+    refresh_token: Option<String>,
+}
+
+/// A connection to one MooseDB server, clones share the session.
+#[derive(Clone)]
+pub struct Client {
+    base_url: String,
+    agent: ureq::Agent,
+    session: Arc<RwLock<Session>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Self {
+        Client {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new().build(),
+            session: Arc::new(RwLock::new(Session::default())),
+        }
+    }
+
+    /// Use an access token that was issued before, for example to a service account.
+    pub fn with_token(self, token: &str) -> Self {
+        self.session.write().unwrap().token = Some(token.to_string());
+        self
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.session.read().unwrap().token.clone()
+    }
+
+    /// Log in as an admin, the tokens are kept for the following requests.
+    pub async fn login(&self, email: &str, password: &str) -> Result<LoginResult> {
+        let body = LoginRequest {
+            email: email.to_string(),
+            password: password.to_string(),
+        };
+        let result: LoginResult = self.send("POST", "/auth/login", Some(body)).await?;
+        if let LoginResult::Session(tokens) = &result {
+            self.keep(tokens);
+        }
+        Ok(result)
+    }
+
+    pub async fn login_two_factor(
+        &self,
+        challenge_token: &str,
+        code: &str,
+    ) -> Result<LoginResponse> {
+        let body = TwoFactorLoginRequest {
+            challenge_token: challenge_token.to_string(),
+            code: code.to_string(),
+        };
+        let tokens: LoginResponse = self.send("POST", "/auth/login/2fa", Some(body)).await?;
+        self.keep(&tokens);
+        Ok(tokens)
+    }
+
+    /// Swap the refresh token for a new pair of tokens.
+    pub async fn refresh(&self) -> Result<LoginResponse> {
+        let refresh_token = self
+            .session
+            .read()
+            .unwrap()
+            .refresh_token
+            .clone()
+            .ok_or(Error::NotLoggedIn)?;
+        let body = RefreshRequest { refresh_token };
+        let tokens: LoginResponse = self.send("POST", "/auth/refresh", Some(body)).await?;
+        self.keep(&tokens);
+        Ok(tokens)
+    }
+
+    /// Revoke the session on the server and forget its tokens.
+    pub async fn logout(&self) -> Result<Response> {
+        let response = self.send("POST", "/admin/api/logout", None::<()>).await?;
+        *self.session.write().unwrap() = Session::default();
+        Ok(response)
+    }
+
+    pub async fn collections(&self) -> Result<Vec<CollectionSummary>> {
+        let response: CollectionsResponse = self
+            .send("GET", "/admin/api/collections", None::<()>)
+            .await?;
+        Ok(response.collections)
+    }
+
+    pub async fn create_collection(
+        &self,
+        collection: &str,
+        fields: Vec<CollectionFields>,
+    ) -> Result<Response> {
+        let body = CollectionRequest {
+            collection: collection.to_string(),
+            fields,
+        };
+        self.send("POST", "/admin/api/create-collection", Some(body))
+            .await
+    }
+
+    pub async fn update_collection(&self, request: UpdateCollectionRequest) -> Result<Response> {
+        self.send("POST", "/admin/api/update-collection", Some(request))
+            .await
+    }
+
+    pub async fn delete_collection(&self, collection_id: &str) -> Result<Response> {
+        let body = CollectionID {
+            collection_id: collection_id.to_string(),
+        };
+        self.send("POST", "/admin/api/delete-collection", Some(body))
+            .await
+    }
+
+    /// Every record of a collection with its columns, without pagination.
+    pub async fn collection_records<T: DeserializeOwned + Send + 'static>(
+        &self,
+        collection_id: &str,
+    ) -> Result<CollectionRecords<T>> {
+        let body = CollectionID {
+            collection_id: collection_id.to_string(),
+        };
+        self.send("POST", "/admin/api/get-collection-records", Some(body))
+            .await
+    }
+
+    /// Create a record and return its id. Files are sent as [`FileUpload`] values.
+    pub async fn create_record(&self, collection_id: &str, data: impl Serialize) -> Result<String> {
+        let body = CreateRecordRequest {
+            collection_id: collection_id.to_string(),
+            data: to_data(data)?,
+        };
+        let response: CreateRecordResponse = self
+            .send("POST", "/admin/api/create-record", Some(body))
+            .await?;
+        Ok(response.id)
+    }
+
+    /// Update the record whose unique field `on` has the value in `data`, or create one when
+    /// there is none. The response's `status` tells which of the two happened.
+    pub async fn upsert_record(
+        &self,
+        collection_id: &str,
+        on: &str,
+        data: impl Serialize,
+    ) -> Result<CreateRecordResponse> {
+        let body = CreateRecordRequest {
+            collection_id: collection_id.to_string(),
+            data: to_data(data)?,
+        };
+        let path = format!("/admin/api/create-record?upsert_on={}", encode(on));
+        self.send("POST", &path, Some(body)).await
+    }
+
+    pub async fn delete_records(
+        &self,
+        collection_id: &str,
+        record_ids: Vec<String>,
+    ) -> Result<DeleteResponse> {
+        let body = DeleteCollectionRecords {
+            collection_id: collection_id.to_string(),
+            record_ids,
+        };
+        self.send("POST", "/admin/api/delete-collection-records", Some(body))
+            .await
+    }
+
+    /// Run several record operations in one transaction, either all of them are applied or none.
+    /// A failed operation is reported as an [`Error::Api`] whose details name its index.
+    pub async fn batch(&self, operations: Vec<BatchOperation>) -> Result<BatchResponse> {
+        let body = BatchRequest { operations };
+        self.send("POST", "/api/batch", Some(body)).await
+    }
+
+    /// One page of a collection, `items` falls back to the records_per_page setting.
+    pub async fn list_records<T: DeserializeOwned + Send + 'static>(
+        &self,
+        collection_id: &str,
+        page: u32,
+        items: Option<u32>,
+    ) -> Result<RecordsResponse<T>> {
+        let mut path = format!("/api/records/{}?page={}", encode(collection_id), page);
+        if let Some(items) = items {
+            path.push_str(&format!("&items={}", items));
+        }
+        self.send("GET", &path, None::<()>).await
+    }
+
+    pub async fn get_record<T: DeserializeOwned + Send + 'static>(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<T> {
+        let path = format!(
+            "/api/records/{}/{}",
+            encode(collection_id),
+            encode(record_id)
+        );
+        let response: SingleRecordResponse<T> = self.send("GET", &path, None::<()>).await?;
+        Ok(response.record)
+    }
+
+    /// Walk a collection page by page.
+    pub fn pages<T: DeserializeOwned + Send + 'static>(
+        &self,
+        collection_id: &str,
+        items: Option<u32>,
+    ) -> Pages<T> {
+        Pages {
+            client: self.clone(),
+            collection_id: collection_id.to_string(),
+            items,
+            next: Some(1),
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Fetch an uploaded file by the `url` of its [`StoredFile`].
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let request = self.request("GET", url);
+        run(move || {
+            let response = request.call().map_err(api_error)?;
+            let mut bytes = Vec::new();
+            response
+                .into_reader()
+                .read_to_end(&mut bytes)
+                .map_err(|e| Error::Transport(e.to_string()))?;
+            Ok(bytes)
+        })
+        .await
+    }
+
+    fn keep(&self, tokens: &LoginResponse) {
+        let mut session = self.session.write().unwrap();
+        session.token = Some(tokens.token.clone());
+        session.refresh_token = Some(tokens.refresh_token.clone());
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let url = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_string()
+        } else {
+            format!("{}{}", self.base_url, url)
+        };
+        let request = self.agent.request(method, &url);
+        match self.token() {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    async fn send<B, T>(&self, method: &str, path: &str, body: Option<B>) -> Result<T>
+    where
+        B: Serialize + Send + 'static,
+        T: DeserializeOwned + Send + 'static,
+    {
+        let request = self.request(method, path);
+        run(move || {
+            let response = match body {
+                Some(body) => request.send_json(body),
+                None => request.call(),
+            }
+            .map_err(api_error)?;
+            response
+                .into_json()
+                .map_err(|e| Error::Decode(e.to_string()))
+        })
+        .await
+    }
+}
+
+/// Pages of a collection, see [`Client::pages`].
+pub struct Pages<T> {
+    client: Client,
+    collection_id: String,
+    items: Option<u32>,
+    next: Option<u32>,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> Pages<T> {
+    /// Records of the next page, `None` after the last one.
+    pub async fn next(&mut self) -> Result<Option<Vec<T>>> {
+        let Some(page) = self.next else {
+            return Ok(None);
+        };
+        let response: RecordsResponse<T> = self
+            .client
+            .list_records(&self.collection_id, page, self.items)
+            .await?;
+        self.next = response.pagination.has_next_page.then_some(page + 1);
+        Ok(Some(response.records))
+    }
+
+    pub async fn collect_all(mut self) -> Result<Vec<T>> {
+        let mut records = Vec::new();
+        while let Some(page) = self.next().await? {
+            records.extend(page);
+        }
+        Ok(records)
+    }
+}
+
+async fn run<T: Send + 'static>(request: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(request)
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))?
+}
+
+fn api_error(err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Status(status, response) => match response.into_json::<ErrorBody>() {
+            Ok(body) => Error::Api { status, body },
+            Err(err) => Error::Decode(format!("{} response: {}", status, err)),
+        },
+        ureq::Error::Transport(err) => Error::Transport(err.to_string()),
+    }
+}
+
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn to_data(record: impl Serialize) -> Result<Map<String, Value>> {
+    match serde_json::to_value(record) {
+        Ok(Value::Object(data)) => Ok(data),
+        Ok(_) => Err(Error::Encode("Record data must be an object".to_string())),
+        Err(err) => Err(Error::Encode(err.to_string())),
+    }
+}
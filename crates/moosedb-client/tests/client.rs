@@ -0,0 +1,906 @@
+use moosedb::MooseDb;
+use moosedb_client::{
+    BatchOperation, Client, CollectionFields, FileUpload, LoginResult, RecordStatus, StoredFile,
+    UpdateCollectionRequest,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Super admin that the first migration creates
+const EMAIL: &str = "admin@moosedb.com";
+const PASSWORD: &str = "moosedb";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Book {
+    #[serde(skip_serializing)]
+    id: String,
+    title: String,
+    pages: i64,
+}
+
+// Starts a server on a free port with a fresh data directory and logs in to it
+async fn logged_in(name: &str) -> Client {
+    let client = anonymous(name);
+    match client.login(EMAIL, PASSWORD).await.unwrap() {
+        LoginResult::Session(_) => client,
+        LoginResult::TwoFactorRequired(_) => panic!("A fresh admin has no two-factor"),
+    }
+}
+
+fn data_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("moosedb-client-{}-{}", name, std::process::id()))
+}
+
+// Names of the objects the local storage of server `name` holds
+fn stored_files(name: &str) -> Vec<String> {
+    match std::fs::read_dir(data_dir(name).join("uploads")) {
+        Ok(entries) => entries
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn anonymous(name: &str) -> Client {
+    Client::new(&serve(name))
+}
+
+// Starts a server with a fresh data directory and returns its base URL
+fn serve(name: &str) -> String {
+    let data_dir = data_dir(name);
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let db = MooseDb::builder()
+        .data_dir(data_dir)
+        .port(0)
+        .build()
+        .unwrap();
+    let (server, addrs) = db.serve().unwrap();
+    actix_web::rt::spawn(server);
+    format!("http://{}", addrs[0])
+}
+
+// Status of a POST to `/admin/api/<path>` made with `token`
+async fn post_status(base_url: &str, path: &str, token: &str, body: serde_json::Value) -> u16 {
+    let url = format!("{}/admin/api/{}", base_url, path);
+    let bearer = format!("Bearer {}", token);
+    actix_web::rt::task::spawn_blocking(move || {
+        match ureq::post(&url)
+            .set("Authorization", &bearer)
+            .send_json(body)
+        {
+            Ok(response) => response.status(),
+            Err(ureq::Error::Status(status, _)) => status,
+            Err(err) => panic!("{}", err),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+async fn books(client: &Client) -> String {
+    client
+        .create_collection(
+            "books",
+            vec![
+                CollectionFields {
+                    nullable: false,
+                    ..CollectionFields::new("title", "VARCHAR")
+                },
+                CollectionFields::new("pages", "INTEGER"),
+            ],
+        )
+        .await
+        .unwrap();
+
+    client
+        .collections()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|c| c.table_name == "books")
+        .unwrap()
+        .table_id
+}
+
+#[actix_web::test]
+async fn login_refresh_and_logout() {
+    let client = anonymous("session");
+
+    let err = client.login(EMAIL, "wrong").await.unwrap_err();
+    assert_eq!(err.code(), Some("unauthorized"));
+    let err = client.collections().await.unwrap_err();
+    assert_eq!(err.code(), Some("unauthorized"));
+
+    client.login(EMAIL, PASSWORD).await.unwrap();
+    let refreshed = client.refresh().await.unwrap();
+    assert_eq!(client.token(), Some(refreshed.token));
+    client.collections().await.unwrap();
+
+    client.logout().await.unwrap();
+    assert_eq!(client.token(), None);
+    assert!(client.refresh().await.is_err());
+}
+
+#[actix_web::test]
+async fn manage_collections() {
+    let client = logged_in("collections").await;
+    let books = books(&client).await;
+
+    let err = client
+        .create_collection("books", vec![CollectionFields::new("title", "VARCHAR")])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("conflict"));
+
+    client
+        .update_collection(UpdateCollectionRequest {
+            collection_id: books.clone(),
+            collection_name: Some("novels".to_string()),
+            fields: vec![CollectionFields::new("author", "VARCHAR")],
+        })
+        .await
+        .unwrap();
+    let records = client.collection_records::<Book>(&books).await.unwrap();
+    assert!(records.columns.iter().any(|c| c.name == "author"));
+    let names: Vec<String> = client
+        .collections()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| c.table_name)
+        .collect();
+    assert_eq!(names, ["novels"]);
+
+    client.delete_collection(&books).await.unwrap();
+    assert!(client.collections().await.unwrap().is_empty());
+    let err = client.delete_collection(&books).await.unwrap_err();
+    assert_eq!(err.code(), Some("not_found"));
+}
+
+#[actix_web::test]
+async fn renaming_onto_a_taken_name_changes_nothing() {
+    let client = logged_in("rename").await;
+    let books = books(&client).await;
+    client
+        .create_collection("covers", vec![CollectionFields::new("image", "FILE")])
+        .await
+        .unwrap();
+    let covers = client
+        .collections()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|c| c.table_name == "covers")
+        .unwrap()
+        .table_id;
+    let id = client
+        .create_record(
+            &covers,
+            json!({ "image": FileUpload::new("front.txt", "text/plain", b"front") }),
+        )
+        .await
+        .unwrap();
+
+    let err = client
+        .update_collection(UpdateCollectionRequest {
+            collection_id: covers.clone(),
+            collection_name: Some("books".to_string()),
+            fields: vec![CollectionFields::new("caption", "VARCHAR")],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("conflict"));
+
+    let records = client
+        .collection_records::<serde_json::Value>(&covers)
+        .await
+        .unwrap();
+    let columns: Vec<&str> = records.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(columns, ["id", "image", "created_at", "updated_at"]);
+    let record: serde_json::Value = client.get_record(&covers, &id).await.unwrap();
+    assert_eq!(record["image"][0]["original_name"], "front.txt");
+    assert_eq!(stored_files("rename").len(), 1);
+
+    client
+        .create_record(&books, json!({ "title": "Still books", "pages": 10 }))
+        .await
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn record_crud_and_pages() {
+    let client = logged_in("records").await;
+    let books = books(&client).await;
+
+    let mut ids = Vec::new();
+    for pages in 1..=5 {
+        let book = Book {
+            id: String::new(),
+            title: format!("Volume {}", pages),
+            pages,
+        };
+        ids.push(client.create_record(&books, &book).await.unwrap());
+    }
+
+    let err = client
+        .create_record(&books, json!({ "pages": 10 }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("validation_failed"));
+
+    let book: Book = client.get_record(&books, &ids[2]).await.unwrap();
+    assert_eq!(book.id, ids[2]);
+    assert_eq!(book.title, "Volume 3");
+
+    let page = client
+        .list_records::<Book>(&books, 1, Some(2))
+        .await
+        .unwrap();
+    assert_eq!(page.records.len(), 2);
+    assert_eq!(page.pagination.total_records, 5);
+    assert!(page.pagination.has_next_page);
+
+    let mut pages = client.pages::<Book>(&books, Some(2));
+    let mut sizes = Vec::new();
+    while let Some(records) = pages.next().await.unwrap() {
+        sizes.push(records.len());
+    }
+    assert_eq!(sizes, [2, 2, 1]);
+    let mut all: Vec<i64> = client
+        .pages::<Book>(&books, Some(2))
+        .collect_all()
+        .await
+        .unwrap()
+        .iter()
+        .map(|b| b.pages)
+        .collect();
+    all.sort();
+    assert_eq!(all, [1, 2, 3, 4, 5]);
+
+    let deleted = client
+        .delete_records(&books, ids[..2].to_vec())
+        .await
+        .unwrap();
+    assert_eq!(deleted.deleted_count, 2);
+    let err = client
+        .get_record::<Book>(&books, &ids[0])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("not_found"));
+    let rest = client.pages::<Book>(&books, None).collect_all().await;
+    assert_eq!(rest.unwrap().len(), 3);
+}
+
+#[actix_web::test]
+async fn upload_and_download_a_file() {
+    let client = logged_in("files").await;
+    client
+        .create_collection(
+            "notes",
+            vec![
+                CollectionFields::new("title", "VARCHAR"),
+                CollectionFields {
+                    allowed_extensions: Some("txt".to_string()),
+                    ..CollectionFields::new("attachment", "FILE")
+                },
+            ],
+        )
+        .await
+        .unwrap();
+    let notes = client.collections().await.unwrap()[0].table_id.clone();
+
+    let content = b"Meeting notes\nBring the moose.\n";
+    let id = client
+        .create_record(
+            &notes,
+            json!({
+                "title": "Monday",
+                "attachment": FileUpload::new("monday.txt", "text/plain", content),
+            }),
+        )
+        .await
+        .unwrap();
+
+    #[derive(Deserialize)]
+    struct Note {
+        attachment: Vec<StoredFile>,
+    }
+    let note: Note = client.get_record(&notes, &id).await.unwrap();
+    let file = &note.attachment[0];
+    assert_eq!(file.original_name, "monday.txt");
+    assert_eq!(file.size, content.len() as i64);
+    assert_eq!(client.download(&file.url).await.unwrap(), content);
+    assert_eq!(stored_files("files").len(), 1);
+
+    client.delete_records(&notes, vec![id]).await.unwrap();
+    assert!(stored_files("files").is_empty());
+
+    let err = client
+        .create_record(
+            &notes,
+            json!({ "attachment": FileUpload::new("script.sh", "text/plain", b"echo") }),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("validation_failed"));
+}
+
+#[actix_web::test]
+async fn batch_is_all_or_nothing() {
+    let client = logged_in("batch").await;
+    for (name, fields) in [
+        (
+            "orders",
+            vec![CollectionFields {
+                unique: true,
+                ..CollectionFields::new("number", "VARCHAR")
+            }],
+        ),
+        (
+            "items",
+            vec![
+                CollectionFields::new("order_id", "VARCHAR"),
+                CollectionFields {
+                    nullable: false,
+                    ..CollectionFields::new("sku", "VARCHAR")
+                },
+            ],
+        ),
+    ] {
+        client.create_collection(name, fields).await.unwrap();
+    }
+    let collections = client.collections().await.unwrap();
+    let id_of = |name: &str| {
+        collections
+            .iter()
+            .find(|c| c.table_name == name)
+            .unwrap()
+            .table_id
+            .clone()
+    };
+    let (orders, items) = (id_of("orders"), id_of("items"));
+
+    let create = |collection: &str, data: serde_json::Value, reference: Option<&str>| {
+        BatchOperation::Create {
+            collection_id: collection.to_string(),
+            data: data.as_object().unwrap().clone(),
+            reference: reference.map(str::to_string),
+        }
+    };
+
+    let response = client
+        .batch(vec![
+            create(&orders, json!({ "number": "A-1" }), Some("order")),
+            create(
+                &items,
+                json!({ "order_id": { "$ref": "order" }, "sku": "moose" }),
+                None,
+            ),
+            create(
+                &items,
+                json!({ "order_id": { "$ref": 0 }, "sku": "elk" }),
+                None,
+            ),
+        ])
+        .await
+        .unwrap();
+    let order_id = response.results[0].id.clone();
+    assert!(
+        response
+            .results
+            .iter()
+            .all(|r| r.status == RecordStatus::Created)
+    );
+    let item: serde_json::Value = client
+        .get_record(&items, &response.results[1].id)
+        .await
+        .unwrap();
+    assert_eq!(item["order_id"], order_id.as_str());
+
+    // The second item is invalid, so the order and the first item are not kept either
+    let err = client
+        .batch(vec![
+            create(&orders, json!({ "number": "A-2" }), Some("order")),
+            create(
+                &items,
+                json!({ "order_id": { "$ref": "order" }, "sku": "bear" }),
+                None,
+            ),
+            create(&items, json!({ "order_id": { "$ref": "order" } }), None),
+        ])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("validation_failed"));
+    let moosedb_client::Error::Api { body, .. } = &err else {
+        panic!("Expected an API error");
+    };
+    assert_eq!(body.details.as_ref().unwrap()["operation"], 2);
+    let counts = |collection: String| {
+        let client = client.clone();
+        async move {
+            client
+                .list_records::<serde_json::Value>(&collection, 1, None)
+                .await
+                .unwrap()
+                .pagination
+                .total_records
+        }
+    };
+    assert_eq!(counts(orders.clone()).await, 1);
+    assert_eq!(counts(items.clone()).await, 2);
+
+    let response = client
+        .batch(vec![
+            BatchOperation::Upsert {
+                collection_id: orders.clone(),
+                on: "number".to_string(),
+                data: json!({ "number": "A-1" }).as_object().unwrap().clone(),
+                reference: None,
+            },
+            BatchOperation::Update {
+                collection_id: items.clone(),
+                id: json!(response.results[2].id),
+                data: json!({ "sku": "reindeer" }).as_object().unwrap().clone(),
+                reference: None,
+            },
+            BatchOperation::Delete {
+                collection_id: items.clone(),
+                id: json!(response.results[1].id),
+            },
+        ])
+        .await
+        .unwrap();
+    let statuses: Vec<RecordStatus> = response.results.iter().map(|r| r.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            RecordStatus::Updated,
+            RecordStatus::Updated,
+            RecordStatus::Deleted
+        ]
+    );
+    assert_eq!(response.results[0].id, order_id);
+    assert_eq!(counts(items.clone()).await, 1);
+
+    let too_many = (0..101)
+        .map(|i| create(&orders, json!({ "number": format!("B-{}", i) }), None))
+        .collect();
+    let err = client.batch(too_many).await.unwrap_err();
+    assert_eq!(err.code(), Some("bad_request"));
+}
+
+#[actix_web::test]
+async fn upsert_on_a_unique_field() {
+    let client = logged_in("upsert").await;
+    client
+        .create_collection(
+            "people",
+            vec![
+                CollectionFields {
+                    unique: true,
+                    ..CollectionFields::new("email", "VARCHAR")
+                },
+                CollectionFields::new("name", "VARCHAR"),
+            ],
+        )
+        .await
+        .unwrap();
+    let people = client.collections().await.unwrap()[0].table_id.clone();
+
+    let id = client
+        .create_record(
+            &people,
+            json!({ "email": "ada@example.com", "name": "Ada" }),
+        )
+        .await
+        .unwrap();
+
+    let err = client
+        .create_record(&people, json!({ "email": "ada@example.com" }))
+        .await
+        .unwrap_err();
+    let moosedb_client::Error::Api { status, body } = &err else {
+        panic!("Expected an API error");
+    };
+    assert_eq!((*status, body.code.as_str()), (409, "conflict"));
+    assert_eq!(body.details.as_ref().unwrap()["field"], "email");
+
+    let updated = client
+        .upsert_record(
+            &people,
+            "email",
+            json!({ "email": "ada@example.com", "name": "Ada Lovelace" }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        (updated.id.as_str(), updated.status),
+        (id.as_str(), RecordStatus::Updated)
+    );
+    let record: serde_json::Value = client.get_record(&people, &id).await.unwrap();
+    assert_eq!(record["name"], "Ada Lovelace");
+
+    let created = client
+        .upsert_record(&people, "email", json!({ "email": "grace@example.com" }))
+        .await
+        .unwrap();
+    assert_eq!(created.status, RecordStatus::Created);
+    assert_ne!(created.id, id);
+
+    let err = client
+        .upsert_record(&people, "name", json!({ "name": "Ada" }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("bad_request"));
+
+    // A whole number is still a DECIMAL, stored and matched like 10.0
+    client
+        .create_collection(
+            "prices",
+            vec![
+                CollectionFields {
+                    unique: true,
+                    ..CollectionFields::new("price", "DECIMAL")
+                },
+                CollectionFields::new("label", "VARCHAR"),
+            ],
+        )
+        .await
+        .unwrap();
+    let prices = client
+        .collections()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|c| c.table_name == "prices")
+        .unwrap()
+        .table_id;
+    let id = client
+        .create_record(&prices, json!({ "price": 10, "label": "ten" }))
+        .await
+        .unwrap();
+    let record: serde_json::Value = client.get_record(&prices, &id).await.unwrap();
+    assert_eq!(record["price"], 10.0);
+
+    let updated = client
+        .upsert_record(&prices, "price", json!({ "price": 10, "label": "TEN" }))
+        .await
+        .unwrap();
+    assert_eq!(
+        (updated.id.as_str(), updated.status),
+        (id.as_str(), RecordStatus::Updated)
+    );
+}
+
+#[actix_web::test]
+async fn failed_writes_leave_no_files() {
+    let client = logged_in("orphans").await;
+    client
+        .create_collection(
+            "badges",
+            vec![
+                CollectionFields {
+                    unique: true,
+                    ..CollectionFields::new("code", "VARCHAR")
+                },
+                CollectionFields::new("image", "FILE"),
+            ],
+        )
+        .await
+        .unwrap();
+    let badges = client.collections().await.unwrap()[0].table_id.clone();
+    let badge = |name: &str| {
+        json!({
+            "code": "gold",
+            "image": FileUpload::new(name, "text/plain", name.as_bytes()),
+        })
+    };
+
+    client
+        .create_record(&badges, badge("first.txt"))
+        .await
+        .unwrap();
+    assert_eq!(stored_files("orphans").len(), 1);
+
+    let err = client
+        .create_record(&badges, badge("second.txt"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("conflict"));
+    assert_eq!(stored_files("orphans").len(), 1);
+
+    client
+        .upsert_record(&badges, "code", badge("third.txt"))
+        .await
+        .unwrap();
+    let stored = stored_files("orphans");
+    assert_eq!(stored.len(), 1);
+    assert!(stored[0].starts_with("third"));
+
+    let create = |data: serde_json::Value| BatchOperation::Create {
+        collection_id: badges.clone(),
+        data: data.as_object().unwrap().clone(),
+        reference: None,
+    };
+    let err = client
+        .batch(vec![
+            create(json!({ "code": "silver", "image": FileUpload::new("fourth.txt", "text/plain", b"4") })),
+            create(json!({ "code": "silver" })),
+        ])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("conflict"));
+    assert_eq!(stored_files("orphans"), stored);
+}
+
+#[actix_web::test]
+async fn thumbnails_of_protected_files_need_a_token() {
+    let client = logged_in("thumbs").await;
+    client
+        .create_collection(
+            "scans",
+            vec![CollectionFields {
+                protected: true,
+                thumb_sizes: Some("100x100".to_string()),
+                ..CollectionFields::new("image", "FILE")
+            }],
+        )
+        .await
+        .unwrap();
+    let scans = client.collections().await.unwrap()[0].table_id.clone();
+
+    // A 1x1 transparent PNG
+    let png = FileUpload {
+        filename: "scan.png".to_string(),
+        mime_type: "image/png".to_string(),
+        data: "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==".to_string(),
+    };
+    let id = client
+        .create_record(&scans, json!({ "image": png }))
+        .await
+        .unwrap();
+    let record: serde_json::Value = client.get_record(&scans, &id).await.unwrap();
+    let file: StoredFile = serde_json::from_value(record["image"][0].clone()).unwrap();
+    let (base_url, _) = file.url.split_once("/uploads/").unwrap();
+
+    let get = |url: String, token: Option<String>| {
+        actix_web::rt::task::spawn_blocking(move || {
+            let request = ureq::get(&url);
+            let request = match token {
+                Some(token) => request.query("token", &token),
+                None => request,
+            };
+            match request.call() {
+                Ok(response) => response.status(),
+                Err(ureq::Error::Status(status, _)) => status,
+                Err(err) => panic!("{}", err),
+            }
+        })
+    };
+    let original = format!("{}/uploads/{}", base_url, file.stored_name);
+    assert_eq!(get(original.clone(), None).await.unwrap(), 401);
+
+    let token_url = format!("{}/admin/api/file-token", base_url);
+    let bearer = format!("Bearer {}", client.token().unwrap());
+    let file_id = file.id.clone();
+    let token = actix_web::rt::task::spawn_blocking(move || {
+        let response: serde_json::Value = ureq::post(&token_url)
+            .set("Authorization", &bearer)
+            .send_json(json!({ "collection_id": scans, "file_id": file_id }))
+            .unwrap()
+            .into_json()
+            .unwrap();
+        response["token"].as_str().unwrap().to_string()
+    })
+    .await
+    .unwrap();
+
+    let thumb = format!("{}?thumb=100x100", original);
+    assert_eq!(get(thumb.clone(), None).await.unwrap(), 401);
+    assert_eq!(get(thumb, Some(token.clone())).await.unwrap(), 200);
+
+    // The generated thumbnail can't be fetched by its storage key, with or without a token
+    let thumb_key = format!("{}.thumb-100x100-fit.png", original);
+    assert_eq!(get(thumb_key.clone(), None).await.unwrap(), 404);
+    assert_eq!(get(thumb_key, Some(token)).await.unwrap(), 404);
+}
+
+#[actix_web::test]
+async fn parallel_guesses_are_locked_out() {
+    let client = anonymous("lockout");
+    let guesses: Vec<_> = (0..20)
+        .map(|_| {
+            let client = client.clone();
+            actix_web::rt::spawn(async move { client.login(EMAIL, "guess").await })
+        })
+        .collect();
+
+    let mut checked = 0;
+    for guess in guesses {
+        match guess.await.unwrap() {
+            Err(err) if err.code() == Some("unauthorized") => checked += 1,
+            Err(err) => assert_eq!(err.code(), Some("too_many_attempts")),
+            Ok(_) => panic!("A wrong password logged in"),
+        }
+    }
+    // Only the default login_max_attempts of 5 get their password checked
+    assert_eq!(checked, 5);
+
+    let err = client.login(EMAIL, PASSWORD).await.unwrap_err();
+    assert_eq!(err.code(), Some("too_many_attempts"));
+}
+
+#[actix_web::test]
+async fn owners_cannot_disable_each_other_at_once() {
+    let base_url = serve("owners");
+    let first = Client::new(&base_url);
+    first.login(EMAIL, PASSWORD).await.unwrap();
+    let first_token = first.token().unwrap();
+
+    let second_email = "second@moosedb.com";
+    let body = json!({
+        "name": "Second",
+        "email": second_email,
+        "password": PASSWORD,
+        "confirm_password": PASSWORD,
+        "role": "owner",
+    });
+    let created = post_status(&base_url, "create-super-admin", &first_token, body).await;
+    assert_eq!(created, 200);
+    let second = Client::new(&base_url);
+    second.login(second_email, PASSWORD).await.unwrap();
+    let second_token = second.token().unwrap();
+
+    // Holding SQLite's write lock lets both requests get past their checks before either writes
+    let lock = rusqlite::Connection::open(data_dir("owners").join("database.sqlite")).unwrap();
+    lock.execute_batch("BEGIN IMMEDIATE").unwrap();
+
+    let disable = |token: String, email: &str| {
+        let (base_url, body) = (
+            base_url.clone(),
+            json!({ "email": email, "disabled": true }),
+        );
+        actix_web::rt::spawn(
+            async move { post_status(&base_url, "disable-admin", &token, body).await },
+        )
+    };
+    let (a, b) = (
+        disable(first_token, second_email),
+        disable(second_token, EMAIL),
+    );
+    actix_web::rt::time::sleep(std::time::Duration::from_millis(500)).await;
+    lock.execute_batch("ROLLBACK").unwrap();
+
+    let mut statuses = [a.await.unwrap(), b.await.unwrap()];
+    statuses.sort();
+    assert_eq!(statuses, [200, 400]);
+}
+
+#[actix_web::test]
+async fn forwarded_headers_need_a_trusted_proxy() {
+    let base_url = serve("forwarded");
+    let url = format!("{}/auth/login", base_url);
+    let status = actix_web::rt::task::spawn_blocking(move || {
+        match ureq::post(&url)
+            .set("X-Forwarded-For", "203.0.113.9")
+            .send_json(json!({ "email": EMAIL, "password": "guess" }))
+        {
+            Ok(response) => response.status(),
+            Err(ureq::Error::Status(status, _)) => status,
+            Err(err) => panic!("{}", err),
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(status, 401);
+
+    // Without `trusted_proxies` the attempt belongs to the address the request came from
+    let db = rusqlite::Connection::open(data_dir("forwarded").join("database.sqlite")).unwrap();
+    let ip: String = db
+        .query_row("SELECT ip FROM _login_attempts", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(ip, "127.0.0.1");
+}
+
+#[actix_web::test]
+async fn password_resets_are_rate_limited() {
+    let base_url = serve("resets");
+    let client = Client::new(&base_url);
+    client.login(EMAIL, PASSWORD).await.unwrap();
+    let token = client.token().unwrap();
+    // Nothing listens on port 1, the emails fail in the background
+    for (key, value) in [
+        ("smtp_host", "127.0.0.1"),
+        ("smtp_port", "1"),
+        ("app_url", "http://127.0.0.1"),
+        ("password_reset_max_requests_per_ip", "4"),
+    ] {
+        let body = json!({ "key": key, "value": value });
+        assert_eq!(
+            post_status(&base_url, "update-setting", &token, body).await,
+            200
+        );
+    }
+
+    let forgot = |email: &'static str| {
+        let url = format!("{}/auth/forgot-password", base_url);
+        actix_web::rt::task::spawn_blocking(move || {
+            match ureq::post(&url).send_json(json!({ "email": email })) {
+                Ok(response) => response.status(),
+                Err(ureq::Error::Status(status, _)) => status,
+                Err(err) => panic!("{}", err),
+            }
+        })
+    };
+    // The default password_reset_max_requests is 3, unknown emails count the same
+    for _ in 0..3 {
+        assert_eq!(forgot(EMAIL).await.unwrap(), 200);
+    }
+    assert_eq!(forgot(EMAIL).await.unwrap(), 429);
+    assert_eq!(forgot("nobody@example.com").await.unwrap(), 200);
+    assert_eq!(forgot("someone@example.com").await.unwrap(), 429);
+}
+
+#[actix_web::test]
+async fn config_overrides_outlast_settings_changes() {
+    let data_dir = data_dir("override");
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    let config_file = data_dir.join("moosedb.toml");
+    std::fs::write(&config_file, "access_token_ttl = 120\n").unwrap();
+    let db = MooseDb::builder()
+        .config_file(config_file)
+        .data_dir(data_dir)
+        .port(0)
+        .build()
+        .unwrap();
+    let (server, addrs) = db.serve().unwrap();
+    actix_web::rt::spawn(server);
+    let base_url = format!("http://{}", addrs[0]);
+
+    let expires_in = || async {
+        match Client::new(&base_url).login(EMAIL, PASSWORD).await.unwrap() {
+            LoginResult::Session(session) => session.expires_in,
+            LoginResult::TwoFactorRequired(_) => panic!("A fresh admin has no two-factor"),
+        }
+    };
+    assert_eq!(expires_in().await, 120);
+
+    let client = Client::new(&base_url);
+    client.login(EMAIL, PASSWORD).await.unwrap();
+    let token = client.token().unwrap();
+    let body = json!({ "key": "access_token_ttl", "value": "600" });
+    assert_eq!(
+        post_status(&base_url, "update-setting", &token, body).await,
+        200
+    );
+    assert_eq!(expires_in().await, 120);
+    assert_eq!(
+        post_status(&base_url, "settings/reload", &token, json!({})).await,
+        200
+    );
+    assert_eq!(expires_in().await, 120);
+
+    let url = format!("{}/admin/api/settings", base_url);
+    let bearer = format!("Bearer {}", token);
+    let settings: serde_json::Value = actix_web::rt::task::spawn_blocking(move || {
+        ureq::get(&url)
+            .set("Authorization", &bearer)
+            .call()
+            .unwrap()
+            .into_json()
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    let ttl = settings["settings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|setting| setting["key"] == "access_token_ttl")
+        .unwrap();
+    assert_eq!(
+        (&ttl["value"], &ttl["overridden"]),
+        (&json!("120"), &json!(true))
+    );
+}
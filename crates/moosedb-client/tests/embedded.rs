@@ -0,0 +1,137 @@
+use actix_web::{App, HttpResponse, HttpServer, web};
+use moosedb::{ApiError, MooseDb};
+use moosedb_client::{Client, CollectionFields, FileUpload, RecordStatus, StoredFile};
+use serde_json::{Map, json};
+
+fn open(name: &str) -> MooseDb {
+    let data_dir =
+        std::env::temp_dir().join(format!("moosedb-embedded-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    MooseDb::builder()
+        .data_dir(data_dir)
+        .port(0)
+        .build()
+        .unwrap()
+}
+
+fn data(value: serde_json::Value) -> Map<String, serde_json::Value> {
+    value.as_object().unwrap().clone()
+}
+
+#[actix_web::test]
+async fn mounted_under_a_prefix() {
+    let db = open("mounted");
+    let mounted = db.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/health", web::get().to(HttpResponse::Ok))
+            .service(mounted.scope("/db"))
+    })
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    let client = Client::new(&format!("http://{}/db", addr));
+    client.login("admin@moosedb.com", "moosedb").await.unwrap();
+    client
+        .create_collection(
+            "photos",
+            vec![
+                CollectionFields::new("caption", "VARCHAR"),
+                CollectionFields::new("image", "FILE"),
+            ],
+        )
+        .await
+        .unwrap();
+    let photos = client.collections().await.unwrap()[0].table_id.clone();
+
+    let id = client
+        .create_record(
+            &photos,
+            json!({ "image": FileUpload::new("moose.txt", "text/plain", b"moose") }),
+        )
+        .await
+        .unwrap();
+    let record: serde_json::Value = client.get_record(&photos, &id).await.unwrap();
+    let file: StoredFile = serde_json::from_value(record["image"][0].clone()).unwrap();
+    assert!(
+        file.url
+            .starts_with(&format!("http://{}/db/uploads/", addr))
+    );
+    assert_eq!(client.download(&file.url).await.unwrap(), b"moose");
+
+    // Records written by the host app directly show up over HTTP
+    db.create_record(&photos, data(json!({ "caption": "From the host" })))
+        .await
+        .unwrap();
+    let page = client.list_records::<serde_json::Value>(&photos, 1, None);
+    assert_eq!(page.await.unwrap().pagination.total_records, 2);
+
+    let health = client.download(&format!("http://{}/health", addr)).await;
+    assert!(health.is_ok());
+}
+
+#[actix_web::test]
+async fn record_store_validates_like_the_api() {
+    let db = open("store");
+    let (server, addrs) = db.serve().unwrap();
+    actix_web::rt::spawn(server);
+
+    // Collections are only managed over HTTP
+    let client = Client::new(&format!("http://{}", addrs[0]));
+    client.login("admin@moosedb.com", "moosedb").await.unwrap();
+    client
+        .create_collection(
+            "people",
+            vec![CollectionFields {
+                unique: true,
+                nullable: false,
+                ..CollectionFields::new("email", "VARCHAR")
+            }],
+        )
+        .await
+        .unwrap();
+    let people = db.collections()[0].table_id.clone();
+
+    let id = db
+        .create_record(&people, data(json!({ "email": "ada@example.com" })))
+        .await
+        .unwrap();
+    let record = db.get_record(&people, &id).await.unwrap();
+    assert_eq!(record["email"], "ada@example.com");
+
+    match db.create_record(&people, data(json!({ "email": 7 }))).await {
+        Err(ApiError::Validation(fields)) => assert!(fields.contains_key("email")),
+        other => panic!("Expected a validation error, got {:?}", other),
+    }
+    match db
+        .create_record(&people, data(json!({ "email": "ada@example.com" })))
+        .await
+    {
+        Err(ApiError::Duplicate(field)) => assert_eq!(field, "email"),
+        other => panic!("Expected a duplicate, got {:?}", other),
+    }
+    let upserted = db
+        .upsert_record(
+            &people,
+            "email",
+            data(json!({ "email": "ada@example.com" })),
+        )
+        .await
+        .unwrap();
+    assert_eq!(upserted, (id.clone(), RecordStatus::Updated));
+    match db.create_record("missing", Map::new()).await {
+        Err(ApiError::NotFound(_)) => {}
+        other => panic!("Expected not found, got {:?}", other),
+    }
+
+    assert_eq!(
+        db.delete_records(&people, vec![id.clone()]).await.unwrap(),
+        1
+    );
+    assert!(matches!(
+        db.get_record(&people, &id).await,
+        Err(ApiError::NotFound(_))
+    ));
+}
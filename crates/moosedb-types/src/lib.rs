@@ -0,0 +1,342 @@
+//! Request and response bodies of the MooseDB HTTP API, shared by the server and its clients.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Body of endpoints that only report whether they worked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    pub success: bool,
+    pub message: String,
+}
+
+/// Body of every failed request, `code` is stable and safe to match on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorBody {
+    pub success: bool,
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Returned by login instead of tokens when the admin has two-factor authentication on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChallengeResponse {
+    pub success: bool,
+    pub message: String,
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PaginationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub items: Option<u32>,
+}
+
+/// One page of `/api/records/{collection_id}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordsResponse<T = Value> {
+    pub success: bool,
+    pub message: String,
+    pub records: Vec<T>,
+    pub pagination: PaginationInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaginationInfo {
+    pub current_page: u32,
+    pub items_per_page: u32,
+    pub total_records: i64,
+    pub total_pages: u32,
+    pub records_shown: usize,
+    pub has_next_page: bool,
+    pub has_prev_page: bool,
+    pub next_page: Option<String>,
+    pub prev_page: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SingleRecordResponse<T = Value> {
+    pub success: bool,
+    pub message: String,
+    pub record: T,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionID {
+    pub collection_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionSummary {
+    pub table_id: String,
+    pub table_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionsResponse {
+    pub success: bool,
+    pub collections: Vec<CollectionSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub field_type: String,
+}
+
+/// All records of a collection together with its columns, as the admin UI lists them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionRecords<T = Value> {
+    pub success: bool,
+    pub message: String,
+    pub records: Vec<T>,
+    pub columns: Vec<ColumnInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteCollectionRecords {
+    pub collection_id: String,
+    pub record_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteResponse {
+    pub success: bool,
+    pub message: String,
+    pub deleted_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionRequest {
+    pub collection: String,
+    pub fields: Vec<CollectionFields>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionFields {
+    pub title: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    pub unique: bool,
+    pub nullable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    pub allowed_extensions: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumb_sizes: Option<String>,
+    #[serde(default)]
+    pub protected: bool,
+}
+
+impl CollectionFields {
+    /// A field without limits, `field_type` is one of the collection field types like VARCHAR.
+    pub fn new(title: &str, field_type: &str) -> Self {
+        CollectionFields {
+            title: title.to_string(),
+            field_type: field_type.to_string(),
+            unique: false,
+            nullable: true,
+            min: None,
+            max: None,
+            allowed_extensions: None,
+            max_file_size: None,
+            max_files: None,
+            thumb_sizes: None,
+            protected: false,
+        }
+    }
+}
+
+pub type UpdateCollectionFields = CollectionFields;
+
+/// Renames a collection and adds fields to it, existing fields are left alone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateCollectionRequest {
+    pub collection_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection_name: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<UpdateCollectionFields>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateRecordRequest {
+    pub collection_id: String,
+    pub data: Map<String, Value>,
+}
+
+/// Query of `create-record`. With `upsert_on` set to a unique field, the record with the same
+/// value in that field is updated instead of failing with a conflict.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreateRecordParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upsert_on: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateRecordResponse {
+    pub success: bool,
+    pub message: String,
+    pub id: String,
+    #[serde(default)]
+    pub status: RecordStatus,
+}
+
+/// What a record write did.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    #[default]
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// One step of `/api/batch`. Record ids and top level `data` values can be
+/// `{"$ref": "<ref>"}` or `{"$ref": <index>}` to use the id of an earlier step.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        collection_id: String,
+        data: Map<String, Value>,
+        #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+        reference: Option<String>,
+    },
+    /// Changes the fields in `data` and leaves the others as they are
+    Update {
+        collection_id: String,
+        id: Value,
+        data: Map<String, Value>,
+        #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+        reference: Option<String>,
+    },
+    Delete {
+        collection_id: String,
+        id: Value,
+    },
+    /// Updates the record whose unique field `on` matches `data`, or creates one
+    Upsert {
+        collection_id: String,
+        on: String,
+        data: Map<String, Value>,
+        #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+        reference: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchResult {
+    pub collection_id: String,
+    pub id: String,
+    pub status: RecordStatus,
+}
+
+/// Results in the order of the operations. A batch either runs completely or not at all.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchResponse {
+    pub success: bool,
+    pub message: String,
+    pub results: Vec<BatchResult>,
+}
+
+/// A file sent inside a record, `data` is base64.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileUpload {
+    pub filename: String,
+    pub mime_type: String,
+    pub data: String,
+}
+
+impl FileUpload {
+    pub fn new(filename: &str, mime_type: &str, bytes: &[u8]) -> Self {
+        FileUpload {
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            data: base64_encode(bytes),
+        }
+    }
+}
+
+/// A file as it is returned in the file fields of a record.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredFile {
+    pub id: String,
+    pub stored_name: String,
+    pub original_name: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub sha256: Option<String>,
+    pub uploaded_at: Option<String>,
+    pub url: String,
+    pub protected: bool,
+}
+
+pub fn base64_encode(input: &[u8]) -> String {
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        output.push(alphabet[(b[0] >> 2) as usize] as char);
+        output.push(alphabet[(((b[0] & 0x03) << 4) | (b[1] >> 4)) as usize] as char);
+        if chunk.len() > 1 {
+            output.push(alphabet[(((b[1] & 0x0f) << 2) | (b[2] >> 6)) as usize] as char);
+        } else {
+            output.push('=');
+        }
+        if chunk.len() > 2 {
+            output.push(alphabet[(b[2] & 0x3f) as usize] as char);
+        } else {
+            output.push('=');
+        }
+    }
+
+    output
+}
@@ -0,0 +1,389 @@
+use crate::AppData;
+use crate::apis::admins::{Role, require_role};
+use crate::apis::audit::AuditContext;
+use crate::apis::error::ApiError;
+use crate::apis::login_attempts::{client_info, normalize_email};
+use crate::apis::sessions::{revoke_admin_sessions, unix_now};
+use crate::mailer::{Mailer, render_template};
+use crate::settings;
+use crate::utils::random::simple_uid;
+
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use bcrypt::{DEFAULT_COST, hash};
+use moosedb_types::Response;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Create a single-use token for `purpose`, only its hash is stored
+pub fn create_auth_token(
+    conn: &Connection,
+    email: &str,
+    purpose: &str,
+    ttl: i64,
+) -> rusqlite::Result<String> {
+    let token = simple_uid(48);
+
+    // Only the latest token of each kind stays valid
+    conn.execute(
+        "DELETE FROM _auth_tokens WHERE email = ?1 AND purpose = ?2 AND used_at IS NULL",
+        params![email, purpose],
+    )?;
+    conn.execute(
+        "INSERT INTO _auth_tokens (email, purpose, token_hash, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        params![email, purpose, hash_token(&token), unix_now() + ttl],
+    )?;
+
+    Ok(token)
+}
+
+// Mark a token as used and return the email it was issued for
+pub fn consume_auth_token(
+    conn: &Connection,
+    token: &str,
+    purpose: &str,
+) -> rusqlite::Result<Option<String>> {
+    let token_hash = hash_token(token);
+    let email: Option<String> = conn
+        .query_row(
+            "SELECT email FROM _auth_tokens
+             WHERE token_hash = ?1 AND purpose = ?2 AND used_at IS NULL AND expires_at > ?3",
+            params![token_hash, purpose, unix_now()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    if email.is_some() {
+        let used = conn.execute(
+            "UPDATE _auth_tokens SET used_at = CURRENT_TIMESTAMP WHERE token_hash = ?1 AND used_at IS NULL",
+            [&token_hash],
+        )?;
+        if used != 1 {
+            return Ok(None);
+        }
+    }
+
+    Ok(email)
+}
+
+// Links in emails always start with the app_url setting. The request's Host header is chosen by
+// the client, so it must never end up in a link that carries a token.
+fn app_url(configs: &HashMap<String, String>) -> Result<String, String> {
+    configs
+        .get("app_url")
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .ok_or_else(|| "Links can't be emailed until the app_url setting is set".to_string())
+}
+
+fn token_ttl(data: &AppData, key: &str) -> i64 {
+    settings::int(&data.configs.read().unwrap(), key)
+}
+
+// Email a one-time link to `email`. `template` is `reset` or `verify`, `path` is the dashboard page
+// that receives the token.
+pub async fn send_token_email(
+    data: &AppData,
+    email: &str,
+    name: &str,
+    template: &str,
+    path: &str,
+) -> Result<(), String> {
+    let configs = data.configs.read().unwrap().clone();
+    let mailer = Mailer::from_configs(&configs)?;
+    let app_url = app_url(&configs)?;
+
+    let (purpose, ttl_key) = match template {
+        "reset" => ("password_reset", "password_reset_ttl"),
+        _ => ("email_verification", "email_verification_ttl"),
+    };
+    let ttl = token_ttl(data, ttl_key);
+
+    let owner = email.to_string();
+    let token = data
+        .database
+        .write(move |conn| Ok(create_auth_token(conn, &owner, purpose, ttl)?))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let url = format!("{}{}?token={}", app_url, path, token);
+    let app_name = configs.get("appname").cloned().unwrap_or_default();
+    let expires_in = (ttl / 60).to_string();
+    let message = render_template(
+        &configs,
+        template,
+        email,
+        &[
+            ("app_name", &app_name),
+            ("name", name),
+            ("email", email),
+            ("url", &url),
+            ("expires_in", &expires_in),
+        ],
+    );
+
+    web::block(move || mailer.send(&message))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String,
+}
+
+/// Limits read from the `password_reset_*` settings.
+struct ResetPolicy {
+    max_requests: i64,
+    max_requests_per_ip: i64,
+    window: i64,
+}
+
+impl ResetPolicy {
+    fn from_configs(configs: &HashMap<String, String>) -> Self {
+        ResetPolicy {
+            max_requests: settings::int(configs, "password_reset_max_requests"),
+            max_requests_per_ip: settings::int(configs, "password_reset_max_requests_per_ip"),
+            window: settings::int(configs, "password_reset_window"),
+        }
+    }
+}
+
+// Records a reset request unless the email or IP already made as many as allowed within the
+// window. Returns 0 when it was recorded, otherwise the seconds until the oldest one leaves it.
+fn throttle_reset(
+    conn: &Connection,
+    policy: &ResetPolicy,
+    email: &str,
+    ip: &str,
+) -> rusqlite::Result<i64> {
+    let now = unix_now();
+    conn.execute(
+        "DELETE FROM _password_reset_requests WHERE requested_at <= ?1",
+        [now - policy.window],
+    )?;
+
+    let wait = |column: &str, value: &str, max_requests: i64| {
+        conn.query_row(
+            &format!(
+                "SELECT COUNT(*), MIN(requested_at) FROM _password_reset_requests WHERE {column} = ?1"
+            ),
+            [value],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+        )
+        .map(|(count, oldest)| match oldest {
+            Some(oldest) if count >= max_requests => (oldest + policy.window - now).max(1),
+            _ => 0,
+        })
+    };
+    let remaining =
+        wait("email", email, policy.max_requests)?.max(wait("ip", ip, policy.max_requests_per_ip)?);
+
+    if remaining == 0 {
+        conn.execute(
+            "INSERT INTO _password_reset_requests (email, ip, requested_at) VALUES (?1, ?2, ?3)",
+            params![email, ip, now],
+        )?;
+    }
+    Ok(remaining)
+}
+
+// Always answers the same way and at once, the email is sent in the background, so it can't be
+// used to find out which emails are admins. Requests are limited per email and IP whether or not
+// the email belongs to an admin.
+pub async fn forgot_password(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<ForgotPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let policy = {
+        let configs = data.configs.read().unwrap();
+        if let Err(err) = Mailer::from_configs(&configs).and_then(|_| app_url(&configs)) {
+            return Err(ApiError::NotConfigured(err));
+        }
+        ResetPolicy::from_configs(&configs)
+    };
+
+    let (_, ip) = client_info(&req);
+    let requester = normalize_email(&request.email);
+    let remaining = data
+        .database
+        .write(move |conn| Ok(throttle_reset(conn, &policy, &requester, &ip)?))
+        .await?;
+    if remaining > 0 {
+        return Err(ApiError::Locked(remaining));
+    }
+
+    let lookup = request.email.trim().to_string();
+    let admin: Option<(String, String)> = data
+        .database
+        .run(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT name, email FROM _super_admins WHERE email = ?1",
+                    [&lookup],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?)
+        })
+        .await
+        .ok()
+        .flatten();
+
+    if let Some((name, email)) = admin {
+        actix_web::rt::spawn(async move {
+            let sent =
+                send_token_email(&data, &email, &name, "reset", "/_/auth/reset-password").await;
+            if let Err(err) = sent {
+                log::warn!("Password reset email to {} failed: {}", email, err);
+            }
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(Response {
+        success: true,
+        message: "If the email belongs to an admin, a reset link has been sent to it".to_string(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    password: String,
+    confirm_password: String,
+}
+
+pub async fn reset_password(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<ResetPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if request.password.trim().is_empty() {
+        return Err(ApiError::BadRequest("Password cannot be empty".to_string()));
+    }
+
+    if request.password != request.confirm_password {
+        return Err(ApiError::BadRequest("Passwords do not match".to_string()));
+    }
+
+    // Hashed before taking a turn as the writer, it is the slow part
+    let password = request.password.clone();
+    let Ok(Ok(hashed)) = web::block(move || hash(password, DEFAULT_COST)).await else {
+        return Err(ApiError::Internal("Failed to hash password".to_string()));
+    };
+
+    let auditor = AuditContext::from_request(&req);
+    let token = request.token.clone();
+    let result = data
+        .database
+        .write(move |conn| {
+            let Some(email) = consume_auth_token(conn, &token, "password_reset")? else {
+                return Ok(None);
+            };
+
+            conn.execute(
+                "UPDATE _super_admins SET password = ?1, updated_at = CURRENT_TIMESTAMP WHERE email = ?2",
+                params![hashed, email],
+            )?;
+            revoke_admin_sessions(conn, &email, None)?;
+            auditor.acting_as(&email).write(
+                conn,
+                "admin.password_reset",
+                &email,
+                None,
+                None,
+            );
+
+            Ok(Some(email))
+        })
+        .await;
+
+    match result {
+        Ok(Some(_)) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: "Password has been reset, you can now log in".to_string(),
+        })),
+        Ok(None) => Err(ApiError::BadRequest(
+            "Reset link is invalid or has expired".to_string(),
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    token: String,
+}
+
+pub async fn verify_email(
+    data: web::Data<AppData>,
+    request: web::Json<VerifyEmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let verified = data
+        .database
+        .write(move |conn| {
+            let email = consume_auth_token(conn, &request.token, "email_verification")?;
+            if let Some(email) = &email {
+                let _ = conn.execute(
+                    "UPDATE _super_admins SET email_verified_at = CURRENT_TIMESTAMP WHERE email = ?1",
+                    [email],
+                );
+            }
+            Ok(email)
+        })
+        .await;
+
+    match verified {
+        Ok(Some(email)) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!("{} has been verified", email),
+        })),
+        Ok(None) => Err(ApiError::BadRequest(
+            "Verification link is invalid or has expired".to_string(),
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct TestEmailRequest {
+    to: String,
+}
+
+// Send a test email to check the SMTP settings
+#[post("/send-test-email")]
+pub async fn send_test_email(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<TestEmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let configs = data.configs.read().unwrap().clone();
+    let mailer = match Mailer::from_configs(&configs) {
+        Ok(mailer) => mailer,
+        Err(err) => return Err(ApiError::NotConfigured(err)),
+    };
+
+    let app_name = configs.get("appname").cloned().unwrap_or_default();
+    let email = crate::mailer::Email {
+        to: request.to.trim().to_string(),
+        subject: format!("{} test email", app_name),
+        text: format!("Your {} SMTP settings are working.", app_name),
+        html: format!("<p>Your {} SMTP settings are working.</p>", app_name),
+    };
+
+    match web::block(move || mailer.send(&email)).await {
+        Ok(Ok(())) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: "Test email has been sent".to_string(),
+        })),
+        Ok(Err(err)) => Err(ApiError::Upstream(err)),
+        Err(err) => Err(ApiError::Internal(err.to_string())),
+    }
+}
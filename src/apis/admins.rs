@@ -0,0 +1,554 @@
+use crate::AppData;
+use crate::apis::audit::AuditContext;
+use crate::apis::sessions::revoke_admin_sessions;
+
+use crate::apis::error::ApiError;
+
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, post, web};
+use moosedb_types::Response;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// What an admin account may do, from least to most privileged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Read records of the collections it has access to
+    Viewer,
+    /// Viewer, plus create and delete records
+    Editor,
+    /// Everything except managing owners and other admins
+    Admin,
+    /// Everything
+    Owner,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role.trim().to_lowercase().as_str() {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    // Owners manage everyone, admins only manage editors and viewers
+    fn can_manage(&self, other: Role) -> bool {
+        *self == Role::Owner || (*self == Role::Admin && other < Role::Admin)
+    }
+}
+
+/// The admin making a request, loaded by `validator` for every `/admin/api` call.
+#[derive(Clone, Debug)]
+pub struct AdminAccount {
+    pub email: String,
+    pub role: Role,
+    // Collection id to "read" or "write". `None` gives editors and viewers every collection.
+    permissions: Option<HashMap<String, String>>,
+}
+
+impl AdminAccount {
+    pub fn can_read(&self, collection_id: &str) -> bool {
+        self.role >= Role::Admin
+            || self
+                .permissions
+                .as_ref()
+                .is_none_or(|p| p.contains_key(collection_id))
+    }
+
+    pub fn can_write(&self, collection_id: &str) -> bool {
+        match self.role {
+            Role::Owner | Role::Admin => true,
+            Role::Editor => self
+                .permissions
+                .as_ref()
+                .is_none_or(|p| p.get(collection_id).is_some_and(|level| level == "write")),
+            Role::Viewer => false,
+        }
+    }
+}
+
+fn parse_permissions(permissions: Option<String>) -> Option<HashMap<String, String>> {
+    permissions.and_then(|p| serde_json::from_str(&p).ok())
+}
+
+// Active (not disabled) admin account for `email`
+pub fn load_admin(conn: &Connection, email: &str) -> rusqlite::Result<Option<AdminAccount>> {
+    let row: Option<(String, String, Option<String>)> = conn
+        .query_row(
+            "SELECT email, role, permissions FROM _super_admins WHERE email = ?1 AND disabled = 0",
+            [email],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    Ok(row.map(|(email, role, permissions)| AdminAccount {
+        email,
+        role: Role::parse(&role).unwrap_or(Role::Viewer),
+        permissions: parse_permissions(permissions),
+    }))
+}
+
+fn forbidden(message: &str) -> ApiError {
+    ApiError::Forbidden(message.to_string())
+}
+
+pub fn current_admin(req: &HttpRequest) -> Result<AdminAccount, ApiError> {
+    req.extensions()
+        .get::<AdminAccount>()
+        .cloned()
+        .ok_or_else(|| ApiError::Unauthorized("Unauthorized".to_string()))
+}
+
+// The calling admin, as long as their role is at least `role`
+pub fn require_role(req: &HttpRequest, role: Role) -> Result<AdminAccount, ApiError> {
+    let admin = current_admin(req)?;
+    if admin.role >= role {
+        Ok(admin)
+    } else {
+        Err(forbidden("You do not have permission to do this"))
+    }
+}
+
+pub fn require_read(req: &HttpRequest, collection_id: &str) -> Result<AdminAccount, ApiError> {
+    let admin = current_admin(req)?;
+    if admin.can_read(collection_id) {
+        Ok(admin)
+    } else {
+        Err(forbidden("You do not have access to this collection"))
+    }
+}
+
+pub fn require_write(req: &HttpRequest, collection_id: &str) -> Result<AdminAccount, ApiError> {
+    let admin = current_admin(req)?;
+    if admin.can_write(collection_id) {
+        Ok(admin)
+    } else {
+        Err(forbidden(
+            "You do not have permission to change this collection",
+        ))
+    }
+}
+
+// Checks that `permissions` only holds "read" or "write" levels and serializes it for storage
+pub fn permissions_to_json(
+    permissions: &Option<HashMap<String, String>>,
+) -> Result<Option<String>, String> {
+    let Some(permissions) = permissions else {
+        return Ok(None);
+    };
+    if let Some((collection, level)) = permissions
+        .iter()
+        .find(|(_, level)| level.as_str() != "read" && level.as_str() != "write")
+    {
+        return Err(format!(
+            "Invalid permission '{}' for collection '{}'. Expected read or write",
+            level, collection
+        ));
+    }
+    Ok(serde_json::to_string(permissions).ok())
+}
+
+// Whether the creator may hand out `role` to a new account
+pub fn check_assignable(admin: &AdminAccount, role: Role) -> Result<(), ApiError> {
+    if admin.role.can_manage(role) {
+        Ok(())
+    } else {
+        Err(forbidden(&format!(
+            "You do not have permission to manage {} accounts",
+            role.as_str()
+        )))
+    }
+}
+
+fn active_owner_count(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM _super_admins WHERE role = 'owner' AND disabled = 0",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// Whether demoting, disabling or deleting `email` would leave no active owner. Checked inside the
+// write that makes the change, so two requests can't each see the other owner and both go ahead.
+fn is_last_owner(conn: &Connection, email: &str) -> rusqlite::Result<bool> {
+    match target_account(conn, email)? {
+        Some((Role::Owner, false)) => Ok(active_owner_count(conn)? <= 1),
+        _ => Ok(false),
+    }
+}
+
+// Result of an account change that is refused when it would leave no active owner
+enum AccountChange {
+    LastOwner,
+    Done,
+}
+
+impl AccountChange {
+    fn into_response(self, message: &str) -> Result<HttpResponse, ApiError> {
+        match self {
+            AccountChange::Done => Ok(HttpResponse::Ok().json(Response {
+                success: true,
+                message: message.to_string(),
+            })),
+            AccountChange::LastOwner => Err(ApiError::BadRequest(
+                "There must be at least one owner".to_string(),
+            )),
+        }
+    }
+}
+
+// Role and disabled flag of the account being managed
+fn target_account(conn: &Connection, email: &str) -> rusqlite::Result<Option<(Role, bool)>> {
+    conn.query_row(
+        "SELECT role, disabled FROM _super_admins WHERE email = ?1",
+        [email],
+        |row| {
+            let role: String = row.get(0)?;
+            Ok((Role::parse(&role).unwrap_or(Role::Viewer), row.get(1)?))
+        },
+    )
+    .optional()
+}
+
+// Fields of an account that are recorded in the audit log
+fn account_snapshot(conn: &Connection, email: &str) -> Option<serde_json::Value> {
+    conn.query_row(
+        "SELECT name, email, role, permissions, disabled FROM _super_admins WHERE email = ?1",
+        [email],
+        |row| {
+            Ok(serde_json::json!({
+                "name": row.get::<_, String>(0)?,
+                "email": row.get::<_, String>(1)?,
+                "role": row.get::<_, String>(2)?,
+                "permissions": parse_permissions(row.get(3)?),
+                "disabled": row.get::<_, bool>(4)?,
+            }))
+        },
+    )
+    .ok()
+}
+
+#[derive(Serialize)]
+struct MeResponse {
+    success: bool,
+    message: String,
+    name: String,
+    email: String,
+    role: String,
+    permissions: Option<HashMap<String, String>>,
+}
+
+// Account of the logged in admin, used by the dashboard to hide what it can't do
+#[get("/me")]
+pub async fn get_me(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let admin = current_admin(&req)?;
+    let email = admin.email.clone();
+    let name: String = data
+        .database
+        .run(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT name FROM _super_admins WHERE email = ?1",
+                    [&email],
+                    |row| row.get(0),
+                )
+                .unwrap_or_default())
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(MeResponse {
+        success: true,
+        message: "Account found".to_string(),
+        name,
+        email: admin.email,
+        role: admin.role.as_str().to_string(),
+        permissions: admin.permissions,
+    }))
+}
+
+// Wraps a field that was sent in `Some`, even when its value is `null`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct UpdateAdminRequest {
+    email: String,
+    name: Option<String>,
+    new_email: Option<String>,
+    role: Option<String>,
+    // `null` gives every collection, so it is told apart from a missing field
+    #[serde(default, deserialize_with = "present")]
+    permissions: Option<Option<HashMap<String, String>>>,
+}
+
+#[post("/update-admin")]
+pub async fn update_admin(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<UpdateAdminRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_role(&req, Role::Admin)?;
+    let new_email = request
+        .new_email
+        .as_ref()
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty() && *e != request.email);
+
+    let (email, wanted) = (request.email.clone(), new_email.clone());
+    let target = data
+        .database
+        .run(move |conn| {
+            let Some((role, _)) = target_account(conn, &email)? else {
+                return Ok(None);
+            };
+            let taken = match wanted {
+                Some(new_email) => conn.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
+                    [new_email],
+                    |row| row.get(0),
+                )?,
+                None => false,
+            };
+            Ok(Some((role, taken)))
+        })
+        .await?;
+
+    let Some((current_role, taken)) = target else {
+        return Err(ApiError::NotFound("Admin not found".to_string()));
+    };
+
+    let is_self = admin.email == request.email;
+    if !is_self {
+        check_assignable(&admin, current_role)?;
+    }
+
+    let new_role = match &request.role {
+        Some(role) => match Role::parse(role) {
+            Some(role) => Some(role),
+            None => {
+                return Err(ApiError::BadRequest(format!(
+                    "Invalid role '{}'. Expected owner, admin, editor or viewer",
+                    role
+                )));
+            }
+        },
+        None => None,
+    };
+
+    if let Some(role) = new_role
+        && role != current_role
+    {
+        if is_self {
+            return Err(ApiError::BadRequest(
+                "You cannot change your own role".to_string(),
+            ));
+        }
+        check_assignable(&admin, role)?;
+    }
+
+    let permissions = match &request.permissions {
+        Some(permissions) => match permissions_to_json(permissions) {
+            Ok(json) => Some(json),
+            Err(message) => {
+                return Err(ApiError::BadRequest(message));
+            }
+        },
+        None => None,
+    };
+
+    if let Some(new_email) = &new_email
+        && taken
+    {
+        return Err(ApiError::Conflict(format!(
+            "Super admin with email '{}' already exists",
+            new_email
+        )));
+    }
+
+    let auditor = AuditContext::from_request(&req);
+    let request = request.into_inner();
+    let result = data
+        .database
+        .write(move |conn| {
+            if new_role.is_some_and(|role| role != Role::Owner)
+                && is_last_owner(conn, &request.email)?
+            {
+                return Ok(AccountChange::LastOwner);
+            }
+
+            let before = account_snapshot(conn, &request.email);
+            conn.execute(
+                "UPDATE _super_admins SET
+                    name = COALESCE(?1, name),
+                    role = COALESCE(?2, role),
+                    permissions = CASE WHEN ?3 THEN ?4 ELSE permissions END,
+                    updated_at = CURRENT_TIMESTAMP
+                 WHERE email = ?5",
+                params![
+                    request
+                        .name
+                        .as_ref()
+                        .map(|n| n.trim())
+                        .filter(|n| !n.is_empty()),
+                    new_role.map(|r| r.as_str()),
+                    permissions.is_some(),
+                    permissions.flatten(),
+                    request.email
+                ],
+            )?;
+
+            // Sessions follow the account to its new email, the next refresh issues a matching token
+            if let Some(new_email) = &new_email {
+                conn.execute(
+                    "UPDATE _super_admins SET email = ?1, email_verified_at = NULL WHERE email = ?2",
+                    params![new_email, request.email],
+                )?;
+                conn.execute(
+                    "UPDATE _sessions SET email = ?1 WHERE email = ?2",
+                    params![new_email, request.email],
+                )?;
+            }
+
+            let after = account_snapshot(conn, new_email.as_deref().unwrap_or(&request.email));
+            auditor.write(conn, "admin.update", &request.email, before, after);
+            Ok(AccountChange::Done)
+        })
+        .await?;
+
+    result.into_response("Admin has been updated")
+}
+
+#[derive(Deserialize)]
+struct DisableAdminRequest {
+    email: String,
+    disabled: bool,
+}
+
+// Disabled admins can't log in and lose their sessions, their account is kept
+#[post("/disable-admin")]
+pub async fn disable_admin(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<DisableAdminRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_role(&req, Role::Admin)?;
+    if admin.email == request.email {
+        return Err(ApiError::BadRequest(
+            "You cannot disable your own account".to_string(),
+        ));
+    }
+
+    let email = request.email.clone();
+    let target = data
+        .database
+        .run(move |conn| Ok(target_account(conn, &email)?))
+        .await?;
+
+    let Some((role, _)) = target else {
+        return Err(ApiError::NotFound("Admin not found".to_string()));
+    };
+    check_assignable(&admin, role)?;
+
+    let auditor = AuditContext::from_request(&req);
+    let (email, disable) = (request.email.clone(), request.disabled);
+    let result = data
+        .database
+        .write(move |conn| {
+            if disable && is_last_owner(conn, &email)? {
+                return Ok(AccountChange::LastOwner);
+            }
+
+            conn.execute(
+                "UPDATE _super_admins SET disabled = ?1, updated_at = CURRENT_TIMESTAMP WHERE email = ?2",
+                params![disable, email],
+            )?;
+            if disable {
+                revoke_admin_sessions(conn, &email, None)?;
+            }
+            auditor.write(
+                conn,
+                if disable {
+                    "admin.disable"
+                } else {
+                    "admin.enable"
+                },
+                &email,
+                None,
+                None,
+            );
+            Ok(AccountChange::Done)
+        })
+        .await?;
+
+    result.into_response(if request.disabled {
+        "Admin has been disabled"
+    } else {
+        "Admin has been enabled"
+    })
+}
+
+#[derive(Deserialize)]
+struct DeleteAdminRequest {
+    email: String,
+}
+
+#[post("/delete-admin")]
+pub async fn delete_admin(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<DeleteAdminRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_role(&req, Role::Admin)?;
+    if admin.email == request.email {
+        return Err(ApiError::BadRequest(
+            "You cannot delete your own account".to_string(),
+        ));
+    }
+
+    let email = request.email.clone();
+    let target = data
+        .database
+        .run(move |conn| Ok(target_account(conn, &email)?))
+        .await?;
+
+    let Some((role, _)) = target else {
+        return Err(ApiError::NotFound("Admin not found".to_string()));
+    };
+    check_assignable(&admin, role)?;
+
+    let auditor = AuditContext::from_request(&req);
+    let email = request.email.clone();
+    let result = data
+        .database
+        .write(move |conn| {
+            if is_last_owner(conn, &email)? {
+                return Ok(AccountChange::LastOwner);
+            }
+
+            let before = account_snapshot(conn, &email);
+            revoke_admin_sessions(conn, &email, None)?;
+            conn.execute("DELETE FROM _super_admins WHERE email = ?1", [&email])?;
+            auditor.write(conn, "admin.delete", &email, before, None);
+            Ok(AccountChange::Done)
+        })
+        .await?;
+
+    result.into_response("Admin has been deleted")
+}
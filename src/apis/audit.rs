@@ -0,0 +1,359 @@
+use crate::AppData;
+use crate::Claims;
+use crate::apis::admins::{Role, require_role};
+use crate::apis::error::ApiError;
+use crate::apis::login_attempts::client_info;
+use crate::db::records::row_to_json;
+use crate::db::schema::Collection;
+use crate::settings;
+
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, web};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Values of sensitive settings are never written to the audit log
+pub fn mask_setting(key: &str, value: &str) -> Value {
+    if settings::find(key).is_none_or(|setting| setting.hidden()) {
+        Value::String("********".to_string())
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+// Keep only the keys that changed when both sides are objects, so updates read as a diff
+fn diff(before: Option<Value>, after: Option<Value>) -> (Option<Value>, Option<Value>) {
+    match (before, after) {
+        (Some(Value::Object(mut before)), Some(Value::Object(mut after))) => {
+            let unchanged: Vec<String> = before
+                .iter()
+                .filter(|(key, value)| after.get(*key) == Some(*value))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in unchanged {
+                before.remove(&key);
+                after.remove(&key);
+            }
+            (Some(Value::Object(before)), Some(Value::Object(after)))
+        }
+        other => other,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn write_audit(
+    conn: &Connection,
+    actor: &str,
+    ip: &str,
+    user_agent: &str,
+    action: &str,
+    target: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> rusqlite::Result<()> {
+    let (before, after) = diff(before, after);
+    conn.execute(
+        "INSERT INTO _audit_log (actor, action, target, before, after, ip, user_agent)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            actor,
+            action,
+            target,
+            before.map(|v| v.to_string()),
+            after.map(|v| v.to_string()),
+            ip,
+            user_agent
+        ],
+    )?;
+    Ok(())
+}
+
+/// Who made a request, taken out of the `HttpRequest` so an entry can be written from inside
+/// `Database::run`. The actor comes from the request's `Claims`. A failure to write the entry is
+/// logged but never fails the action itself.
+pub struct AuditContext {
+    actor: String,
+    ip: String,
+    user_agent: String,
+}
+
+impl AuditContext {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let actor = req
+            .extensions()
+            .get::<Claims>()
+            .map(|c| c.email.clone())
+            .unwrap_or_default();
+        let (user_agent, ip) = client_info(req);
+        AuditContext {
+            actor,
+            ip,
+            user_agent,
+        }
+    }
+
+    // For changes made through the library API, outside of any request
+    pub fn system() -> Self {
+        AuditContext {
+            actor: "system".to_string(),
+            ip: String::new(),
+            user_agent: String::new(),
+        }
+    }
+
+    // For requests without a logged in admin, once it is known who made them
+    pub fn acting_as(self, actor: &str) -> Self {
+        AuditContext {
+            actor: actor.to_string(),
+            ..self
+        }
+    }
+
+    pub fn write(
+        &self,
+        conn: &Connection,
+        action: &str,
+        target: &str,
+        before: Option<Value>,
+        after: Option<Value>,
+    ) {
+        if let Err(err) = write_audit(
+            conn,
+            &self.actor,
+            &self.ip,
+            &self.user_agent,
+            action,
+            target,
+            before,
+            after,
+        ) {
+            log::error!("Failed to write audit entry for {}: {}", action, err);
+        }
+    }
+}
+
+// Rows of `table` with the given ids, as they are before a change
+pub fn snapshot_records(conn: &Connection, table: &str, ids: &[String]) -> Option<Value> {
+    let placeholders = (1..=ids.len())
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT * FROM \"{}\" WHERE id IN ({})",
+            table, placeholders
+        ))
+        .ok()?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+
+    let rows: rusqlite::Result<Vec<Value>> = stmt
+        .query_map(rusqlite::params_from_iter(ids), |row| {
+            row_to_json(row, &columns)
+        })
+        .and_then(|rows| rows.collect());
+
+    rows.ok().map(Value::Array)
+}
+
+// Name and fields of a collection as stored in `_database_metadata`
+pub fn snapshot_collection(collection: &Collection) -> Value {
+    serde_json::json!({ "name": collection.name, "fields": collection.fields })
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    // Inclusive bounds on `created_at`, like `2024-01-31` or `2024-01-31 12:00:00`
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    id: i64,
+    actor: String,
+    action: String,
+    target: String,
+    before: Option<Value>,
+    after: Option<Value>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    created_at: String,
+}
+
+#[derive(Serialize)]
+struct AuditResponse {
+    success: bool,
+    message: String,
+    total: i64,
+    entries: Vec<AuditEntry>,
+}
+
+const AUDIT_FILTER: &str =
+    "(?1 IS NULL OR actor = ?1) AND (?2 IS NULL OR action = ?2 OR action LIKE ?2 || '.%')
+     AND (?3 IS NULL OR target = ?3) AND (?4 IS NULL OR created_at >= ?4)
+     AND (?5 IS NULL OR created_at <= ?5 OR date(created_at) = ?5)";
+
+fn query_audit(
+    conn: &Connection,
+    query: &AuditQuery,
+    limit: i64,
+) -> rusqlite::Result<(i64, Vec<AuditEntry>)> {
+    let filters = params![
+        query.actor,
+        query.action,
+        query.target,
+        query.from,
+        query.to
+    ];
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM _audit_log WHERE {}", AUDIT_FILTER),
+        filters,
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, actor, action, target, before, after, ip, user_agent, created_at
+         FROM _audit_log WHERE {} ORDER BY id DESC LIMIT ?6 OFFSET ?7",
+        AUDIT_FILTER
+    ))?;
+    let entries = stmt
+        .query_map(
+            params![
+                query.actor,
+                query.action,
+                query.target,
+                query.from,
+                query.to,
+                limit,
+                query.offset.unwrap_or(0).max(0)
+            ],
+            |row| {
+                let json = |v: Option<String>| v.and_then(|v| serde_json::from_str(&v).ok());
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    actor: row.get(1)?,
+                    action: row.get(2)?,
+                    target: row.get(3)?,
+                    before: json(row.get(4)?),
+                    after: json(row.get(5)?),
+                    ip: row.get(6)?,
+                    user_agent: row.get(7)?,
+                    created_at: row.get(8)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok((total, entries))
+}
+
+// Audit entries, newest first. `?action=collection` matches every `collection.*` action.
+#[get("/audit-log")]
+pub async fn get_audit_log(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let query = query.into_inner();
+    let result = data
+        .database
+        .run(move |conn| {
+            Ok(query_audit(
+                conn,
+                &query,
+                query.limit.unwrap_or(50).clamp(1, 500),
+            )?)
+        })
+        .await;
+
+    match result {
+        Ok((total, entries)) => Ok(HttpResponse::Ok().json(AuditResponse {
+            success: true,
+            message: format!("{} audit entries", total),
+            total,
+            entries,
+        })),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Download the audit log with the same filters, as `?format=csv` (default) or `?format=json`
+#[get("/audit-log/export")]
+pub async fn export_audit_log(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let query = query.into_inner();
+    let format = query.format.clone();
+    let result = data
+        .database
+        .run(move |conn| {
+            Ok(query_audit(
+                conn,
+                &query,
+                query.limit.unwrap_or(i64::MAX).max(1),
+            )?)
+        })
+        .await;
+
+    let (_, entries) = result?;
+
+    if format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok()
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"audit-log.json\"",
+            ))
+            .json(entries));
+    }
+
+    let mut csv = String::from("id,created_at,actor,action,target,before,after,ip,user_agent\n");
+    for entry in entries {
+        let json = |v: &Option<Value>| v.as_ref().map(|v| v.to_string()).unwrap_or_default();
+        let line = [
+            entry.id.to_string(),
+            entry.created_at,
+            entry.actor,
+            entry.action,
+            entry.target,
+            json(&entry.before),
+            json(&entry.after),
+            entry.ip.unwrap_or_default(),
+            entry.user_agent.unwrap_or_default(),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",");
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"audit-log.csv\"",
+        ))
+        .body(csv))
+}
@@ -1,98 +1,271 @@
 use crate::AppData;
 use crate::Claims;
+use crate::apis::admins::load_admin;
+use crate::apis::audit::AuditContext;
+use crate::apis::error::ApiError;
+use crate::apis::login_attempts::*;
+use crate::apis::sessions::*;
+use crate::apis::two_factor::two_factor_challenge;
+use crate::db::database::DbError;
+use crate::settings;
 use actix_web::dev::ServiceRequest;
-use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, Result, post, web};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result, post, web};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use bcrypt::{DEFAULT_COST, hash, verify};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use rusqlite::params;
-use serde::{Deserialize, Serialize};
+use moosedb_types::{LoginRequest, LoginResponse, RefreshRequest, Response};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Deserialize;
+use std::sync::OnceLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Deserialize)]
-pub struct LoginRequest {
-    email: String,
-    password: String,
+fn token_ttls(data: &AppData) -> (i64, i64) {
+    let configs = data.configs.read().unwrap();
+    (
+        settings::int(&configs, "access_token_ttl"),
+        settings::int(&configs, "refresh_token_ttl"),
+    )
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash("moosedb", DEFAULT_COST).unwrap_or_default())
+}
+
+// Create a session for an admin whose credentials have been checked and answer with its tokens
+pub async fn start_session(
+    req: &HttpRequest,
+    data: &AppData,
+    email: &str,
+) -> Result<HttpResponse, ApiError> {
+    let (access_ttl, refresh_ttl) = token_ttls(data);
+    let (user_agent, ip) = client_info(req);
+
+    let owner = email.to_string();
+    let session = data
+        .database
+        .write(move |conn| Ok(create_session(conn, &owner, &user_agent, &ip, refresh_ttl)?))
+        .await;
+    let (session_id, refresh_token) = session?;
+
+    match create_jwt(email, email, &session_id, access_ttl, &data.jwt_secret) {
+        Ok(token) => Ok(HttpResponse::Ok().json(LoginResponse {
+            token,
+            refresh_token,
+            expires_in: access_ttl,
+            success: true,
+            message: "Login successful".to_string(),
+        })),
+        Err(err) => Err(ApiError::Internal(format!(
+            "Failed to create token: {}",
+            err
+        ))),
+    }
 }
 
-#[derive(Serialize)]
-struct LoginResponse {
-    token: String,
-    success: bool,
-    message: String,
+// Result of the password check in `login`
+enum LoginOutcome {
+    Disabled,
+    TwoFactor(String),
+    Verified(String),
+    Invalid,
+}
+
+// Email, password hash, two-factor and disabled flags of the admin logging in
+type LoginAdmin = (String, String, bool, bool);
+
+// Checks the lockout and records the attempt as failed in the same write, so parallel guesses are
+// all counted before any of them is checked. Returns the attempt id, or the seconds to wait.
+fn begin_login(
+    conn: &Connection,
+    policy: &LoginPolicy,
+    email: &str,
+    ip: &str,
+    user_agent: &str,
+) -> Result<Result<(i64, Option<LoginAdmin>), i64>, DbError> {
+    let retry_after = lockout_remaining(conn, policy, email, ip)?;
+    if retry_after > 0 {
+        return Ok(Err(retry_after));
+    }
+    let attempt = record_login_attempt(conn, email, ip, user_agent, false)?;
+
+    let admin = conn
+        .query_row(
+            "SELECT email, password, totp_enabled, disabled from _super_admins WHERE email = ?1",
+            [email],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+    Ok(Ok((attempt, admin)))
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    success: bool,
-    message: String,
+// The slow bcrypt check, run outside the writer so it doesn't hold up other writes
+fn check_password(admin: Option<LoginAdmin>, password: &str) -> LoginOutcome {
+    // Unknown emails still pay for a bcrypt check so timing doesn't reveal which admins exist
+    let hashed_password = admin
+        .as_ref()
+        .map(|(_, hashed, _, _)| hashed.as_str())
+        .unwrap_or_else(|| dummy_hash());
+    let is_valid = verify(password, hashed_password).unwrap_or(false);
+
+    match admin {
+        Some((_, _, _, true)) if is_valid => LoginOutcome::Disabled,
+        Some((email, _, true, _)) if is_valid => LoginOutcome::TwoFactor(email),
+        Some((email, _, _, _)) if is_valid => LoginOutcome::Verified(email),
+        _ => LoginOutcome::Invalid,
+    }
 }
 
 pub async fn login(
+    req: HttpRequest,
     data: web::Data<AppData>,
     credentials: web::Json<LoginRequest>,
-) -> impl Responder {
-    let conn = match data.database.get() {
-        Ok(conn) => conn,
-        Err(_) => {
-            return HttpResponse::InternalServerError().json(ErrorResponse {
-                success: false,
-                message: "Database connection failed.".to_string(),
-            });
-        }
-    };
+) -> Result<HttpResponse, ApiError> {
+    let (user_agent, ip) = client_info(&req);
+    let policy = LoginPolicy::from_configs(&data.configs.read().unwrap());
+    let LoginRequest { email, password } = credentials.into_inner();
+    let begun = data
+        .database
+        .write(move |conn| begin_login(conn, &policy, &email, &ip, &user_agent))
+        .await?;
+    let (attempt, admin) = begun.map_err(ApiError::Locked)?;
 
-    let result: Result<(String, String), rusqlite::Error> = conn.query_row(
-        "SELECT email, password from _super_admins WHERE email = ?1",
-        [&credentials.email],
-        |row| Ok((row.get(0)?, row.get(1)?)),
-    );
+    let outcome = web::block(move || check_password(admin, &password))
+        .await
+        .map_err(|err| ApiError::Internal(err.to_string()))?;
 
-    match result {
-        Ok((email, hashed_password)) => {
-            let is_valid = verify(&credentials.password, &hashed_password).unwrap_or(false);
-            if is_valid {
-                match create_jwt(&email, &email, &data.jwt_secret) {
-                    Ok(token) => HttpResponse::Ok().json(LoginResponse {
-                        token,
-                        success: true,
-                        message: "Login successful".to_string(),
-                    }),
-                    Err(_) => HttpResponse::InternalServerError().json(ErrorResponse {
-                        success: false,
-                        message: "Failed to create token".to_string(),
-                    }),
+    // The attempt stays a failure unless the password was right. With two-factor it only counts
+    // once the code has been checked, a disabled account doesn't count at all.
+    let settle = match &outcome {
+        LoginOutcome::Verified(_) => Some(true),
+        LoginOutcome::TwoFactor(_) | LoginOutcome::Disabled => Some(false),
+        LoginOutcome::Invalid => None,
+    };
+    if let Some(succeeded) = settle {
+        data.database
+            .write(move |conn| {
+                match succeeded {
+                    true => mark_login_succeeded(conn, attempt)?,
+                    false => discard_login_attempt(conn, attempt)?,
                 }
-            } else {
-                HttpResponse::Unauthorized().json(ErrorResponse {
-                    success: false,
-                    message: "Email or Password does not match.".to_string(),
-                })
+                Ok(())
+            })
+            .await?;
+    }
+
+    match outcome {
+        LoginOutcome::Disabled => Err(ApiError::Forbidden(
+            "This account has been disabled.".to_string(),
+        )),
+        LoginOutcome::TwoFactor(email) => two_factor_challenge(&email, &data.jwt_secret),
+        LoginOutcome::Verified(email) => start_session(&req, &data, &email).await,
+        LoginOutcome::Invalid => Err(ApiError::Unauthorized(
+            "Invalid email or password.".to_string(),
+        )),
+    }
+}
+
+// Swap a refresh token for a new access token and a rotated refresh token
+pub async fn refresh(
+    data: web::Data<AppData>,
+    request: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (access_ttl, refresh_ttl) = token_ttls(&data);
+
+    let rotated = data
+        .database
+        .write(move |conn| {
+            Ok(rotate_refresh_token(
+                conn,
+                &request.refresh_token,
+                refresh_ttl,
+            )?)
+        })
+        .await;
+
+    match rotated {
+        Ok(Some((session_id, email, refresh_token))) => {
+            match create_jwt(&email, &email, &session_id, access_ttl, &data.jwt_secret) {
+                Ok(token) => Ok(HttpResponse::Ok().json(LoginResponse {
+                    token,
+                    refresh_token,
+                    expires_in: access_ttl,
+                    success: true,
+                    message: "Token refreshed".to_string(),
+                })),
+                Err(err) => Err(ApiError::Internal(format!(
+                    "Failed to create token: {}",
+                    err
+                ))),
             }
         }
-        Err(_) => HttpResponse::Unauthorized().json(ErrorResponse {
-            success: false,
-            message: "Email not found!".to_string(),
-        }),
+        Ok(None) => Err(ApiError::Unauthorized(
+            "Refresh token is invalid or has expired.".to_string(),
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[post("/logout")]
+pub async fn logout(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let Some(session_id) = req.extensions().get::<Claims>().map(|c| c.jti.clone()) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let revoked = data
+        .database
+        .write(move |conn| Ok(revoke_session(conn, &session_id)?))
+        .await;
+
+    match revoked {
+        Ok(_) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: "Logged out".to_string(),
+        })),
+        Err(err) => Err(err.into()),
     }
 }
 
 pub async fn validator(
     req: ServiceRequest,
-    credentials: BearerAuth,
+    credentials: Option<BearerAuth>,
 ) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
-    let jwt_secret = req
-        .app_data::<web::Data<AppData>>()
-        .map(|data| data.jwt_secret.clone())
-        .unwrap_or_default();
+    let Some(credentials) = credentials else {
+        let error = ApiError::Unauthorized("A bearer token is required".to_string()).into();
+        return Err((error, req));
+    };
 
-    match verify_jwt(credentials.token(), &jwt_secret) {
-        Ok(claims) => {
+    let Some(data) = req.app_data::<web::Data<AppData>>().cloned() else {
+        let error = ApiError::Internal("App data is missing".to_string()).into();
+        return Err((error, req));
+    };
+
+    let claims = verify_jwt(credentials.token(), &data.jwt_secret).ok();
+    let admin = match &claims {
+        Some(claims) => {
+            let (session_id, email) = (claims.jti.clone(), claims.email.clone());
+            data.database
+                .run(move |conn| {
+                    if !session_is_active(conn, &session_id) {
+                        return Ok(None);
+                    }
+                    Ok(load_admin(conn, &email)?)
+                })
+                .await
+                .ok()
+                .flatten()
+        }
+        None => None,
+    };
+
+    match (claims, admin) {
+        (Some(claims), Some(admin)) => {
             req.extensions_mut().insert(claims);
+            req.extensions_mut().insert(admin);
             Ok(req)
         }
-        Err(_) => {
-            let error = actix_web::error::ErrorUnauthorized("Invalid token");
+        _ => {
+            let error = ApiError::Unauthorized("Invalid token".to_string()).into();
             Err((error, req))
         }
     }
@@ -101,6 +274,8 @@ pub async fn validator(
 fn create_jwt(
     email: &str,
     user_id: &str,
+    session_id: &str,
+    ttl: i64,
     secret: &str,
 ) -> Result<String, jsonwebtoken::errors::Error> {
     let now = SystemTime::now()
@@ -111,8 +286,9 @@ fn create_jwt(
     let claims = Claims {
         sub: user_id.to_string(),
         email: email.to_string(),
-        exp: now + 3600,
+        exp: now + ttl as usize,
         iat: now,
+        jti: session_id.to_string(),
     };
 
     encode(
@@ -143,70 +319,65 @@ pub async fn update_your_password(
     req: HttpRequest,
     body: web::Json<UpdatePasswordRequest>,
     data: web::Data<AppData>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     if body.new_password.trim().is_empty() || body.confirm_new_password.trim().is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "success": false,
-            "message": "Password fields cannot be empty"
-        }));
+        return Err(ApiError::BadRequest(
+            "Password fields cannot be empty".to_string(),
+        ));
     }
 
     if body.new_password != body.confirm_new_password {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "success": false,
-            "message": "Passwords do not match"
-        }));
+        return Err(ApiError::BadRequest("Passwords do not match".to_string()));
     }
 
-    let email = match req.extensions().get::<Claims>().map(|c| c.email.clone()) {
-        Some(e) => e,
+    let (email, session_id) = match req
+        .extensions()
+        .get::<Claims>()
+        .map(|c| (c.email.clone(), c.jti.clone()))
+    {
+        Some(claims) => claims,
         None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "success": false,
-                "message": "Unauthorized"
-            }));
+            return Err(ApiError::Unauthorized("Unauthorized".to_string()));
         }
     };
 
-    let pool = data.database.clone();
+    // Hashed before taking a turn as the writer, it is the slow part
     let new_password = body.new_password.clone();
+    let Ok(Ok(hashed)) = web::block(move || hash(new_password, DEFAULT_COST)).await else {
+        return Err(ApiError::Internal("Failed to hash password".to_string()));
+    };
 
-    let result = web::block(move || -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = pool.get()?;
-
-        let exists: bool = conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
-            params![email],
-            |row| row.get(0),
-        )?;
-
-        if !exists {
-            return Ok(false);
-        }
+    let auditor = AuditContext::from_request(&req);
+    let result = data
+        .database
+        .write(move |conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
+                params![email],
+                |row| row.get(0),
+            )?;
 
-        let hashed = hash(new_password, DEFAULT_COST)?;
+            if !exists {
+                return Ok(false);
+            }
 
-        conn.execute(
-            "UPDATE _super_admins SET password = ?1, updated_at = CURRENT_TIMESTAMP WHERE email = ?2",
-            params![hashed, email],
-        )?;
+            conn.execute(
+                "UPDATE _super_admins SET password = ?1, updated_at = CURRENT_TIMESTAMP WHERE email = ?2",
+                params![hashed, email],
+            )?;
+            revoke_admin_sessions(conn, &email, Some(&session_id))?;
+            auditor.write(conn, "admin.password", &email, None, None);
 
-        Ok(true)
-    })
-    .await;
+            Ok(true)
+        })
+        .await;
 
     match result {
-        Ok(Ok(true)) => HttpResponse::Ok().json(serde_json::json!({
+        Ok(true) => Ok(HttpResponse::Ok().json(serde_json::json!({
             "success": true,
             "message": "Password updated successfully"
-        })),
-        Ok(Ok(false)) => HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "message": "User not found"
-        })),
-        _ => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "message": "Failed to update password"
-        })),
+        }))),
+        Ok(false) => Err(ApiError::NotFound("User not found".to_string())),
+        Err(err) => Err(err.into()),
     }
 }
@@ -0,0 +1,304 @@
+use crate::AppData;
+use crate::apis::admins::require_write;
+use crate::apis::audit::AuditContext;
+use crate::apis::collections::delete_rows;
+use crate::apis::error::ApiError;
+use crate::apis::records::{
+    RecordOutcome, StagedRecord, insert_record, stage_uploads, update_record, upsert_record,
+};
+use crate::db::database::DbError;
+use crate::db::schema::Schema;
+use crate::settings;
+use crate::storage::Storage;
+use crate::storage::staged::Staged;
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use moosedb_types::{BatchOperation, BatchRequest, BatchResponse, BatchResult, RecordStatus};
+use rusqlite::Connection;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub async fn run_batch(
+    req: HttpRequest,
+    request: web::Json<BatchRequest>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    let operations = request.into_inner().operations;
+    for operation in &operations {
+        require_write(&req, collection_of(operation))?;
+    }
+
+    let auditor = AuditContext::from_request(&req);
+    let response = batch(&data, auditor, operations).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Runs every operation in one transaction, shared with the library API
+pub(crate) async fn batch(
+    data: &AppData,
+    auditor: AuditContext,
+    operations: Vec<BatchOperation>,
+) -> Result<BatchResponse, ApiError> {
+    let max_operations = settings::int(&data.configs.read().unwrap(), "batch_max_operations");
+    if operations.is_empty() {
+        return Err(ApiError::BadRequest(
+            "A batch needs at least one operation".to_string(),
+        ));
+    }
+    if operations.len() as i64 > max_operations {
+        return Err(ApiError::BadRequest(format!(
+            "A batch can have at most {} operations, got {}",
+            max_operations,
+            operations.len()
+        )));
+    }
+
+    let count = operations.len();
+    let staged = Arc::new(Staged::new(data.storage.clone()));
+    let (schema, storage) = (data.schema.clone(), staged.clone());
+    let staging = web::block(move || stage(&schema, storage.as_ref(), operations)).await;
+
+    let result = match staging {
+        Ok(Ok(operations)) => {
+            let (schema, storage) = (data.schema.clone(), staged.clone());
+            data.database
+                .write(move |conn| run(conn, &schema, storage.as_ref(), &auditor, operations))
+                .await
+                .map_err(ApiError::from)
+                .and_then(|result| result)
+        }
+        Ok(Err(err)) => Err(err),
+        Err(err) => Err(ApiError::Internal(err.to_string())),
+    };
+
+    match result {
+        Ok(results) => {
+            staged.commit();
+            Ok(BatchResponse {
+                success: true,
+                message: format!("{} operation(s) completed", count),
+                results,
+            })
+        }
+        Err(err) => {
+            staged.discard();
+            Err(err)
+        }
+    }
+}
+
+fn collection_of(operation: &BatchOperation) -> &str {
+    match operation {
+        BatchOperation::Create { collection_id, .. }
+        | BatchOperation::Update { collection_id, .. }
+        | BatchOperation::Delete { collection_id, .. }
+        | BatchOperation::Upsert { collection_id, .. } => collection_id,
+    }
+}
+
+// Writes the uploads of every operation to storage before the batch takes the writer
+fn stage(
+    schema: &Schema,
+    storage: &dyn Storage,
+    operations: Vec<BatchOperation>,
+) -> Result<Vec<(BatchOperation, StagedRecord)>, ApiError> {
+    let mut staged = Vec::with_capacity(operations.len());
+    for (index, mut operation) in operations.into_iter().enumerate() {
+        let record = match &mut operation {
+            BatchOperation::Create {
+                collection_id,
+                data,
+                ..
+            }
+            | BatchOperation::Update {
+                collection_id,
+                data,
+                ..
+            }
+            | BatchOperation::Upsert {
+                collection_id,
+                data,
+                ..
+            } => stage_uploads(schema, storage, collection_id, std::mem::take(data))
+                .map_err(|err| ApiError::Operation(index, Box::new(err)))?,
+            BatchOperation::Delete { .. } => StagedRecord::default(),
+        };
+        staged.push((operation, record));
+    }
+    Ok(staged)
+}
+
+// The savepoint undoes the operations before a failing one, the outer transaction then commits
+// nothing. The error names the operation.
+fn run(
+    conn: &Connection,
+    schema: &Schema,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    operations: Vec<(BatchOperation, StagedRecord)>,
+) -> Result<Result<Vec<BatchResult>, ApiError>, DbError> {
+    conn.execute_batch("SAVEPOINT batch")?;
+
+    let mut results: Vec<BatchResult> = Vec::with_capacity(operations.len());
+    let mut references: HashMap<String, String> = HashMap::new();
+
+    for (index, operation) in operations.into_iter().enumerate() {
+        match apply(
+            conn,
+            schema,
+            storage,
+            auditor,
+            operation,
+            &results,
+            &mut references,
+        ) {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                conn.execute_batch("ROLLBACK TO batch")?;
+                return Ok(Err(ApiError::Operation(index, Box::new(err))));
+            }
+        }
+    }
+
+    conn.execute_batch("RELEASE batch")?;
+    Ok(Ok(results))
+}
+
+fn apply(
+    conn: &Connection,
+    schema: &Schema,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    (operation, mut record): (BatchOperation, StagedRecord),
+    results: &[BatchResult],
+    references: &mut HashMap<String, String>,
+) -> Result<BatchResult, ApiError> {
+    let (collection_id, reference, outcome) = match operation {
+        BatchOperation::Create {
+            collection_id,
+            reference,
+            ..
+        } => {
+            resolve_data(&mut record.data, results, references)?;
+            let outcome = insert_record(conn, schema, auditor, &collection_id, record)?;
+            (collection_id, reference, outcome)
+        }
+        BatchOperation::Update {
+            collection_id,
+            id,
+            reference,
+            ..
+        } => {
+            let id = resolve_id(&id, results, references)?;
+            resolve_data(&mut record.data, results, references)?;
+            let outcome =
+                update_record(conn, schema, storage, auditor, &collection_id, &id, record)?;
+            (collection_id, reference, outcome)
+        }
+        BatchOperation::Delete { collection_id, id } => {
+            let id = resolve_id(&id, results, references)?;
+            let outcome = match schema.get(&collection_id) {
+                None => RecordOutcome::NotFound,
+                Some(collection) => {
+                    match delete_rows(
+                        conn,
+                        storage,
+                        auditor,
+                        &collection,
+                        std::slice::from_ref(&id),
+                    )? {
+                        0 => RecordOutcome::RecordNotFound(id),
+                        _ => RecordOutcome::Written(
+                            collection.name.clone(),
+                            id,
+                            RecordStatus::Deleted,
+                        ),
+                    }
+                }
+            };
+            (collection_id, None, outcome)
+        }
+        BatchOperation::Upsert {
+            collection_id,
+            on,
+            reference,
+            ..
+        } => {
+            resolve_data(&mut record.data, results, references)?;
+            let outcome =
+                upsert_record(conn, schema, storage, auditor, &collection_id, record, &on)?;
+            (collection_id, reference, outcome)
+        }
+    };
+
+    let (_, id, status) = outcome.into_result(&collection_id)?;
+    if let Some(name) = reference {
+        if references.contains_key(&name) {
+            return Err(ApiError::BadRequest(format!(
+                "Reference '{}' is used more than once",
+                name
+            )));
+        }
+        references.insert(name, id.clone());
+    }
+
+    Ok(BatchResult {
+        collection_id,
+        id,
+        status,
+    })
+}
+
+// `{"$ref": "<ref>"}` or `{"$ref": <index>}` of an earlier operation
+fn reference_of(value: &Value) -> Option<&Value> {
+    value.as_object().filter(|o| o.len() == 1)?.get("$ref")
+}
+
+fn lookup(
+    target: &Value,
+    results: &[BatchResult],
+    references: &HashMap<String, String>,
+) -> Result<String, ApiError> {
+    let id = match target {
+        Value::String(name) => references.get(name),
+        Value::Number(index) => index
+            .as_u64()
+            .and_then(|i| results.get(i as usize))
+            .map(|result| &result.id),
+        _ => None,
+    };
+    id.cloned().ok_or_else(|| {
+        ApiError::BadRequest(format!(
+            "Reference {} does not name an earlier operation",
+            target
+        ))
+    })
+}
+
+fn resolve_id(
+    id: &Value,
+    results: &[BatchResult],
+    references: &HashMap<String, String>,
+) -> Result<String, ApiError> {
+    match (id, reference_of(id)) {
+        (Value::String(id), _) => Ok(id.clone()),
+        (_, Some(target)) => lookup(target, results, references),
+        _ => Err(ApiError::BadRequest(
+            "A record id must be a string or a reference".to_string(),
+        )),
+    }
+}
+
+fn resolve_data(
+    data: &mut Map<String, Value>,
+    results: &[BatchResult],
+    references: &HashMap<String, String>,
+) -> Result<(), ApiError> {
+    for value in data.values_mut() {
+        if let Some(target) = reference_of(value) {
+            *value = Value::String(lookup(target, results, references)?);
+        }
+    }
+    Ok(())
+}
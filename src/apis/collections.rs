@@ -1,30 +1,32 @@
 use crate::AppData;
-use crate::Response;
+use crate::apis::account::send_token_email;
+use crate::apis::admins::*;
+use crate::apis::audit::{AuditContext, snapshot_collection, snapshot_records};
+use crate::apis::error::ApiError;
+use crate::apis::files::{
+    base_url, expand_file_fields, file_references, parse_thumb_sizes, remove_uploaded_file,
+};
 use crate::db::connection::create_super_admin;
+use crate::db::database::DbError;
+use crate::db::records;
+use crate::db::schema::{Collection, Schema};
+use crate::mailer::Mailer;
+use crate::storage::Storage;
+use crate::storage::staged::Staged;
 use crate::utils::random::*;
 
+use moosedb_types::{
+    CollectionID, CollectionRecords, CollectionRequest, CollectionSummary, CollectionsResponse,
+    ColumnInfo, DeleteCollectionRecords, DeleteResponse, Response, UpdateCollectionFields,
+    UpdateCollectionRequest,
+};
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use actix_web::{Error, HttpResponse, Responder, Result, get, post, web};
-
-#[derive(Deserialize)]
-struct CollectionID {
-    collection_id: String,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct ColumnInfo {
-    name: String,
-    field_type: String,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct CollectionRecords {
-    success: bool,
-    message: String,
-    records: Option<Vec<serde_json::Value>>,
-    columns: Option<Vec<ColumnInfo>>,
-}
+use actix_web::{HttpRequest, HttpResponse, get, post, web};
+use bcrypt::{DEFAULT_COST, hash};
 
 #[allow(dead_code)]
 #[derive(Deserialize, Serialize, Debug)]
@@ -40,280 +42,113 @@ struct GetCollectionRequest {
 struct CollectionData {
     success: bool,
     message: String,
-    records: Option<Vec<serde_json::Value>>,
+    records: Vec<serde_json::Value>,
 }
 
 #[post("/get-collection")]
 pub async fn get_collection(
     data: web::Data<AppData>,
     request: web::Json<GetCollectionRequest>,
-) -> Result<impl Responder> {
-    let conn = match data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionData {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-                records: None,
-            }));
-        }
-    };
-
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
-
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::NotFound().json(CollectionData {
-            success: false,
-            message: "Metadata table does not exist".to_string(),
-            records: None,
-        }));
+) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+    if request.collection_id.is_none() && request.collection_name.is_none() {
+        return Err(ApiError::BadRequest(
+            "Either collection_id or collection_name must be provided".to_string(),
+        ));
     }
 
-    let table_name: Result<String, _> = if let Some(collection_id) = &request.collection_id {
-        conn.query_row(
-            "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-            [collection_id],
-            |row| row.get(0),
-        )
-    } else if let Some(collection_name) = &request.collection_name {
-        conn.query_row(
-            "SELECT table_name FROM _database_metadata WHERE table_name = ?1 LIMIT 1",
-            [collection_name],
-            |row| row.get(0),
-        )
-    } else {
-        return Ok(HttpResponse::BadRequest().json(CollectionData {
-            success: false,
-            message: "Either collection_id or collection_name must be provided".to_string(),
-            records: None,
-        }));
-    };
-
-    let table_name = match table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(CollectionData {
-                success: false,
-                message: "Collection not found".to_string(),
-                records: None,
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionData {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-                records: None,
-            }));
-        }
-    };
-
-    let mut stmt = match conn.prepare(&format!(
-        "SELECT * FROM \"{}\" ORDER BY updated_at DESC",
-        table_name
-    )) {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionData {
-                success: false,
-                message: format!("Failed to prepare query: {}", err),
-                records: None,
-            }));
-        }
+    let collection = match (&request.collection_id, &request.collection_name) {
+        (Some(collection_id), _) => data.schema.get(collection_id),
+        (None, Some(collection_name)) => data.schema.by_name(collection_name),
+        (None, None) => None,
     };
-
-    let column_count = stmt.column_count();
-    let column_names: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
-
-    let rows = match stmt.query_map([], |row| {
-        let mut record = serde_json::Map::new();
-        for i in 0..column_count {
-            let value: serde_json::Value = match row.get_ref(i) {
-                Ok(rusqlite::types::ValueRef::Null) => serde_json::Value::Null,
-                Ok(rusqlite::types::ValueRef::Integer(v)) => serde_json::json!(v),
-                Ok(rusqlite::types::ValueRef::Real(v)) => serde_json::json!(v),
-                Ok(rusqlite::types::ValueRef::Text(v)) => {
-                    serde_json::Value::String(String::from_utf8_lossy(v).to_string())
-                }
-                _ => serde_json::Value::Null,
-            };
-            record.insert(column_names[i].clone(), value);
-        }
-        Ok(serde_json::Value::Object(record))
-    }) {
-        Ok(rows) => rows,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionData {
-                success: false,
-                message: format!("Failed to query records: {}", err),
-                records: None,
-            }));
-        }
+    let Some(collection) = collection else {
+        return Err(ApiError::NotFound("Collection not found".to_string()));
     };
 
-    let records: Vec<serde_json::Value> = rows.filter_map(|r| r.ok()).collect();
+    let table_name = collection.name.clone();
+    let records = data
+        .database
+        .run(move |conn| Ok(records::list_records(conn, &collection.name, None)?))
+        .await?;
 
     Ok(HttpResponse::Ok().json(CollectionData {
         success: true,
         message: format!("Retrieved {} records from '{}'", records.len(), table_name),
-        records: Some(records),
+        records,
     }))
 }
 
-#[derive(Deserialize)]
-struct DeleteCollectionRecords {
-    collection_id: String,
-    record_ids: Vec<String>,
-}
-
-#[derive(Serialize)]
-struct DeleteResponse {
-    success: bool,
-    message: String,
-    deleted_count: Option<usize>,
-}
-
 #[post("/delete-collection-records")]
 pub async fn delete_collection_records(
+    req: HttpRequest,
     data: web::Data<AppData>,
     request: web::Json<DeleteCollectionRecords>,
-) -> Result<impl Responder> {
-    let collection_id = request.collection_id.clone();
-    let record_ids = request.record_ids.clone();
+) -> Result<HttpResponse, ApiError> {
+    require_write(&req, &request.collection_id)?;
+    let auditor = AuditContext::from_request(&req);
+    let request = request.into_inner();
+    let response =
+        delete_records(&data, auditor, request.collection_id, request.record_ids).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
 
+// Removes records and their files, shared with the library API
+pub(crate) async fn delete_records(
+    data: &AppData,
+    auditor: AuditContext,
+    collection_id: String,
+    record_ids: Vec<String>,
+) -> Result<DeleteResponse, ApiError> {
     if record_ids.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(DeleteResponse {
-            success: false,
-            message: "No record IDs provided".to_string(),
-            deleted_count: None,
-        }));
+        return Err(ApiError::BadRequest("No record IDs provided".to_string()));
     }
 
-    let conn = match data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(DeleteResponse {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-                deleted_count: None,
-            }));
-        }
-    };
-
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
+    let schema = data.schema.clone();
+    let staged = Arc::new(Staged::new(data.storage.clone()));
+    let storage = staged.clone();
+    let id = collection_id.clone();
+    let result = data
+        .database
+        .write(move |conn| {
+            let Some(collection) = schema.get(&id) else {
+                return Ok(None);
+            };
+            let deleted_count =
+                delete_rows(conn, storage.as_ref(), &auditor, &collection, &record_ids)?;
+            Ok(Some((collection.name.clone(), deleted_count)))
+        })
+        .await;
 
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::NotFound().json(DeleteResponse {
-            success: false,
-            message: "Metadata table does not exist".to_string(),
-            deleted_count: None,
-        }));
+    // Files go once the rows are gone for good
+    if result.is_ok() {
+        staged.commit();
     }
 
-    let table_name: Result<String, _> = conn.query_row(
-        "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-        [&collection_id],
-        |row| row.get(0),
-    );
-
-    let table_name = match table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(DeleteResponse {
-                success: false,
-                message: format!("Collection with id '{}' not found", collection_id),
-                deleted_count: None,
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(DeleteResponse {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-                deleted_count: None,
-            }));
-        }
-    };
-
-    let mut file_field_stmt = match conn.prepare(
-        "SELECT field_name FROM _database_metadata WHERE table_name = ?1 AND field_type = 'FILE'",
-    ) {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(DeleteResponse {
-                success: false,
-                message: format!("Failed to query file fields: {}", err),
-                deleted_count: None,
-            }));
-        }
-    };
-
-    let file_fields: Vec<String> = file_field_stmt
-        .query_map([&table_name], |row| row.get(0))
-        .and_then(|rows| rows.collect())
-        .unwrap_or_default();
-
-    if !file_fields.is_empty() {
-        let id_placeholders = record_ids
-            .iter()
-            .enumerate()
-            .map(|(i, _)| format!("?{}", i + 1))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let cols = file_fields
-            .iter()
-            .map(|f| format!("\"{}\"", f))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let select_query = format!(
-            "SELECT {} FROM \"{}\" WHERE id IN ({})",
-            cols, table_name, id_placeholders
-        );
-
-        let params: Vec<Box<dyn rusqlite::ToSql>> = record_ids
-            .iter()
-            .map(|id| Box::new(id.clone()) as Box<dyn rusqlite::ToSql>)
-            .collect();
-        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-
-        if let Ok(mut stmt) = conn.prepare(&select_query) {
-            let col_count = file_fields.len();
-            let _ = stmt
-                .query_map(params_refs.as_slice(), |row| {
-                    let mut paths = Vec::new();
-                    for i in 0..col_count {
-                        if let Ok(Some(raw)) = row.get::<_, Option<String>>(i) {
-                            if let Ok(serde_json::Value::Array(arr)) = serde_json::from_str(&raw) {
-                                for entry in arr {
-                                    if let Some(p) = entry.as_str() {
-                                        paths.push(p.to_string());
-                                    }
-                                }
-                            } else {
-                                paths.push(raw);
-                            }
-                        }
-                    }
-                    Ok(paths)
-                })
-                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
-                .map(|all_paths| {
-                    for paths in all_paths {
-                        for path in paths {
-                            let _ = std::fs::remove_file(&path);
-                        }
-                    }
-                });
-        }
+    match result? {
+        Some((table_name, deleted_count)) => Ok(DeleteResponse {
+            success: true,
+            message: format!("Deleted {} record(s) from '{}'", deleted_count, table_name),
+            deleted_count,
+        }),
+        None => Err(ApiError::NotFound(format!(
+            "Collection with id '{}' not found",
+            collection_id
+        ))),
     }
+}
+
+// Deletes records together with their files, returning how many there were
+pub(crate) fn delete_rows(
+    conn: &Connection,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    collection: &Collection,
+    record_ids: &[String],
+) -> Result<usize, DbError> {
+    let table_name = &collection.name;
+    let before = snapshot_records(conn, table_name, record_ids);
+    remove_record_files(conn, storage, collection, record_ids);
 
     let placeholders = record_ids
         .iter()
@@ -321,133 +156,110 @@ pub async fn delete_collection_records(
         .map(|(i, _)| format!("?{}", i + 1))
         .collect::<Vec<_>>()
         .join(", ");
+    let deleted_count = conn.execute(
+        &format!(
+            "DELETE FROM \"{}\" WHERE id IN ({})",
+            table_name, placeholders
+        ),
+        rusqlite::params_from_iter(record_ids),
+    )?;
+
+    auditor.write(conn, "record.delete", &collection.id, before, None);
+    Ok(deleted_count)
+}
 
-    let query = format!(
-        "DELETE FROM \"{}\" WHERE id IN ({})",
-        table_name, placeholders
-    );
+// Removes the uploads referenced by the FILE fields of the given records
+fn remove_record_files(
+    conn: &Connection,
+    storage: &dyn Storage,
+    collection: &Collection,
+    record_ids: &[String],
+) {
+    let file_fields: Vec<&String> = collection.file_fields().map(|f| &f.name).collect();
+
+    if file_fields.is_empty() {
+        return;
+    }
 
-    let params: Vec<Box<dyn rusqlite::ToSql>> = record_ids
+    let id_placeholders = record_ids
         .iter()
-        .map(|id| Box::new(id.clone()) as Box<dyn rusqlite::ToSql>)
-        .collect();
+        .enumerate()
+        .map(|(i, _)| format!("?{}", i + 1))
+        .collect::<Vec<_>>()
+        .join(", ");
 
-    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    let cols = file_fields
+        .iter()
+        .map(|f| format!("\"{}\"", f))
+        .collect::<Vec<_>>()
+        .join(", ");
 
-    let deleted_count = match conn.execute(&query, params_refs.as_slice()) {
-        Ok(count) => count,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(DeleteResponse {
-                success: false,
-                message: format!("Failed to delete records: {}", err),
-                deleted_count: None,
-            }));
-        }
-    };
+    let select_query = format!(
+        "SELECT {} FROM \"{}\" WHERE id IN ({})",
+        cols, collection.name, id_placeholders
+    );
 
-    Ok(HttpResponse::Ok().json(DeleteResponse {
-        success: true,
-        message: format!("Deleted {} record(s) from '{}'", deleted_count, table_name),
-        deleted_count: Some(deleted_count),
-    }))
+    if let Ok(mut stmt) = conn.prepare(&select_query) {
+        let col_count = file_fields.len();
+        let _ = stmt
+            .query_map(rusqlite::params_from_iter(record_ids), |row| {
+                let mut paths = Vec::new();
+                for i in 0..col_count {
+                    if let Ok(Some(raw)) = row.get::<_, Option<String>>(i) {
+                        paths.extend(file_references(&raw));
+                    }
+                }
+                Ok(paths)
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map(|all_paths| {
+                for paths in all_paths {
+                    for path in paths {
+                        remove_uploaded_file(conn, storage, &path);
+                    }
+                }
+            });
+    }
 }
 
 #[post("/get-collection-records")]
 pub async fn get_collection_records(
+    req: HttpRequest,
     data: web::Data<AppData>,
     request: web::Json<CollectionID>,
-) -> Result<impl Responder> {
+) -> Result<HttpResponse, ApiError> {
+    require_read(&req, &request.collection_id)?;
     let collection_id = request.collection_id.clone();
 
-    let conn = match data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
-    };
-
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
-
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::NotFound().json(CollectionRecords {
-            success: false,
-            message: "Metadata table does not exist".to_string(),
-            records: None,
-            columns: None,
-        }));
-    }
-
-    let table_name: Result<String, _> = conn.query_row(
-        "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-        [&collection_id],
-        |row| row.get(0),
-    );
-
-    let table_name = match table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(CollectionRecords {
-                success: false,
-                message: format!("Collection with id '{}' not found", collection_id),
-                records: None,
-                columns: None,
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
-    };
-
-    let mut metadata_stmt = match conn.prepare(
-        "SELECT field_name, field_type FROM _database_metadata WHERE table_name = ?1 ORDER BY ROWID"
-    ) {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to prepare metadata query: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
+    let Some(collection) = data.schema.get(&collection_id) else {
+        return Err(ApiError::NotFound(format!(
+            "Collection with id '{}' not found",
+            collection_id
+        )));
     };
 
-    let columns_info: Vec<ColumnInfo> = match metadata_stmt.query_map([&table_name], |row| {
-        Ok(ColumnInfo {
-            name: row.get(0)?,
-            field_type: row.get(1)?,
+    let storage = data.storage.clone();
+    let base_url = base_url(&req);
+    let cached = collection.clone();
+    let result = data
+        .database
+        .run(move |conn| {
+            let mut records = records::list_records(conn, &cached.name, None)?;
+            expand_file_fields(conn, storage.as_ref(), &cached, &base_url, &mut records);
+            Ok(records)
         })
-    }) {
-        Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to query column metadata: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
-    };
+        .await;
+
+    let records = result?;
 
     let mut all_columns = vec![ColumnInfo {
         name: "id".to_string(),
         field_type: "INTEGER".to_string(),
     }];
-    all_columns.extend(columns_info);
+    all_columns.extend(collection.fields.iter().map(|field| ColumnInfo {
+        name: field.name.clone(),
+        field_type: field.field_type.clone(),
+    }));
     all_columns.push(ColumnInfo {
         name: "created_at".to_string(),
         field_type: "TIMESTAMP".to_string(),
@@ -457,290 +269,163 @@ pub async fn get_collection_records(
         field_type: "TIMESTAMP".to_string(),
     });
 
-    let mut stmt = match conn.prepare(&format!(
-        "SELECT * FROM \"{}\" ORDER BY updated_at DESC",
-        table_name
-    )) {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to prepare query: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
-    };
-
-    let column_count = stmt.column_count();
-    let column_names: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
-
-    let rows = match stmt.query_map([], |row| {
-        let mut record = serde_json::Map::new();
-        for i in 0..column_count {
-            let value: serde_json::Value = match row.get_ref(i) {
-                Ok(rusqlite::types::ValueRef::Null) => serde_json::Value::Null,
-                Ok(rusqlite::types::ValueRef::Integer(v)) => serde_json::json!(v),
-                Ok(rusqlite::types::ValueRef::Real(v)) => serde_json::json!(v),
-                Ok(rusqlite::types::ValueRef::Text(v)) => {
-                    serde_json::Value::String(String::from_utf8_lossy(v).to_string())
-                }
-                _ => serde_json::Value::Null,
-            };
-            record.insert(column_names[i].clone(), value);
-        }
-        Ok(serde_json::Value::Object(record))
-    }) {
-        Ok(rows) => rows,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(CollectionRecords {
-                success: false,
-                message: format!("Failed to query records: {}", err),
-                records: None,
-                columns: None,
-            }));
-        }
-    };
-
-    let records: Vec<serde_json::Value> = rows.filter_map(|r| r.ok()).collect();
-
     Ok(HttpResponse::Ok().json(CollectionRecords {
         success: true,
-        message: format!("Retrieved {} records from '{}'", records.len(), table_name),
-        records: Some(records),
-        columns: Some(all_columns),
+        message: format!(
+            "Retrieved {} records from '{}'",
+            records.len(),
+            collection.name
+        ),
+        records,
+        columns: all_columns,
     }))
 }
 
 #[post("/delete-collection")]
 pub async fn delete_collection(
+    req: HttpRequest,
     data: web::Data<AppData>,
     request: web::Json<CollectionID>,
-) -> Result<impl Responder> {
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
     let collection_id = request.collection_id.clone();
 
-    let conn = match data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-            }));
-        }
-    };
-
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
-
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::NotFound().json(Response {
-            success: false,
-            message: "Metadata table does not exist".to_string(),
-        }));
-    }
+    let auditor = AuditContext::from_request(&req);
+    let schema = data.schema.clone();
+    let staged = Arc::new(Staged::new(data.storage.clone()));
+    let storage = staged.clone();
+    let id = collection_id.clone();
+    let result = data
+        .database
+        .write(move |conn| {
+            let Some(collection) = schema.get(&id) else {
+                return Ok(None);
+            };
+            let table_name = collection.name.clone();
+
+            let file_fields: Vec<&String> = collection.file_fields().map(|f| &f.name).collect();
+
+            if !file_fields.is_empty() {
+                let cols = file_fields
+                    .iter()
+                    .map(|f| format!("\"{}\"", f))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                let select_query = format!("SELECT {} FROM \"{}\"", cols, table_name);
+
+                if let Ok(mut stmt) = conn.prepare(&select_query) {
+                    let col_count = file_fields.len();
+                    let _ = stmt
+                        .query_map([], |row| {
+                            let mut paths = Vec::new();
+                            for i in 0..col_count {
+                                if let Ok(Some(raw)) = row.get::<_, Option<String>>(i) {
+                                    paths.extend(file_references(&raw));
+                                }
+                            }
+                            Ok(paths)
+                        })
+                        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                        .map(|all_paths| {
+                            for paths in all_paths {
+                                for path in paths {
+                                    remove_uploaded_file(conn, storage.as_ref(), &path);
+                                }
+                            }
+                        });
+                }
+            }
 
-    let table_name: Result<String, _> = conn.query_row(
-        "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-        [&collection_id],
-        |row| row.get(0),
-    );
+            let mut before = snapshot_collection(&collection);
+            before["records"] = records::count_records(conn, &table_name)
+                .map(serde_json::Value::from)
+                .unwrap_or_default();
 
-    let table_name = match table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(Response {
-                success: false,
-                message: format!("Collection with id '{}' not found", collection_id),
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-            }));
-        }
-    };
+            conn.execute(&format!("DROP TABLE IF EXISTS \"{}\"", table_name), [])?;
+            conn.execute("DELETE FROM _database_metadata WHERE table_id = ?1", [&id])?;
+            schema.reload(conn)?;
 
-    let file_fields: Vec<String> = conn
-        .prepare(
-            "SELECT field_name FROM _database_metadata WHERE table_name = ?1 AND field_type = 'FILE'"
-        )
-        .and_then(|mut stmt| {
-            stmt.query_map([&table_name], |row| row.get(0))
-                .and_then(|rows| rows.collect())
+            auditor.write(conn, "collection.delete", &id, Some(before), None);
+            Ok(Some(table_name))
         })
-        .unwrap_or_default();
+        .await;
 
-    if !file_fields.is_empty() {
-        let cols = file_fields
-            .iter()
-            .map(|f| format!("\"{}\"", f))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let select_query = format!("SELECT {} FROM \"{}\"", cols, table_name);
-
-        if let Ok(mut stmt) = conn.prepare(&select_query) {
-            let col_count = file_fields.len();
-            let _ = stmt
-                .query_map([], |row| {
-                    let mut paths = Vec::new();
-                    for i in 0..col_count {
-                        if let Ok(Some(raw)) = row.get::<_, Option<String>>(i) {
-                            if let Ok(serde_json::Value::Array(arr)) = serde_json::from_str(&raw) {
-                                for entry in arr {
-                                    if let Some(p) = entry.as_str() {
-                                        paths.push(p.to_string());
-                                    }
-                                }
-                            } else {
-                                paths.push(raw);
-                            }
-                        }
-                    }
-                    Ok(paths)
-                })
-                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
-                .map(|all_paths| {
-                    for paths in all_paths {
-                        for path in paths {
-                            let _ = std::fs::remove_file(&path);
-                        }
-                    }
-                });
-        }
-    }
-
-    if let Err(err) = conn.execute(&format!("DROP TABLE IF EXISTS \"{}\"", table_name), []) {
-        return Ok(HttpResponse::InternalServerError().json(Response {
-            success: false,
-            message: format!("Failed to drop table: {}", err),
-        }));
+    if result.is_ok() {
+        staged.commit();
     }
 
-    if let Err(err) = conn.execute(
-        "DELETE FROM _database_metadata WHERE table_id = ?1",
-        [&collection_id],
-    ) {
-        return Ok(HttpResponse::InternalServerError().json(Response {
-            success: false,
-            message: format!("Failed to delete from metadata: {}", err),
-        }));
+    match result? {
+        Some(table_name) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!("Collection '{}' deleted successfully", table_name),
+        })),
+        None => Err(ApiError::NotFound(format!(
+            "Collection with id '{}' not found",
+            collection_id
+        ))),
     }
-
-    Ok(HttpResponse::Ok().json(Response {
-        success: true,
-        message: format!("Collection '{}' deleted successfully", table_name),
-    }))
 }
 
 #[get("/collections")]
-pub async fn get_collections(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
-    let conn = match app_data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-            }));
-        }
-    };
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "collections": []
-        })));
-    }
-    let mut stmt =
-        match conn.prepare("SELECT DISTINCT table_id, table_name FROM _database_metadata") {
-            Ok(stmt) => stmt,
-            Err(err) => {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to prepare query: {}", err),
-                }));
-            }
-        };
-    let collections: Result<Vec<serde_json::Value>, _> = stmt
-        .query_map([], |row| {
-            Ok(serde_json::json!({
-                "table_id": row.get::<_, String>(0)?,
-                "table_name": row.get::<_, String>(1)?
-            }))
+pub async fn get_collections(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = current_admin(&req)?;
+    let collections = app_data
+        .schema
+        .all()
+        .iter()
+        .filter(|c| admin.can_read(&c.id))
+        .map(|c| CollectionSummary {
+            table_id: c.id.clone(),
+            table_name: c.name.clone(),
         })
-        .and_then(|mapped_rows| mapped_rows.collect());
-    match collections {
-        Ok(collections) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "collections": collections
-        }))),
-        Err(err) => Ok(HttpResponse::InternalServerError().json(Response {
-            success: false,
-            message: format!("Failed to fetch collections: {}", err),
-        })),
-    }
+        .collect();
+    Ok(HttpResponse::Ok().json(CollectionsResponse {
+        success: true,
+        collections,
+    }))
 }
 
 #[get("/get-super-admins")]
-pub async fn get_super_admins(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
-    let conn = match app_data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "message": format!("Failed to get database connection: {}", err),
-                "records": None::<Vec<serde_json::Value>>,
-                "columns": None::<Vec<String>>,
-            })));
-        }
-    };
-
-    let mut stmt = match conn.prepare("SELECT DISTINCT name, email, created_at FROM _super_admins")
-    {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "message": format!("Failed to prepare query: {}", err),
-                "records": None::<Vec<serde_json::Value>>,
-                "columns": None::<Vec<String>>,
-            })));
-        }
-    };
-
-    let column_names: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
-
-    let super_admins: Result<Vec<serde_json::Value>, _> = stmt
-        .query_map([], |row| {
-            Ok(serde_json::json!({
-                "name": row.get::<_, String>(0)?,
-                "email": row.get::<_, String>(1)?,
-                "created_at": row.get::<_, String>(2)?
-            }))
+pub async fn get_super_admins(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+    let (super_admins, column_names) = app_data
+        .database
+        .run(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT name, email, role, disabled, permissions, created_at FROM _super_admins",
+            )?;
+            let column_names: Vec<String> =
+                stmt.column_names().iter().map(|s| s.to_string()).collect();
+            let super_admins = stmt
+                .query_map([], |row| {
+                    Ok(serde_json::json!({
+                        "name": row.get::<_, String>(0)?,
+                        "email": row.get::<_, String>(1)?,
+                        "role": row.get::<_, String>(2)?,
+                        "disabled": row.get::<_, bool>(3)?,
+                        "permissions": row
+                            .get::<_, Option<String>>(4)?
+                            .and_then(|p| serde_json::from_str::<serde_json::Value>(&p).ok()),
+                        "created_at": row.get::<_, String>(5)?
+                    }))
+                })?
+                .collect::<rusqlite::Result<Vec<serde_json::Value>>>()?;
+            Ok((super_admins, column_names))
         })
-        .and_then(|mapped_rows| mapped_rows.collect());
-
-    match super_admins {
-        Ok(super_admins) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "records": super_admins,
-            "columns": column_names,
-        }))),
-        Err(err) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "message": format!("Failed to fetch super admins: {}", err),
-            "records": None::<Vec<serde_json::Value>>,
-            "columns": None::<Vec<String>>,
-        }))),
-    }
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "records": super_admins,
+        "columns": column_names,
+    })))
 }
 
 #[derive(Deserialize, Serialize)]
@@ -749,119 +434,168 @@ struct CreateAdmin {
     email: String,
     password: String,
     confirm_password: String,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    permissions: Option<HashMap<String, String>>,
 }
 
 #[post("/create-super-admin")]
-pub async fn create_super_admin_func(request: web::Json<CreateAdmin>) -> Result<impl Responder> {
-    match create_super_admin(
-        request.name.to_string(),
-        request.email.to_string(),
-        request.password.to_string(),
-        request.confirm_password.to_string(),
-    ) {
-        Ok(_) => Ok(web::Json(Response {
-            success: true,
-            message: "Super admin has been added!".to_string(),
-        })),
-        Err(err) => Ok(web::Json(Response {
-            success: false,
-            message: err.to_string(),
-        })),
+pub async fn create_super_admin_func(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    request: web::Json<CreateAdmin>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = require_role(&req, Role::Admin)?;
+
+    let role = match request.role.as_deref().map(Role::parse) {
+        None => Role::Admin,
+        Some(Some(role)) => role,
+        Some(None) => {
+            return Err(ApiError::BadRequest(
+                "Invalid role. Expected owner, admin, editor or viewer".to_string(),
+            ));
+        }
+    };
+    check_assignable(&admin, role)?;
+
+    let permissions = permissions_to_json(&request.permissions).map_err(ApiError::BadRequest)?;
+
+    if request.password != request.confirm_password {
+        return Err(ApiError::BadRequest("Passwords do not match".to_string()));
     }
-}
 
-#[derive(Deserialize, Serialize, Debug)]
-struct CollectionRequest {
-    collection: String,
-    fields: Vec<CollectionFields>,
-}
+    // Hashed before taking a turn as the writer, it is the slow part
+    let password = request.password.clone();
+    let Ok(Ok(hashed)) = web::block(move || hash(password, DEFAULT_COST)).await else {
+        return Err(ApiError::Internal("Failed to hash password".to_string()));
+    };
 
-#[derive(Deserialize, Serialize, Debug)]
-struct CollectionFields {
-    title: String,
-    #[serde(rename = "type")]
-    field_type: String,
-    unique: bool,
-    nullable: bool,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    min: Option<u32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    max: Option<u32>,
-    allowed_extensions: Option<String>,
+    let auditor = AuditContext::from_request(&req);
+    let request = request.into_inner();
+    let (name, email) = (request.name.clone(), request.email.clone());
+    let result = app_data
+        .database
+        .write(move |conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
+                [&request.email],
+                |row| row.get(0),
+            )?;
+            if exists {
+                return Ok(false);
+            }
+
+            create_super_admin(
+                conn,
+                request.name.clone(),
+                request.email.clone(),
+                &hashed,
+                role.as_str(),
+                permissions,
+            )?;
+            auditor.write(
+                conn,
+                "admin.create",
+                &request.email,
+                None,
+                Some(serde_json::json!({
+                    "name": request.name,
+                    "email": request.email,
+                    "role": role.as_str(),
+                    "permissions": request.permissions,
+                })),
+            );
+            Ok(true)
+        })
+        .await;
+
+    match result? {
+        true => {
+            // Verification is only sent when email has been configured
+            let message = if Mailer::from_configs(&app_data.configs.read().unwrap()).is_err() {
+                "Super admin has been added!".to_string()
+            } else {
+                match send_token_email(&app_data, &email, &name, "verify", "/_/auth/verify-email")
+                    .await
+                {
+                    Ok(_) => "Super admin has been added! A verification email has been sent."
+                        .to_string(),
+                    Err(err) => format!(
+                        "Super admin has been added, but the verification email failed: {}",
+                        err
+                    ),
+                }
+            };
+
+            Ok(HttpResponse::Ok().json(Response {
+                success: true,
+                message,
+            }))
+        }
+        false => Err(ApiError::Conflict(format!(
+            "Super admin with email '{}' already exists",
+            email
+        ))),
+    }
 }
 
 #[post("/create-collection")]
 pub async fn create_collection(
+    req: HttpRequest,
     data: web::Json<CollectionRequest>,
     app_data: web::Data<AppData>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
     if data.collection.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(Response {
-            success: false,
-            message: "Collection name is required".to_string(),
-        }));
+        return Err(ApiError::BadRequest(
+            "Collection name is required".to_string(),
+        ));
     }
 
     if data.collection.starts_with('_') {
-        return Ok(HttpResponse::BadRequest().json(Response {
-            success: false,
-            message: "Collection name cannot start with '_'".to_string(),
-        }));
+        return Err(ApiError::BadRequest(
+            "Collection name cannot start with '_'".to_string(),
+        ));
     }
 
-    let conn = match app_data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-            }));
+    for field in &data.fields {
+        if let Some(sizes) = &field.thumb_sizes
+            && let Err(err) = parse_thumb_sizes(sizes)
+        {
+            return Err(ApiError::BadRequest(format!("'{}': {}", field.title, err)));
         }
-    };
+    }
 
-    let table_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?",
-        [&data.collection],
-        |row| row.get(0),
-    );
+    let auditor = AuditContext::from_request(&req);
+    let schema = app_data.schema.clone();
+    let collection = data.collection.clone();
+    let result = app_data
+        .database
+        .write(move |conn| insert_collection(conn, &schema, &auditor, &data))
+        .await;
 
-    if let Ok(count) = table_exists {
-        if count > 0 {
-            return Ok(HttpResponse::BadRequest().json(Response {
-                success: false,
-                message: format!("Collection {} already exists!", &data.collection),
-            }));
-        }
+    match result? {
+        true => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!("Collection {} has been created!", collection),
+        })),
+        false => Err(ApiError::Conflict(format!(
+            "Collection {} already exists!",
+            collection
+        ))),
     }
+}
 
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
-
-    if let Ok(0) = metadata_exists {
-        let create_metadata_table_sql = "CREATE TABLE _database_metadata (
-            table_id TEXT NOT NULL,
-            table_name TEXT NOT NULL,
-            field_name TEXT NOT NULL,
-            field_type TEXT NOT NULL,
-            unique_field BOOLEAN NOT NULL,
-            nullable BOOLEAN NOT NULL,
-            min INTEGER,
-            max INTEGER,
-            allowed_extensions TEXT,
-            created_at TEXT DEFAULT CURRENT_TIMESTAMP,
-            updated_at TEXT DEFAULT CURRENT_TIMESTAMP,
-            PRIMARY KEY (table_name, field_name)
-        )";
-
-        if let Err(err) = conn.execute(create_metadata_table_sql, []) {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to create metadata table: {}", err),
-            }));
-        }
+// Creates the table and its metadata, `false` when a table with that name already exists
+fn insert_collection(
+    conn: &Connection,
+    schema: &Schema,
+    auditor: &AuditContext,
+    data: &CollectionRequest,
+) -> Result<bool, DbError> {
+    if schema.by_name(&data.collection).is_some() {
+        return Ok(false);
     }
 
     let table_id = format!("moo_{}", random_numbers(9));
@@ -906,20 +640,15 @@ pub async fn create_collection(
 
     create_table_sql.push_str(", created_at TEXT DEFAULT CURRENT_TIMESTAMP");
     create_table_sql.push_str(", updated_at TEXT DEFAULT CURRENT_TIMESTAMP");
-    create_table_sql.push_str(")");
+    create_table_sql.push(')');
 
-    if let Err(err) = conn.execute(&create_table_sql, []) {
-        return Ok(HttpResponse::InternalServerError().json(Response {
-            success: false,
-            message: format!("Failed to create table: {}", err),
-        }));
-    }
+    conn.execute(&create_table_sql, [])?;
 
     for field in &data.fields {
-        let insert_metadata_sql = "INSERT INTO _database_metadata (table_id, table_name, field_name, field_type, unique_field, nullable, min, max, allowed_extensions) 
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
+        let insert_metadata_sql = "INSERT INTO _database_metadata (table_id, table_name, field_name, field_type, unique_field, nullable, min, max, allowed_extensions, max_file_size, max_files, thumb_sizes, protected) 
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
 
-        if let Err(err) = conn.execute(
+        conn.execute(
             insert_metadata_sql,
             rusqlite::params![
                 table_id,
@@ -930,20 +659,26 @@ pub async fn create_collection(
                 field.nullable,
                 field.min,
                 field.max,
-                field.allowed_extensions
+                field.allowed_extensions,
+                field.max_file_size,
+                field.max_files,
+                field.thumb_sizes,
+                field.protected
             ],
-        ) {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to save field metadata: {}", err),
-            }));
-        }
+        )?;
     }
 
-    Ok(HttpResponse::Ok().json(Response {
-        success: true,
-        message: format!("Collection {} has been created!", &data.collection),
-    }))
+    schema.reload(conn)?;
+
+    auditor.write(
+        conn,
+        "collection.create",
+        &table_id,
+        None,
+        schema.get(&table_id).map(|c| snapshot_collection(&c)),
+    );
+
+    Ok(true)
 }
 
 fn sql_type_from_field_type(field_type: &str) -> &str {
@@ -960,121 +695,101 @@ fn sql_type_from_field_type(field_type: &str) -> &str {
     }
 }
 
-
-#[derive(Deserialize, Serialize, Debug)]
-pub struct UpdateCollectionFields {
-    title: String,
-    #[serde(rename = "type")]
-    field_type: String,
-    unique: bool,
-    nullable: bool,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    min: Option<u32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    max: Option<u32>,
-    allowed_extensions: Option<String>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-pub struct UpdateCollectionRequest {
-    collection_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    collection_name: Option<String>,
-    #[serde(default)]
-    fields: Vec<UpdateCollectionFields>,
-}
-
 #[post("/update-collection")]
 pub async fn update_collection(
+    req: HttpRequest,
     data: web::Json<UpdateCollectionRequest>,
     app_data: web::Data<AppData>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
     if data.collection_id.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(Response {
-            success: false,
-            message: "collection_id is required".to_string(),
-        }));
+        return Err(ApiError::BadRequest(
+            "collection_id is required".to_string(),
+        ));
     }
 
-    if let Some(ref name) = data.collection_name {
-        if name.starts_with('_') {
-            return Ok(HttpResponse::BadRequest().json(Response {
-                success: false,
-                message: "Collection name cannot start with '_'".to_string(),
-            }));
-        }
+    if let Some(ref name) = data.collection_name
+        && name.starts_with('_')
+    {
+        return Err(ApiError::BadRequest(
+            "Collection name cannot start with '_'".to_string(),
+        ));
     }
 
-    let conn = match app_data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-            }));
+    for field in &data.fields {
+        if let Some(sizes) = &field.thumb_sizes
+            && let Err(err) = parse_thumb_sizes(sizes)
+        {
+            return Err(ApiError::BadRequest(format!("'{}': {}", field.title, err)));
         }
-    };
+    }
 
-    let metadata_exists: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_database_metadata'",
-        [],
-        |row| row.get(0),
-    );
+    let auditor = AuditContext::from_request(&req);
+    let schema = app_data.schema.clone();
+    let staged = Arc::new(Staged::new(app_data.storage.clone()));
+    let storage = staged.clone();
+    let collection_id = data.collection_id.clone();
+    let result = app_data
+        .database
+        .write(move |conn| alter_collection(conn, &schema, storage.as_ref(), &auditor, &data))
+        .await;
+
+    if let Ok(UpdateOutcome::Updated(_)) = &result {
+        staged.commit();
+    }
 
-    if let Ok(0) = metadata_exists {
-        return Ok(HttpResponse::NotFound().json(Response {
-            success: false,
-            message: "Metadata table does not exist".to_string(),
-        }));
+    match result? {
+        UpdateOutcome::Updated(table_name) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!("Collection '{}' updated successfully", table_name),
+        })),
+        UpdateOutcome::NotFound => Err(ApiError::NotFound(format!(
+            "Collection with id '{}' not found",
+            collection_id
+        ))),
+        UpdateOutcome::NameTaken(name) => Err(ApiError::Conflict(format!(
+            "A collection named '{}' already exists",
+            name
+        ))),
     }
+}
 
-    let current_table_name: Result<String, _> = conn.query_row(
-        "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-        [&data.collection_id],
-        |row| row.get(0),
-    );
+// Result of the part of `update_collection` that runs on the database pool
+enum UpdateOutcome {
+    NotFound,
+    NameTaken(String),
+    Updated(String),
+}
 
-    let current_table_name = match current_table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(Response {
-                success: false,
-                message: format!("Collection with id '{}' not found", data.collection_id),
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-            }));
-        }
+fn alter_collection(
+    conn: &Connection,
+    schema: &Schema,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    data: &UpdateCollectionRequest,
+) -> Result<UpdateOutcome, DbError> {
+    let Some(collection) = schema.get(&data.collection_id) else {
+        return Ok(UpdateOutcome::NotFound);
     };
+    let current_table_name = collection.name.clone();
+    let before = snapshot_collection(&collection);
+
+    // Checked before anything is altered, a taken name leaves the collection as it was
+    let new_name = data
+        .collection_name
+        .as_ref()
+        .filter(|name| **name != current_table_name);
+    if let Some(name) = new_name
+        && schema.by_name(name).is_some()
+    {
+        return Ok(UpdateOutcome::NameTaken(name.clone()));
+    }
 
-    let existing_fields: Vec<(String, String)> = {
-        let mut stmt = match conn.prepare(
-            "SELECT field_name, field_type FROM _database_metadata WHERE table_name = ?1",
-        ) {
-            Ok(stmt) => stmt,
-            Err(err) => {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to prepare metadata query: {}", err),
-                }));
-            }
-        };
-
-        match stmt.query_map([&current_table_name], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        }) {
-            Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
-            Err(err) => {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to fetch existing fields: {}", err),
-                }));
-            }
-        }
-    };
+    let existing_fields: Vec<(String, String)> = collection
+        .fields
+        .iter()
+        .map(|field| (field.name.clone(), field.field_type.clone()))
+        .collect();
 
     let incoming_field_names: std::collections::HashSet<String> =
         data.fields.iter().map(|f| f.title.clone()).collect();
@@ -1107,15 +822,7 @@ pub async fn update_collection(
                     let mut paths = Vec::new();
                     for i in 0..col_count {
                         if let Ok(Some(raw)) = row.get::<_, Option<String>>(i) {
-                            if let Ok(serde_json::Value::Array(arr)) = serde_json::from_str(&raw) {
-                                for entry in arr {
-                                    if let Some(p) = entry.as_str() {
-                                        paths.push(p.to_string());
-                                    }
-                                }
-                            } else {
-                                paths.push(raw);
-                            }
+                            paths.extend(file_references(&raw));
                         }
                     }
                     Ok(paths)
@@ -1124,15 +831,17 @@ pub async fn update_collection(
                 .map(|all_paths| {
                     for paths in all_paths {
                         for path in paths {
-                            let _ = std::fs::remove_file(&path);
+                            remove_uploaded_file(conn, storage, &path);
                         }
                     }
                 });
         }
     }
 
-    let existing_field_names: std::collections::HashSet<String> =
-        existing_fields.iter().map(|(name, _)| name.clone()).collect();
+    let existing_field_names: std::collections::HashSet<String> = existing_fields
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
 
     let new_fields: Vec<&UpdateCollectionFields> = data
         .fields
@@ -1152,90 +861,50 @@ pub async fn update_collection(
             alter_sql.push_str(" UNIQUE");
         }
 
-        if let Err(err) = conn.execute(&alter_sql, []) {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to add column '{}': {}", field.title, err),
-            }));
-        }
+        conn.execute(&alter_sql, [])?;
     }
 
     for (field_name, _) in &removed_fields {
-        if let Err(err) = conn.execute(
+        conn.execute(
             &format!(
                 "ALTER TABLE \"{}\" DROP COLUMN \"{}\"",
                 current_table_name, field_name
             ),
             [],
-        ) {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to drop column '{}': {}", field_name, err),
-            }));
-        }
+        )?;
     }
 
-    let target_table_name = match &data.collection_name {
-        Some(new_name) if new_name != &current_table_name => {
-            let name_taken: Result<i64, _> = conn.query_row(
-                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?1",
-                [new_name],
-                |row| row.get(0),
-            );
-
-            if let Ok(count) = name_taken {
-                if count > 0 {
-                    return Ok(HttpResponse::BadRequest().json(Response {
-                        success: false,
-                        message: format!("A collection named '{}' already exists", new_name),
-                    }));
-                }
-            }
-
-            if let Err(err) = conn.execute(
+    let target_table_name = match new_name {
+        Some(new_name) => {
+            conn.execute(
                 &format!(
                     "ALTER TABLE \"{}\" RENAME TO \"{}\"",
                     current_table_name, new_name
                 ),
                 [],
-            ) {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to rename table: {}", err),
-                }));
-            }
+            )?;
 
-            if let Err(err) = conn.execute(
+            conn.execute(
                 "UPDATE _database_metadata SET table_name = ?1 WHERE table_id = ?2",
                 rusqlite::params![new_name, data.collection_id],
-            ) {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to update metadata table name: {}", err),
-                }));
-            }
+            )?;
 
             new_name.clone()
         }
-        _ => current_table_name.clone(),
+        None => current_table_name.clone(),
     };
 
     for (field_name, _) in &removed_fields {
-        if let Err(err) = conn.execute(
+        conn.execute(
             "DELETE FROM _database_metadata WHERE table_name = ?1 AND field_name = ?2",
             rusqlite::params![target_table_name, field_name],
-        ) {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to remove metadata for field '{}': {}", field_name, err),
-            }));
-        }
+        )?;
     }
 
     for field in &data.fields {
         if existing_field_names.contains(&field.title) {
-            if let Err(err) = conn.execute(
-                "UPDATE _database_metadata SET field_type = ?1, unique_field = ?2, nullable = ?3, min = ?4, max = ?5, allowed_extensions = ?6, updated_at = CURRENT_TIMESTAMP WHERE table_name = ?7 AND field_name = ?8",
+            conn.execute(
+                "UPDATE _database_metadata SET field_type = ?1, unique_field = ?2, nullable = ?3, min = ?4, max = ?5, allowed_extensions = ?6, max_file_size = ?7, max_files = ?8, thumb_sizes = ?9, protected = ?10, updated_at = CURRENT_TIMESTAMP WHERE table_name = ?11 AND field_name = ?12",
                 rusqlite::params![
                     field.field_type,
                     field.unique,
@@ -1243,18 +912,17 @@ pub async fn update_collection(
                     field.min,
                     field.max,
                     field.allowed_extensions,
+                    field.max_file_size,
+                    field.max_files,
+                    field.thumb_sizes,
+                    field.protected,
                     target_table_name,
                     field.title,
                 ],
-            ) {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to update metadata for field '{}': {}", field.title, err),
-                }));
-            }
+            )?;
         } else {
-            if let Err(err) = conn.execute(
-                "INSERT INTO _database_metadata (table_id, table_name, field_name, field_type, unique_field, nullable, min, max, allowed_extensions) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            conn.execute(
+                "INSERT INTO _database_metadata (table_id, table_name, field_name, field_type, unique_field, nullable, min, max, allowed_extensions, max_file_size, max_files, thumb_sizes, protected) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
                 rusqlite::params![
                     data.collection_id,
                     target_table_name,
@@ -1265,18 +933,26 @@ pub async fn update_collection(
                     field.min,
                     field.max,
                     field.allowed_extensions,
+                    field.max_file_size,
+                    field.max_files,
+                    field.thumb_sizes,
+                    field.protected,
                 ],
-            ) {
-                return Ok(HttpResponse::InternalServerError().json(Response {
-                    success: false,
-                    message: format!("Failed to insert metadata for field '{}': {}", field.title, err),
-                }));
-            }
+            )?;
         }
     }
 
-    Ok(HttpResponse::Ok().json(Response {
-        success: true,
-        message: format!("Collection '{}' updated successfully", target_table_name),
-    }))
-}
\ No newline at end of file
+    schema.reload(conn)?;
+
+    auditor.write(
+        conn,
+        "collection.update",
+        &data.collection_id,
+        Some(before),
+        schema
+            .get(&data.collection_id)
+            .map(|c| snapshot_collection(&c)),
+    );
+
+    Ok(UpdateOutcome::Updated(target_table_name))
+}
@@ -0,0 +1,199 @@
+use crate::db::database::DbError;
+
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use moosedb_types::ErrorBody;
+use rusqlite::ErrorCode;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Error returned by a handler. Each variant has a stable `code` that clients can match on
+/// instead of the message, which is only meant for people.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request can't be handled as sent
+    BadRequest(String),
+    /// Message for each field that failed validation
+    Validation(HashMap<String, String>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// The request clashes with data that already exists
+    Conflict(String),
+    /// A value of a unique field that another record already has, by field name
+    Duplicate(String),
+    /// Too many failed logins, seconds until the next attempt is allowed
+    Locked(i64),
+    /// A feature that needs settings which haven't been filled in, like email
+    NotConfigured(String),
+    /// A service the server talks to, like the SMTP server, failed
+    Upstream(String),
+    /// The database did not have room for the request in time
+    Busy,
+    /// The database took too long to answer
+    Timeout,
+    /// Anything the client can't fix. Logged, the client only gets a generic message.
+    Internal(String),
+    /// Step of a batch that failed, by index. Nothing the batch did was kept.
+    Operation(usize, Box<ApiError>),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) | ApiError::Duplicate(_) => "conflict",
+            ApiError::Locked(_) => "too_many_attempts",
+            ApiError::NotConfigured(_) => "not_configured",
+            ApiError::Upstream(_) => "upstream_failed",
+            ApiError::Busy => "database_busy",
+            ApiError::Timeout => "database_timeout",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Operation(_, err) => err.code(),
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::Validation(fields) => Some(json!({ "fields": fields })),
+            ApiError::Duplicate(field) => Some(json!({ "field": field })),
+            ApiError::Locked(retry_after) => Some(json!({ "retry_after": retry_after })),
+            ApiError::Operation(index, err) => {
+                let mut details = err.details().unwrap_or_else(|| json!({}));
+                details["operation"] = json!(index);
+                Some(details)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::NotConfigured(message)
+            | ApiError::Upstream(message) => write!(f, "{}", message),
+            ApiError::Validation(fields) => write!(
+                f,
+                "{} field{} failed validation",
+                fields.len(),
+                if fields.len() == 1 { "" } else { "s" }
+            ),
+            ApiError::Duplicate(field) => {
+                write!(f, "A record with the same '{}' already exists", field)
+            }
+            ApiError::Locked(retry_after) => {
+                write!(f, "Too many attempts, try again in {} seconds", retry_after)
+            }
+            ApiError::Busy => write!(f, "Database is busy, try again"),
+            ApiError::Timeout => write!(f, "Database took too long to respond"),
+            ApiError::Internal(_) => write!(f, "Something went wrong on the server"),
+            ApiError::Operation(index, err) => write!(f, "Operation {} failed: {}", index, err),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) | ApiError::Duplicate(_) => StatusCode::CONFLICT,
+            ApiError::Locked(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::NotConfigured(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Operation(_, err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let cause = match self {
+            ApiError::Operation(_, err) => err.as_ref(),
+            err => err,
+        };
+        if let ApiError::Internal(reason) = cause {
+            log::error!("{}", reason);
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::Locked(retry_after) = self {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+        response.json(ErrorBody {
+            success: false,
+            code: self.code().to_string(),
+            message: self.to_string(),
+            details: self.details(),
+        })
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(err: DbError) -> Self {
+        match err {
+            DbError::Busy => ApiError::Busy,
+            DbError::Timeout => ApiError::Timeout,
+            DbError::Pool(reason) => ApiError::Internal(format!("Connection pool: {}", reason)),
+            DbError::Sqlite(err) => err.into(),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err.sqlite_error() {
+            Some(e) if e.code == ErrorCode::ConstraintViolation => match e.extended_code {
+                rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                | rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY => match unique_field(&err) {
+                    Some(field) => ApiError::Duplicate(field),
+                    None => ApiError::Conflict(
+                        "A record with the same unique value already exists".to_string(),
+                    ),
+                },
+                _ => ApiError::BadRequest(
+                    "A value does not satisfy the constraints of its field".to_string(),
+                ),
+            },
+            _ => ApiError::Internal(format!("SQLite: {}", err)),
+        }
+    }
+}
+
+// SQLite names the column as `UNIQUE constraint failed: table.column`
+fn unique_field(err: &rusqlite::Error) -> Option<String> {
+    let rusqlite::Error::SqliteFailure(_, Some(message)) = err else {
+        return None;
+    };
+    let columns = message.strip_prefix("UNIQUE constraint failed: ")?;
+    let column = columns.split(", ").next()?;
+    column.rsplit_once('.').map(|(_, field)| field.to_string())
+}
+
+// Bodies, paths and queries that can't be parsed get the same envelope as every other error
+pub fn json_error(err: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid JSON body: {}", err)).into()
+}
+
+pub fn path_error(err: PathError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid path: {}", err)).into()
+}
+
+pub fn query_error(err: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid query string: {}", err)).into()
+}
@@ -0,0 +1,839 @@
+use crate::AppData;
+use crate::apis::admins::require_read;
+use crate::apis::error::ApiError;
+use crate::db::schema::{Collection, Field};
+use crate::settings;
+use crate::storage::Storage;
+use crate::utils::random::*;
+
+use actix_files::NamedFile;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header::{
+    self, ContentDisposition, DispositionParam, DispositionType, HeaderValue,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use hmac::{Hmac, Mac};
+use mime_guess::from_path;
+use moosedb_types::StoredFile;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct PreparedUpload {
+    pub filename: String,
+    pub bytes: Vec<u8>,
+    pub mime_type: String,
+}
+
+fn slugify(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>()
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn base64_decode(input: &str) -> Result<Vec<u8>, String> {
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut lookup = [0u8; 256];
+    for (i, &c) in alphabet.iter().enumerate() {
+        lookup[c as usize] = i as u8;
+    }
+
+    let input: Vec<u8> = input.bytes().filter(|&c| c != b'=').collect();
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+
+    for chunk in input.chunks(4) {
+        let b = chunk
+            .iter()
+            .map(|&c| lookup[c as usize])
+            .collect::<Vec<_>>();
+
+        if b.len() >= 2 {
+            output.push((b[0] << 2) | (b[1] >> 4));
+        }
+        if b.len() >= 3 {
+            output.push((b[1] << 4) | (b[2] >> 2));
+        }
+        if b.len() >= 4 {
+            output.push((b[2] << 6) | b[3]);
+        }
+    }
+
+    Ok(output)
+}
+
+// Checks the decoded bytes against the extension the client claimed and
+// returns the MIME type that will be stored and served for the file.
+pub fn detect_mime_type(filename: &str, bytes: &[u8]) -> Result<String, String> {
+    let ext = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let claimed = mime_guess::from_ext(&ext);
+
+    match infer::get(bytes) {
+        Some(kind) => {
+            let matches_claim = kind.extension() == ext
+                || claimed.iter().any(|m| m.essence_str() == kind.mime_type());
+            let unknown_claim =
+                claimed.is_empty() && kind.matcher_type() != infer::MatcherType::App;
+
+            if !matches_claim && !unknown_claim {
+                return Err(format!(
+                    "'{}' looks like {} but has a '.{}' extension",
+                    filename,
+                    kind.mime_type(),
+                    ext
+                ));
+            }
+            Ok(kind.mime_type().to_string())
+        }
+        None => {
+            let claimed = claimed.first_or_octet_stream();
+            let sniffable = matches!(claimed.type_().as_str(), "image" | "audio" | "video")
+                && claimed.subtype() != "svg";
+
+            if sniffable || claimed.essence_str() == "application/pdf" {
+                return Err(format!(
+                    "'{}' does not contain valid {} data",
+                    filename,
+                    claimed.essence_str()
+                ));
+            }
+            Ok(claimed.essence_str().to_string())
+        }
+    }
+}
+
+// An upload that has been written to storage but is not recorded in `_files` yet
+pub struct StoredUpload {
+    pub stored_name: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub sha256: String,
+}
+
+// Writes the upload to storage under a new unique name. Done before the writer is taken, so a
+// slow backend doesn't hold up other writes; `record_upload` then adds it to `_files`.
+pub fn store_upload(storage: &dyn Storage, upload: PreparedUpload) -> Result<StoredUpload, String> {
+    let stem = Path::new(&upload.filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+
+    let ext = Path::new(&upload.filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+
+    let slug = slugify(stem);
+    let slug = if slug.is_empty() {
+        "file".to_string()
+    } else {
+        slug
+    };
+
+    let unique_name = if ext.is_empty() {
+        format!("{}-{}", slug, random_numbers(9))
+    } else {
+        format!("{}-{}.{}", slug, random_numbers(9), ext)
+    };
+
+    storage.put(&unique_name, &upload.bytes, &upload.mime_type)?;
+
+    Ok(StoredUpload {
+        stored_name: unique_name,
+        sha256: format!("{:x}", Sha256::digest(&upload.bytes)),
+        size: upload.bytes.len() as i64,
+        filename: upload.filename,
+        mime_type: upload.mime_type,
+    })
+}
+
+// Records a stored upload in `_files` and returns the file id that gets stored in the record's
+// FILE column.
+pub fn record_upload(
+    conn: &Connection,
+    upload: &StoredUpload,
+    collection_id: &str,
+    field_name: &str,
+) -> rusqlite::Result<String> {
+    let file_id = format!("fil{}", simple_uid(12));
+    conn.execute(
+        "INSERT INTO _files (file_id, stored_name, original_name, mime_type, size, sha256, collection_id, field_name)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            file_id,
+            upload.stored_name,
+            upload.filename,
+            upload.mime_type,
+            upload.size,
+            upload.sha256,
+            collection_id,
+            field_name
+        ],
+    )?;
+    Ok(file_id)
+}
+
+// FILE columns hold file ids; databases created before `_files` existed hold
+// `uploads/<name>` paths instead, so both forms are accepted here.
+fn stored_name_of(conn: &Connection, reference: &str) -> Option<String> {
+    if let Some(name) = reference.strip_prefix("uploads/") {
+        return Some(name.to_string());
+    }
+
+    conn.query_row(
+        "SELECT stored_name FROM _files WHERE file_id = ?1",
+        [reference],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+pub fn remove_uploaded_file(conn: &Connection, storage: &dyn Storage, reference: &str) {
+    if let Some(stored_name) = stored_name_of(conn, reference) {
+        let _ = storage.delete(&stored_name);
+        remove_thumbnails(storage, &stored_name);
+        let _ = conn.execute("DELETE FROM _files WHERE stored_name = ?1", [stored_name]);
+    }
+}
+
+fn remove_thumbnails(storage: &dyn Storage, stored_name: &str) {
+    if let Ok(thumbnails) = storage.list(&format!("{}.thumb-", stored_name)) {
+        for thumbnail in thumbnails {
+            let _ = storage.delete(&thumbnail);
+        }
+    }
+}
+
+fn parse_thumb_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.trim().split_once('x')?;
+    let width: u32 = width.parse().ok()?;
+    let height: u32 = height.parse().ok()?;
+
+    if (1..=4096).contains(&width) && (1..=4096).contains(&height) {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+// Parses a FILE field's `thumb_sizes` whitelist, e.g. "100x100,200x150".
+pub fn parse_thumb_sizes(sizes: &str) -> Result<Vec<(u32, u32)>, String> {
+    sizes
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|size| {
+            parse_thumb_size(size).ok_or_else(|| {
+                format!(
+                    "invalid thumb size '{}', expected WIDTHxHEIGHT between 1 and 4096",
+                    size.trim()
+                )
+            })
+        })
+        .collect()
+}
+
+fn generate_thumbnail(
+    storage: &dyn Storage,
+    source: &str,
+    target: &str,
+    width: u32,
+    height: u32,
+    crop: bool,
+) -> Result<(), String> {
+    let bytes = storage
+        .get(source)?
+        .ok_or_else(|| "File not found".to_string())?;
+    let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+
+    let thumbnail = if crop {
+        image.resize_to_fill(width, height, image::imageops::FilterType::Triangle)
+    } else {
+        image.resize(width, height, image::imageops::FilterType::Triangle)
+    };
+
+    let format = image::ImageFormat::from_path(target).map_err(|e| e.to_string())?;
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    thumbnail
+        .write_to(&mut encoded, format)
+        .map_err(|e| e.to_string())?;
+
+    let mime = from_path(target).first_or_octet_stream();
+    storage.put(target, encoded.get_ref(), mime.as_ref())
+}
+
+// Splits the raw value of a FILE column into the file references it holds.
+pub fn file_references(raw: &str) -> Vec<String> {
+    match serde_json::from_str(raw) {
+        Ok(serde_json::Value::Array(arr)) => arr
+            .into_iter()
+            .filter_map(|entry| entry.as_str().map(|s| s.to_string()))
+            .collect(),
+        _ => vec![raw.to_string()],
+    }
+}
+
+pub fn load_file(
+    conn: &Connection,
+    storage: &dyn Storage,
+    reference: &str,
+    base_url: &str,
+) -> Option<StoredFile> {
+    let (column, key) = match reference.strip_prefix("uploads/") {
+        Some(name) => ("stored_name", name),
+        None => ("file_id", reference),
+    };
+
+    let found = conn
+        .query_row(
+            &format!(
+                "SELECT file_id, stored_name, original_name, size, mime_type, sha256, created_at
+                 FROM _files WHERE {} = ?1",
+                column
+            ),
+            [key],
+            |row| {
+                let stored_name: String = row.get(1)?;
+                Ok(StoredFile {
+                    id: row.get(0)?,
+                    url: format!("{}/uploads/{}", base_url, stored_name),
+                    stored_name,
+                    original_name: row.get(2)?,
+                    size: row.get(3)?,
+                    mime_type: row.get(4)?,
+                    sha256: row.get(5)?,
+                    uploaded_at: row.get(6)?,
+                    protected: false,
+                })
+            },
+        )
+        .optional()
+        .ok()
+        .flatten();
+
+    if found.is_some() || column == "file_id" {
+        return found;
+    }
+
+    let size = storage
+        .local_path(key)
+        .and_then(|path| std::fs::metadata(path).ok())
+        .map(|m| m.len() as i64)
+        .unwrap_or(0);
+
+    Some(StoredFile {
+        id: key.to_string(),
+        stored_name: key.to_string(),
+        original_name: key.to_string(),
+        size,
+        mime_type: from_path(key).first_or_octet_stream().to_string(),
+        sha256: None,
+        uploaded_at: None,
+        url: format!("{}/uploads/{}", base_url, key),
+        protected: false,
+    })
+}
+
+// Includes the prefix MooseDB is mounted under when it runs inside another app
+pub fn base_url(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    let prefix = req
+        .app_data::<web::Data<AppData>>()
+        .map(|data| data.prefix.as_str())
+        .unwrap_or_default();
+    format!("{}://{}{}", info.scheme(), info.host(), prefix)
+}
+
+// Replaces the raw value of every FILE column in `records` with the list of
+// file objects it references.
+pub fn expand_file_fields(
+    conn: &Connection,
+    storage: &dyn Storage,
+    collection: &Collection,
+    base_url: &str,
+    records: &mut [serde_json::Value],
+) {
+    let file_fields: Vec<&Field> = collection.file_fields().collect();
+    if file_fields.is_empty() {
+        return;
+    }
+
+    for record in records.iter_mut() {
+        let Some(record) = record.as_object_mut() else {
+            continue;
+        };
+
+        for field in &file_fields {
+            let Some(raw) = record.get(&field.name).and_then(|v| v.as_str()) else {
+                continue;
+            };
+
+            let files: Vec<StoredFile> = file_references(raw)
+                .iter()
+                .filter_map(|reference| load_file(conn, storage, reference, base_url))
+                .map(|file| StoredFile {
+                    protected: field.protected,
+                    ..file
+                })
+                .collect();
+
+            record.insert(
+                field.name.clone(),
+                serde_json::to_value(files).unwrap_or_default(),
+            );
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn file_token_signature(stored_name: &str, expires: u64, secret: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}:{}", stored_name, expires).as_bytes());
+    mac
+}
+
+// Tokens look like `{expires}.{hex hmac}` and are bound to a single stored file
+pub fn sign_file_token(stored_name: &str, expires: u64, secret: &str) -> String {
+    let signature: String = file_token_signature(stored_name, expires, secret)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}.{}", expires, signature)
+}
+
+pub fn verify_file_token(stored_name: &str, token: &str, secret: &str) -> bool {
+    let Some((expires, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(expires) = expires.parse::<u64>() else {
+        return false;
+    };
+    if expires < unix_now() || signature.len() % 2 != 0 {
+        return false;
+    }
+
+    let Ok(signature) = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+    else {
+        return false;
+    };
+
+    file_token_signature(stored_name, expires, secret)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+#[derive(Deserialize)]
+struct FileTokenRequest {
+    collection_id: String,
+    file_id: String,
+}
+
+#[derive(Serialize)]
+struct FileTokenResponse {
+    success: bool,
+    message: String,
+    token: String,
+    url: String,
+    expires_at: u64,
+}
+
+// Issue a short lived token for a file, as long as it still belongs to a record the caller can view
+#[post("/file-token")]
+pub async fn create_file_token(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<FileTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    // Checked first, whether a file exists is only told to those who can read its collection
+    require_read(&req, &request.collection_id)?;
+
+    let FileTokenRequest {
+        collection_id,
+        file_id,
+    } = request.into_inner();
+    let schema = data.schema.clone();
+    let found = data
+        .database
+        .run(move |conn| {
+            let file: Option<(String, Option<String>)> = conn
+                .query_row(
+                    "SELECT stored_name, field_name FROM _files WHERE file_id = ?1 AND collection_id = ?2",
+                    [&file_id, &collection_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            let Some((stored_name, Some(field_name))) = file else {
+                return Ok(None);
+            };
+
+            let table_name = schema
+                .get(&collection_id)
+                .filter(|collection| collection.field(&field_name).is_some())
+                .map(|collection| collection.name.clone());
+
+            // Only a file that is still part of a record can get a token
+            let referenced = table_name.is_some_and(|table_name| {
+                conn.query_row(
+                    &format!(
+                        "SELECT EXISTS(SELECT 1 FROM \"{}\" WHERE instr(\"{}\", ?1) > 0 OR instr(\"{}\", ?2) > 0)",
+                        table_name, field_name, field_name
+                    ),
+                    params![format!("\"{}\"", file_id), format!("uploads/{}", stored_name)],
+                    |row| row.get(0),
+                )
+                .unwrap_or(false)
+            });
+            Ok(Some((stored_name, referenced)))
+        })
+        .await?;
+
+    let Some((stored_name, true)) = found else {
+        return Err(ApiError::NotFound("File not found".to_string()));
+    };
+
+    let expiry = settings::int(&data.configs.read().unwrap(), "file_token_expiry") as u64;
+    let expires_at = unix_now() + expiry;
+    let token = sign_file_token(&stored_name, expires_at, &data.jwt_secret);
+
+    Ok(HttpResponse::Ok().json(FileTokenResponse {
+        success: true,
+        message: "Token has been created".to_string(),
+        url: format!("{}/uploads/{}?token={}", base_url(&req), stored_name, token),
+        token,
+        expires_at,
+    }))
+}
+
+// Body of a proxied HEAD response, reports the size of the object without sending it
+struct HeadBody(u64);
+
+impl MessageBody for HeadBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.0)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Poll::Ready(None)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ServeUploadParams {
+    download: Option<String>,
+    thumb: Option<String>,
+    mode: Option<String>,
+    token: Option<String>,
+}
+
+struct UploadInfo {
+    mime_type: String,
+    original_name: String,
+    collection_id: Option<String>,
+    field_name: Option<String>,
+}
+
+pub async fn serve_upload(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    query: web::Query<ServeUploadParams>,
+) -> Result<HttpResponse, ApiError> {
+    let filename = req.match_info().get("filename").unwrap_or("").to_string();
+    let mut key = filename.clone();
+
+    let stored_key = filename.clone();
+    let stored = data
+        .database
+        .run(move |conn| {
+            let stored: Option<UploadInfo> = conn
+                .query_row(
+                    "SELECT mime_type, original_name, collection_id, field_name FROM _files WHERE stored_name = ?1",
+                    [&stored_key],
+                    |row| {
+                        Ok(UploadInfo {
+                            mime_type: row.get(0)?,
+                            original_name: row.get(1)?,
+                            collection_id: row.get(2)?,
+                            field_name: row.get(3)?,
+                        })
+                    },
+                )
+                .optional()?;
+
+            Ok(stored)
+        })
+        .await;
+
+    let stored = stored?;
+
+    // Thumbnails have no `_files` row of their own and are only served through `?thumb=`, which
+    // checks the protection of the file they were made from
+    if stored.is_none() && filename.contains(".thumb-") {
+        return Err(ApiError::NotFound("File not found".to_string()));
+    }
+
+    let field = match stored.as_ref().map(|s| (&s.collection_id, &s.field_name)) {
+        Some((Some(collection_id), Some(field_name))) => data
+            .schema
+            .get(collection_id)
+            .and_then(|collection| collection.field(field_name).cloned()),
+        _ => None,
+    };
+    let (protected, thumb_sizes) = field
+        .map(|field| (field.protected, field.thumb_sizes))
+        .unwrap_or((false, None));
+
+    let UploadInfo {
+        mut mime_type,
+        original_name,
+        ..
+    } = match stored {
+        Some(info) => info,
+        None => UploadInfo {
+            mime_type: from_path(&filename).first_or_octet_stream().to_string(),
+            original_name: filename.clone(),
+            collection_id: None,
+            field_name: None,
+        },
+    };
+
+    if protected {
+        match &query.token {
+            None => {
+                return Err(ApiError::Unauthorized(
+                    "A token is required to access this file".to_string(),
+                ));
+            }
+            Some(token) if !verify_file_token(&filename, token, &data.jwt_secret) => {
+                return Err(ApiError::Forbidden(
+                    "Token is invalid or has expired".to_string(),
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some(thumb) = &query.thumb {
+        let Some((width, height)) = parse_thumb_size(thumb) else {
+            return Err(ApiError::BadRequest(
+                "Invalid thumb size, expected WIDTHxHEIGHT".to_string(),
+            ));
+        };
+
+        let crop = match query.mode.as_deref() {
+            None | Some("fit") => false,
+            Some("crop") => true,
+            Some(_) => {
+                return Err(ApiError::BadRequest(
+                    "Invalid mode, expected crop or fit".to_string(),
+                ));
+            }
+        };
+
+        if !mime_type.starts_with("image/") || mime_type == "image/svg+xml" {
+            return Err(ApiError::BadRequest(
+                "Thumbnails are only available for images".to_string(),
+            ));
+        }
+
+        let allowed: Vec<(u32, u32)> = thumb_sizes
+            .and_then(|sizes| parse_thumb_sizes(&sizes).ok())
+            .unwrap_or_default();
+
+        if !allowed.contains(&(width, height)) {
+            return Err(ApiError::BadRequest(
+                "Thumb size is not allowed for this file".to_string(),
+            ));
+        }
+
+        let ext = Path::new(&filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png")
+            .to_string();
+        let thumb_key = format!(
+            "{}.thumb-{}x{}-{}.{}",
+            filename,
+            width,
+            height,
+            if crop { "crop" } else { "fit" },
+            ext
+        );
+
+        let storage = data.storage.clone();
+        let source = filename.clone();
+        let target = thumb_key.clone();
+        let generated = web::block(move || {
+            if storage.exists(&target)? {
+                return Ok(());
+            }
+            generate_thumbnail(storage.as_ref(), &source, &target, width, height, crop)
+        })
+        .await;
+
+        match generated {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                return Err(ApiError::Internal(format!(
+                    "Thumbnail {} could not be generated: {}",
+                    thumb_key, err
+                )));
+            }
+            Err(err) => return Err(ApiError::Internal(err.to_string())),
+        }
+
+        mime_type = from_path(&thumb_key).first_or_octet_stream().to_string();
+        key = thumb_key;
+    }
+
+    if let Some(url) = data.storage.read_url(&key) {
+        return Ok(HttpResponse::Found()
+            .insert_header(("Location", url))
+            .finish());
+    }
+
+    let disposition = ContentDisposition {
+        disposition: if query.download.is_some() {
+            DispositionType::Attachment
+        } else {
+            DispositionType::Inline
+        },
+        parameters: vec![DispositionParam::Filename(original_name)],
+    };
+
+    let cache_control = if protected {
+        "private, no-store".to_string()
+    } else {
+        data.configs
+            .read()
+            .unwrap()
+            .get("uploads_cache_control")
+            .cloned()
+            .unwrap_or_else(|| "public, max-age=86400".to_string())
+    };
+
+    if let Some(path) = data.storage.local_path(&key) {
+        let file = match NamedFile::open_async(&path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ApiError::NotFound("File not found".to_string()));
+            }
+            Err(err) => {
+                return Err(ApiError::Internal(format!(
+                    "Upload {} could not be read: {}",
+                    key, err
+                )));
+            }
+        };
+
+        let mut response = file
+            .set_content_type(
+                mime_type
+                    .parse()
+                    .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM),
+            )
+            .set_content_disposition(disposition)
+            .into_response(&req);
+
+        if let Ok(value) = HeaderValue::from_str(&cache_control) {
+            response.headers_mut().insert(header::CACHE_CONTROL, value);
+        }
+        response.headers_mut().insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+        return Ok(response);
+    }
+
+    // Range and conditional headers are passed on so the backend can answer with 206 or 304
+    let forwarded: Vec<(String, String)> = [
+        header::RANGE,
+        header::IF_MATCH,
+        header::IF_NONE_MATCH,
+        header::IF_MODIFIED_SINCE,
+        header::IF_UNMODIFIED_SINCE,
+    ]
+    .iter()
+    .filter_map(|name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| (name.to_string(), value.to_string()))
+    })
+    .collect();
+
+    let method = req.method().to_string();
+    let storage = data.storage.clone();
+    match web::block(move || storage.fetch(&method, &key, &forwarded)).await {
+        Ok(Ok(Some(object))) => {
+            let status = StatusCode::from_u16(object.status).unwrap_or(StatusCode::OK);
+            let mut response = HttpResponse::build(status);
+
+            let mut content_length = None;
+            for (name, value) in object.headers {
+                if name == "content-length" {
+                    content_length = value.parse::<u64>().ok();
+                } else {
+                    response.insert_header((name, value));
+                }
+            }
+
+            if status.is_success() {
+                response
+                    .content_type(mime_type)
+                    .insert_header(disposition)
+                    .insert_header(("X-Content-Type-Options", "nosniff"));
+            }
+
+            response.insert_header((header::CACHE_CONTROL, cache_control));
+
+            Ok(match content_length {
+                Some(length) if req.method() == Method::HEAD => response.body(HeadBody(length)),
+                _ => response.body(object.body),
+            })
+        }
+        Ok(Ok(None)) => Err(ApiError::NotFound("File not found".to_string())),
+        Ok(Err(err)) => Err(ApiError::Internal(format!(
+            "Upload could not be fetched: {}",
+            err
+        ))),
+        Err(err) => Err(ApiError::Internal(err.to_string())),
+    }
+}
@@ -0,0 +1,219 @@
+use crate::AppData;
+use crate::apis::admins::{Role, require_role};
+use crate::apis::error::ApiError;
+use crate::apis::sessions::unix_now;
+use crate::settings;
+
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Attempts are kept for a month so admins can look back at them
+const RETENTION_SECONDS: i64 = 30 * 24 * 3600;
+
+/// Limits read from the `login_*` settings.
+pub struct LoginPolicy {
+    max_attempts: i64,
+    max_attempts_per_ip: i64,
+    window: i64,
+    lockout: i64,
+}
+
+impl LoginPolicy {
+    pub fn from_configs(configs: &HashMap<String, String>) -> Self {
+        LoginPolicy {
+            max_attempts: settings::int(configs, "login_max_attempts"),
+            max_attempts_per_ip: settings::int(configs, "login_max_attempts_per_ip"),
+            window: settings::int(configs, "login_attempt_window"),
+            lockout: settings::int(configs, "login_lockout_seconds"),
+        }
+    }
+}
+
+// User agent and IP of the client making the request. Forwarded headers are only believed from
+// the configured `trusted_proxies`, anyone else could send them to pose as another address.
+pub fn client_info(req: &HttpRequest) -> (String, String) {
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    let behind_proxy = peer.is_some_and(|ip| {
+        req.app_data::<web::Data<AppData>>()
+            .is_some_and(|data| data.config.trusted_proxies.contains(&ip))
+    });
+    let ip = if behind_proxy {
+        req.connection_info()
+            .realip_remote_addr()
+            .unwrap_or("")
+            .to_string()
+    } else {
+        peer.map(|ip| ip.to_string()).unwrap_or_default()
+    };
+    (user_agent, ip)
+}
+
+pub(crate) fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// Returns the id of the new attempt
+pub fn record_login_attempt(
+    conn: &Connection,
+    email: &str,
+    ip: &str,
+    user_agent: &str,
+    success: bool,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO _login_attempts (email, ip, user_agent, success, attempted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![normalize_email(email), ip, user_agent, success, unix_now()],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute(
+        "DELETE FROM _login_attempts WHERE attempted_at < ?1",
+        [unix_now() - RETENTION_SECONDS],
+    )?;
+    Ok(id)
+}
+
+// Turn an attempt that was recorded as failed before the password was checked into a success
+pub fn mark_login_succeeded(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("UPDATE _login_attempts SET success = 1 WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+// Drop an attempt that was recorded before its outcome was known and should not count either way
+pub fn discard_login_attempt(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM _login_attempts WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+// Seconds left before `column = value` may try again. Failures are counted inside the window and
+// since the last successful login, each one past the limit doubles the wait up to the window.
+fn remaining_for(
+    conn: &Connection,
+    column: &str,
+    value: &str,
+    max_attempts: i64,
+    policy: &LoginPolicy,
+) -> rusqlite::Result<i64> {
+    let now = unix_now();
+    let (failures, last_failure): (i64, Option<i64>) = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), MAX(attempted_at) FROM _login_attempts
+             WHERE {column} = ?1 AND success = 0 AND attempted_at > ?2
+             AND attempted_at >= (SELECT COALESCE(MAX(attempted_at), 0) FROM _login_attempts
+                                  WHERE {column} = ?1 AND success = 1)"
+        ),
+        params![value, now - policy.window],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let Some(last_failure) = last_failure else {
+        return Ok(0);
+    };
+    if failures < max_attempts {
+        return Ok(0);
+    }
+
+    let doublings = (failures - max_attempts).min(16) as u32;
+    let wait = policy
+        .lockout
+        .saturating_mul(1 << doublings)
+        .min(policy.window);
+    Ok((last_failure + wait - now).max(0))
+}
+
+// Seconds before another login attempt is accepted for this email and IP, 0 if it is allowed
+pub fn lockout_remaining(
+    conn: &Connection,
+    policy: &LoginPolicy,
+    email: &str,
+    ip: &str,
+) -> rusqlite::Result<i64> {
+    let by_email = remaining_for(
+        conn,
+        "email",
+        &normalize_email(email),
+        policy.max_attempts,
+        policy,
+    )?;
+    let by_ip = remaining_for(conn, "ip", ip, policy.max_attempts_per_ip, policy)?;
+    Ok(by_email.max(by_ip))
+}
+
+#[derive(Deserialize)]
+struct LoginAttemptsQuery {
+    email: Option<String>,
+    ip: Option<String>,
+    all: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct LoginAttempt {
+    email: String,
+    ip: String,
+    user_agent: Option<String>,
+    success: bool,
+    created_at: String,
+}
+
+#[derive(Serialize)]
+struct LoginAttemptsResponse {
+    success: bool,
+    message: String,
+    attempts: Vec<LoginAttempt>,
+}
+
+// Latest failed logins, filtered by `?email=` or `?ip=`. `?all=true` includes successful ones.
+#[get("/login-attempts")]
+pub async fn get_login_attempts(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    query: web::Query<LoginAttemptsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let email = query.email.as_deref().map(normalize_email);
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let (ip, all) = (query.ip.clone(), query.all.unwrap_or(false));
+
+    let attempts: Result<Vec<LoginAttempt>, _> = data
+        .database
+        .run(move |conn| {
+            let attempts = conn
+                .prepare(
+                    "SELECT email, ip, user_agent, success, created_at FROM _login_attempts
+                     WHERE (?1 IS NULL OR email = ?1) AND (?2 IS NULL OR ip = ?2) AND (?3 OR success = 0)
+                     ORDER BY attempted_at DESC, id DESC LIMIT ?4",
+                )?
+                .query_map(params![email, ip, all, limit], |row| {
+                    Ok(LoginAttempt {
+                        email: row.get(0)?,
+                        ip: row.get(1)?,
+                        user_agent: row.get(2)?,
+                        success: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(attempts)
+        })
+        .await;
+
+    match attempts {
+        Ok(attempts) => Ok(HttpResponse::Ok().json(LoginAttemptsResponse {
+            success: true,
+            message: format!("{} login attempts", attempts.len()),
+            attempts,
+        })),
+        Err(err) => Err(err.into()),
+    }
+}
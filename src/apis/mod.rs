@@ -1,5 +1,15 @@
+pub mod account;
+pub mod admins;
+pub mod audit;
 pub mod auth;
+pub mod batch;
 pub mod collections;
+pub mod error;
+pub mod files;
+pub mod login_attempts;
+pub mod openapi;
 pub mod public;
 pub mod records;
+pub mod sessions;
 pub mod settings;
+pub mod two_factor;
@@ -0,0 +1,937 @@
+use crate::AppData;
+use crate::apis::files::base_url;
+use crate::db::schema::{Collection, Field};
+
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use serde_json::{Map, Value, json};
+
+// OpenAPI 3 document of the server. The routes below are fixed, every collection adds its own
+// record schemas and paths from the schema cache.
+
+struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    // Needs a bearer token from `/auth/login`
+    auth: bool,
+    // Component schema of the JSON body
+    body: Option<&'static str>,
+}
+
+impl Endpoint {
+    const fn new(
+        method: &'static str,
+        path: &'static str,
+        tag: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        Endpoint {
+            method,
+            path,
+            tag,
+            summary,
+            auth: false,
+            body: None,
+        }
+    }
+
+    const fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Endpoint::new("get", path, tag, summary)
+    }
+
+    const fn post(path: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Endpoint::new("post", path, tag, summary)
+    }
+
+    const fn auth(self) -> Self {
+        Endpoint { auth: true, ..self }
+    }
+
+    const fn body(self, schema: &'static str) -> Self {
+        Endpoint {
+            body: Some(schema),
+            ..self
+        }
+    }
+}
+
+const ENDPOINTS: &[Endpoint] = &[
+    Endpoint::post("/auth/login", "auth", "Log in with email and password").body("LoginRequest"),
+    Endpoint::post(
+        "/auth/login/2fa",
+        "auth",
+        "Finish a login with a two-factor code",
+    )
+    .body("TwoFactorLoginRequest"),
+    Endpoint::post(
+        "/auth/refresh",
+        "auth",
+        "Exchange a refresh token for a new token pair",
+    )
+    .body("RefreshRequest"),
+    Endpoint::post(
+        "/auth/forgot-password",
+        "auth",
+        "Email a password reset link",
+    )
+    .body("EmailRequest"),
+    Endpoint::post(
+        "/auth/reset-password",
+        "auth",
+        "Set a new password with a reset token",
+    )
+    .body("ResetPasswordRequest"),
+    Endpoint::post(
+        "/auth/verify-email",
+        "auth",
+        "Verify an email address with its token",
+    )
+    .body("TokenRequest"),
+    Endpoint::get(
+        "/uploads/{filename}",
+        "files",
+        "Download an uploaded file or a thumbnail",
+    ),
+    Endpoint::post(
+        "/api/get-version",
+        "public",
+        "Versions of the server and its libraries",
+    ),
+    Endpoint::get(
+        "/api/records/{collection_id}",
+        "public",
+        "List records of a collection by page",
+    ),
+    Endpoint::get(
+        "/api/records/{collection_id}/{record_id}",
+        "public",
+        "Get a single record",
+    ),
+    Endpoint::get("/api/openapi.json", "public", "This document"),
+    Endpoint::post(
+        "/api/batch",
+        "records",
+        "Create, update, delete and upsert records in one transaction",
+    )
+    .auth()
+    .body("BatchRequest"),
+    Endpoint::get(
+        "/admin/api/collections",
+        "collections",
+        "Collections the caller can read",
+    )
+    .auth(),
+    Endpoint::post(
+        "/admin/api/create-collection",
+        "collections",
+        "Create a collection",
+    )
+    .auth()
+    .body("CollectionRequest"),
+    Endpoint::post(
+        "/admin/api/update-collection",
+        "collections",
+        "Rename or change a collection",
+    )
+    .auth()
+    .body("UpdateCollectionRequest"),
+    Endpoint::post(
+        "/admin/api/delete-collection",
+        "collections",
+        "Delete a collection",
+    )
+    .auth()
+    .body("CollectionIdRequest"),
+    Endpoint::post(
+        "/admin/api/get-collection-records",
+        "records",
+        "Every record of a collection",
+    )
+    .auth()
+    .body("CollectionIdRequest"),
+    Endpoint::post("/admin/api/create-record", "records", "Create a record")
+        .auth()
+        .body("CreateRecordRequest"),
+    Endpoint::post(
+        "/admin/api/delete-collection-records",
+        "records",
+        "Delete records by id",
+    )
+    .auth()
+    .body("DeleteRecordsRequest"),
+    Endpoint::post(
+        "/admin/api/file-token",
+        "files",
+        "Token for a protected file",
+    )
+    .auth()
+    .body("FileTokenRequest"),
+    Endpoint::get("/admin/api/me", "admins", "The logged in admin").auth(),
+    Endpoint::get("/admin/api/get-super-admins", "admins", "List admins").auth(),
+    Endpoint::post("/admin/api/create-super-admin", "admins", "Create an admin")
+        .auth()
+        .body("CreateAdminRequest"),
+    Endpoint::post(
+        "/admin/api/update-admin",
+        "admins",
+        "Change the role or permissions of an admin",
+    )
+    .auth(),
+    Endpoint::post(
+        "/admin/api/disable-admin",
+        "admins",
+        "Disable or enable an admin",
+    )
+    .auth(),
+    Endpoint::post("/admin/api/delete-admin", "admins", "Delete an admin").auth(),
+    Endpoint::post(
+        "/admin/api/update-your-password",
+        "account",
+        "Change your password",
+    )
+    .auth(),
+    Endpoint::post("/admin/api/logout", "account", "Revoke the current session").auth(),
+    Endpoint::get("/admin/api/sessions", "account", "Your active sessions").auth(),
+    Endpoint::post("/admin/api/revoke-session", "account", "Revoke one session").auth(),
+    Endpoint::get("/admin/api/2fa", "account", "Two-factor status").auth(),
+    Endpoint::post("/admin/api/2fa/setup", "account", "Start two-factor setup").auth(),
+    Endpoint::post(
+        "/admin/api/2fa/enable",
+        "account",
+        "Confirm two-factor setup",
+    )
+    .auth(),
+    Endpoint::post("/admin/api/2fa/disable", "account", "Turn off two-factor").auth(),
+    Endpoint::get("/admin/api/login-attempts", "logs", "Recent login attempts").auth(),
+    Endpoint::get("/admin/api/audit-log", "logs", "Audit log entries").auth(),
+    Endpoint::get(
+        "/admin/api/audit-log/export",
+        "logs",
+        "Export the audit log",
+    )
+    .auth(),
+    Endpoint::post("/admin/api/get-setting", "settings", "Value of one setting")
+        .auth()
+        .body("SettingKeyRequest"),
+    Endpoint::post("/admin/api/update-setting", "settings", "Change a setting")
+        .auth()
+        .body("UpdateSettingRequest"),
+    Endpoint::get(
+        "/admin/api/settings",
+        "settings",
+        "Every setting with its schema",
+    )
+    .auth(),
+    Endpoint::post(
+        "/admin/api/settings/reload",
+        "settings",
+        "Reload settings from the database",
+    )
+    .auth(),
+    Endpoint::get("/admin/api/config", "settings", "Startup configuration").auth(),
+    Endpoint::post(
+        "/admin/api/send-test-email",
+        "settings",
+        "Send a test email",
+    )
+    .auth()
+    .body("TestEmailRequest"),
+];
+
+fn path_parameters(path: &str) -> Vec<Value> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect()
+}
+
+fn operation(
+    tag: &str,
+    summary: &str,
+    auth: bool,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+    success: Value,
+) -> Value {
+    let mut operation = json!({
+        "tags": [tag],
+        "summary": summary,
+        "responses": {
+            "200": {
+                "description": "Success",
+                "content": { "application/json": { "schema": success } },
+            },
+            "default": { "$ref": "#/components/responses/Error" },
+        },
+    });
+
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+    if let Some(body) = body {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": body } },
+        });
+    }
+    if auth {
+        operation["security"] = json!([{ "bearerAuth": [] }]);
+    }
+    operation
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+// `created_at` and `updated_at`, set by SQLite in UTC
+fn timestamp() -> Value {
+    json!({ "type": "string", "example": "2025-01-31 12:00:00" })
+}
+
+/// Name of the schemas of a collection, `blog_posts` becomes `BlogPosts`.
+pub fn type_name(collection: &str) -> String {
+    let name: String = collection
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect();
+
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Collection{}", name)
+    } else {
+        name
+    }
+}
+
+/// JSON schema of a field as the API returns it. FILE fields come back as a list of files.
+fn field_schema(field: &Field) -> Value {
+    let mut schema = match field.field_type.as_str() {
+        "INTEGER" => json!({ "type": "integer", "format": "int64" }),
+        "DECIMAL" => json!({ "type": "number", "format": "double" }),
+        // Stored as 0 or 1
+        "BOOLEAN" => json!({ "type": "integer", "enum": [0, 1] }),
+        "DATETIME" | "TIMESTAMP" => json!({ "type": "string" }),
+        "FILE" => json!({ "type": "array", "items": schema_ref("StoredFile") }),
+        _ => json!({ "type": "string" }),
+    };
+
+    let (min_key, max_key) = match field.field_type.as_str() {
+        "VARCHAR" | "TEXT" => ("minLength", "maxLength"),
+        "INTEGER" | "DECIMAL" => ("minimum", "maximum"),
+        _ => ("", ""),
+    };
+    if !min_key.is_empty() {
+        if let Some(min) = field.min {
+            schema[min_key] = json!(min);
+        }
+        if let Some(max) = field.max {
+            schema[max_key] = json!(max);
+        }
+    }
+
+    if field.field_type == "FILE" {
+        if let Some(max_files) = field.max_files {
+            schema["maxItems"] = json!(max_files);
+        }
+        let mut notes = Vec::new();
+        if let Some(extensions) = &field.allowed_extensions {
+            notes.push(format!("Allowed extensions: {}", extensions));
+        }
+        if let Some(size) = field.max_file_size {
+            notes.push(format!("Max file size: {} bytes", size));
+        }
+        if field.protected {
+            notes.push("Protected, downloads need a token from /admin/api/file-token".to_string());
+        }
+        if !notes.is_empty() {
+            schema["description"] = json!(notes.join(". "));
+        }
+    }
+
+    if field.unique {
+        schema["x-unique"] = json!(true);
+    }
+    if field.nullable {
+        schema["nullable"] = json!(true);
+    }
+    schema
+}
+
+/// JSON schema of a field in the body of `create-record`. Files are sent as base64 data.
+fn input_schema(field: &Field) -> Value {
+    if field.field_type != "FILE" {
+        return field_schema(field);
+    }
+
+    let upload = json!({
+        "type": "object",
+        "required": ["filename", "mime_type", "data"],
+        "properties": {
+            "filename": { "type": "string" },
+            "mime_type": { "type": "string" },
+            "data": { "type": "string", "format": "byte" },
+        },
+    });
+    let mut schema = json!({ "oneOf": [upload.clone(), { "type": "array", "items": upload }] });
+    if field.nullable {
+        schema["nullable"] = json!(true);
+    }
+    schema
+}
+
+fn collection_schemas(collection: &Collection, schemas: &mut Map<String, Value>) {
+    let name = type_name(&collection.name);
+
+    let mut properties = Map::new();
+    properties.insert("id".to_string(), json!({ "type": "string" }));
+    for field in &collection.fields {
+        properties.insert(field.name.clone(), field_schema(field));
+    }
+    properties.insert("created_at".to_string(), timestamp());
+    properties.insert("updated_at".to_string(), timestamp());
+
+    let mut required = vec![json!("id")];
+    required.extend(
+        collection
+            .fields
+            .iter()
+            .filter(|field| !field.nullable)
+            .map(|field| json!(field.name)),
+    );
+    required.extend([json!("created_at"), json!("updated_at")]);
+
+    schemas.insert(
+        name.clone(),
+        json!({
+            "type": "object",
+            "description": format!("Record of the '{}' collection", collection.name),
+            "required": required,
+            "properties": properties,
+        }),
+    );
+
+    let inputs: Map<String, Value> = collection
+        .fields
+        .iter()
+        .map(|field| (field.name.clone(), input_schema(field)))
+        .collect();
+    let input_required: Vec<&String> = collection
+        .fields
+        .iter()
+        .filter(|field| !field.nullable)
+        .map(|field| &field.name)
+        .collect();
+    schemas.insert(
+        format!("{}Input", name),
+        json!({
+            "type": "object",
+            "required": input_required,
+            "properties": inputs,
+        }),
+    );
+
+    schemas.insert(
+        format!("{}Page", name),
+        json!({
+            "type": "object",
+            "required": ["success", "message", "records", "pagination"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "records": { "type": "array", "items": schema_ref(&name) },
+                "pagination": schema_ref("Pagination"),
+            },
+        }),
+    );
+
+    schemas.insert(
+        format!("{}Single", name),
+        json!({
+            "type": "object",
+            "required": ["success", "message", "record"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "record": schema_ref(&name),
+            },
+        }),
+    );
+}
+
+fn collection_paths(collection: &Collection, paths: &mut Map<String, Value>) {
+    let name = type_name(&collection.name);
+    let summary = format!("List '{}' records, one page at a time", collection.name);
+
+    paths.insert(
+        format!("/api/records/{}", collection.id),
+        json!({
+            "get": operation(
+                &collection.name,
+                &summary,
+                false,
+                pagination_parameters(),
+                None,
+                schema_ref(&format!("{}Page", name)),
+            ),
+        }),
+    );
+
+    paths.insert(
+        format!("/api/records/{}/{{record_id}}", collection.id),
+        json!({
+            "get": operation(
+                &collection.name,
+                &format!("Get a single '{}' record", collection.name),
+                false,
+                path_parameters("{record_id}"),
+                None,
+                schema_ref(&format!("{}Single", name)),
+            ),
+        }),
+    );
+}
+
+fn pagination_parameters() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "page",
+            "in": "query",
+            "schema": { "type": "integer", "minimum": 1, "default": 1 },
+        }),
+        json!({
+            "name": "items",
+            "in": "query",
+            "description": "Records per page, defaults to the records_per_page setting",
+            "schema": { "type": "integer", "minimum": 1, "maximum": 10000 },
+        }),
+    ]
+}
+
+fn static_paths(paths: &mut Map<String, Value>) {
+    for endpoint in ENDPOINTS {
+        let mut parameters = path_parameters(endpoint.path);
+        let success = match endpoint.path {
+            "/api/records/{collection_id}" => {
+                parameters.extend(pagination_parameters());
+                schema_ref("RecordPage")
+            }
+            "/api/records/{collection_id}/{record_id}" => schema_ref("RecordSingle"),
+            "/auth/login" | "/auth/refresh" => schema_ref("TokenResponse"),
+            "/admin/api/create-record" => {
+                parameters.push(json!({
+                    "name": "upsert_on",
+                    "in": "query",
+                    "description": "Unique field, the record with the same value is updated instead of a conflict",
+                    "schema": { "type": "string" },
+                }));
+                schema_ref("CreateRecordResponse")
+            }
+            "/api/batch" => schema_ref("BatchResponse"),
+            "/uploads/{filename}" => {
+                parameters.extend([
+                    json!({ "name": "token", "in": "query", "schema": { "type": "string" } }),
+                    json!({ "name": "thumb", "in": "query", "schema": { "type": "string", "example": "100x100" } }),
+                    json!({ "name": "mode", "in": "query", "schema": { "type": "string", "enum": ["fit", "crop"] } }),
+                    json!({ "name": "download", "in": "query", "schema": { "type": "string" } }),
+                ]);
+                json!({ "type": "string", "format": "binary" })
+            }
+            "/api/openapi.json" => json!({ "type": "object" }),
+            _ => schema_ref("Response"),
+        };
+
+        let operation = operation(
+            endpoint.tag,
+            endpoint.summary,
+            endpoint.auth,
+            parameters,
+            endpoint.body.map(schema_ref),
+            success,
+        );
+        paths
+            .entry(endpoint.path.to_string())
+            .or_insert_with(|| json!({}))[endpoint.method] = operation;
+    }
+}
+
+fn base_schemas() -> Map<String, Value> {
+    let schemas = json!({
+        "Response": {
+            "type": "object",
+            "required": ["success", "message"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+            },
+            "additionalProperties": true,
+        },
+        "Error": {
+            "type": "object",
+            "required": ["success", "code", "message"],
+            "properties": {
+                "success": { "type": "boolean", "enum": [false] },
+                "code": {
+                    "type": "string",
+                    "enum": [
+                        "bad_request", "validation_failed", "unauthorized", "forbidden",
+                        "not_found", "conflict", "too_many_attempts", "not_configured",
+                        "upstream_failed", "database_busy", "database_timeout", "internal_error",
+                    ],
+                },
+                "message": { "type": "string" },
+                "details": { "type": "object", "additionalProperties": true },
+            },
+        },
+        "Pagination": {
+            "type": "object",
+            "required": [
+                "current_page", "items_per_page", "total_records", "total_pages",
+                "records_shown", "has_next_page", "has_prev_page",
+            ],
+            "properties": {
+                "current_page": { "type": "integer" },
+                "items_per_page": { "type": "integer" },
+                "total_records": { "type": "integer" },
+                "total_pages": { "type": "integer" },
+                "records_shown": { "type": "integer" },
+                "has_next_page": { "type": "boolean" },
+                "has_prev_page": { "type": "boolean" },
+                "next_page": { "type": "string", "nullable": true },
+                "prev_page": { "type": "string", "nullable": true },
+            },
+        },
+        "Record": {
+            "type": "object",
+            "required": ["id", "created_at", "updated_at"],
+            "properties": {
+                "id": { "type": "string" },
+                "created_at": timestamp(),
+                "updated_at": timestamp(),
+            },
+            "additionalProperties": true,
+        },
+        "RecordPage": {
+            "type": "object",
+            "required": ["success", "message", "records", "pagination"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "records": { "type": "array", "items": schema_ref("Record") },
+                "pagination": schema_ref("Pagination"),
+            },
+        },
+        "RecordSingle": {
+            "type": "object",
+            "required": ["success", "message", "record"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "record": schema_ref("Record"),
+            },
+        },
+        "StoredFile": {
+            "type": "object",
+            "required": ["id", "stored_name", "original_name", "size", "mime_type", "url", "protected"],
+            "properties": {
+                "id": { "type": "string" },
+                "stored_name": { "type": "string" },
+                "original_name": { "type": "string" },
+                "size": { "type": "integer" },
+                "mime_type": { "type": "string" },
+                "sha256": { "type": "string", "nullable": true },
+                "uploaded_at": { "type": "string", "nullable": true },
+                "url": { "type": "string" },
+                "protected": { "type": "boolean" },
+            },
+        },
+        "TokenResponse": {
+            "type": "object",
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "token": { "type": "string" },
+                "refresh_token": { "type": "string" },
+                "expires_in": { "type": "integer" },
+            },
+        },
+        "LoginRequest": {
+            "type": "object",
+            "required": ["email", "password"],
+            "properties": {
+                "email": { "type": "string" },
+                "password": { "type": "string" },
+            },
+        },
+        "TwoFactorLoginRequest": {
+            "type": "object",
+            "required": ["challenge_token", "code"],
+            "properties": {
+                "challenge_token": { "type": "string" },
+                "code": { "type": "string" },
+            },
+        },
+        "RefreshRequest": {
+            "type": "object",
+            "required": ["refresh_token"],
+            "properties": { "refresh_token": { "type": "string" } },
+        },
+        "EmailRequest": {
+            "type": "object",
+            "required": ["email"],
+            "properties": { "email": { "type": "string" } },
+        },
+        "TestEmailRequest": {
+            "type": "object",
+            "required": ["to"],
+            "properties": { "to": { "type": "string" } },
+        },
+        "TokenRequest": {
+            "type": "object",
+            "required": ["token"],
+            "properties": { "token": { "type": "string" } },
+        },
+        "ResetPasswordRequest": {
+            "type": "object",
+            "required": ["token", "password", "confirm_password"],
+            "properties": {
+                "token": { "type": "string" },
+                "password": { "type": "string" },
+                "confirm_password": { "type": "string" },
+            },
+        },
+        "CollectionField": {
+            "type": "object",
+            "required": ["title", "type", "unique", "nullable"],
+            "properties": {
+                "title": { "type": "string" },
+                "type": {
+                    "type": "string",
+                    "enum": ["VARCHAR", "TEXT", "INTEGER", "DECIMAL", "BOOLEAN", "DATETIME", "TIMESTAMP", "FILE"],
+                },
+                "unique": { "type": "boolean" },
+                "nullable": { "type": "boolean" },
+                "min": { "type": "integer" },
+                "max": { "type": "integer" },
+                "allowed_extensions": { "type": "string", "nullable": true },
+                "max_file_size": { "type": "integer" },
+                "max_files": { "type": "integer" },
+                "thumb_sizes": { "type": "string" },
+                "protected": { "type": "boolean" },
+            },
+        },
+        "CollectionRequest": {
+            "type": "object",
+            "required": ["collection", "fields"],
+            "properties": {
+                "collection": { "type": "string" },
+                "fields": { "type": "array", "items": schema_ref("CollectionField") },
+            },
+        },
+        "UpdateCollectionRequest": {
+            "type": "object",
+            "required": ["collection_id"],
+            "properties": {
+                "collection_id": { "type": "string" },
+                "collection_name": { "type": "string" },
+                "fields": { "type": "array", "items": schema_ref("CollectionField") },
+            },
+        },
+        "CollectionIdRequest": {
+            "type": "object",
+            "required": ["collection_id"],
+            "properties": { "collection_id": { "type": "string" } },
+        },
+        "CreateRecordRequest": {
+            "type": "object",
+            "required": ["collection_id", "data"],
+            "properties": {
+                "collection_id": { "type": "string" },
+                "data": {
+                    "type": "object",
+                    "description": "Values of the record, see the <Collection>Input schemas",
+                    "additionalProperties": true,
+                },
+            },
+        },
+        "CreateRecordResponse": {
+            "type": "object",
+            "required": ["success", "message", "id", "status"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "id": { "type": "string", "example": "moo0123456789ab" },
+                "status": { "type": "string", "enum": ["created", "updated"] },
+            },
+        },
+        "DeleteRecordsRequest": {
+            "type": "object",
+            "required": ["collection_id", "record_ids"],
+            "properties": {
+                "collection_id": { "type": "string" },
+                "record_ids": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "FileTokenRequest": {
+            "type": "object",
+            "required": ["collection_id", "file_id"],
+            "properties": {
+                "collection_id": { "type": "string" },
+                "file_id": { "type": "string" },
+            },
+        },
+        "CreateAdminRequest": {
+            "type": "object",
+            "required": ["name", "email", "password", "confirm_password"],
+            "properties": {
+                "name": { "type": "string" },
+                "email": { "type": "string" },
+                "password": { "type": "string" },
+                "confirm_password": { "type": "string" },
+                "role": { "type": "string", "enum": ["owner", "admin", "editor", "viewer"] },
+                "permissions": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" },
+                },
+            },
+        },
+        "SettingKeyRequest": {
+            "type": "object",
+            "required": ["key"],
+            "properties": { "key": { "type": "string" } },
+        },
+        "UpdateSettingRequest": {
+            "type": "object",
+            "required": ["key", "value"],
+            "properties": {
+                "key": { "type": "string" },
+                "value": { "type": "string" },
+            },
+        },
+    });
+
+    let mut schemas = match schemas {
+        Value::Object(schemas) => schemas,
+        _ => Map::new(),
+    };
+    if let Value::Object(batch) = batch_schemas() {
+        schemas.extend(batch);
+    }
+    schemas
+}
+
+// Kept apart from `base_schemas`, one `json!` that large hits the macro recursion limit
+fn batch_schemas() -> Value {
+    json!({
+        "BatchRequest": {
+            "type": "object",
+            "required": ["operations"],
+            "properties": {
+                "operations": {
+                    "type": "array",
+                    "description": "Run in order, all of them or none. Ids and top level data values can be {\"$ref\": \"<ref>\"} or {\"$ref\": <index>} to use the id of an earlier operation. The batch_max_operations setting limits the length.",
+                    "items": schema_ref("BatchOperation"),
+                },
+            },
+        },
+        "BatchOperation": {
+            "type": "object",
+            "required": ["op", "collection_id"],
+            "properties": {
+                "op": { "type": "string", "enum": ["create", "update", "delete", "upsert"] },
+                "collection_id": { "type": "string" },
+                "id": {
+                    "description": "Record to update or delete",
+                    "oneOf": [{ "type": "string" }, schema_ref("BatchReference")],
+                },
+                "on": { "type": "string", "description": "Unique field an upsert matches on" },
+                "data": {
+                    "type": "object",
+                    "description": "Values of the record, see the <Collection>Input schemas",
+                    "additionalProperties": true,
+                },
+                "ref": { "type": "string", "description": "Name later operations can refer to" },
+            },
+        },
+        "BatchReference": {
+            "type": "object",
+            "required": ["$ref"],
+            "properties": {
+                "$ref": { "oneOf": [{ "type": "string" }, { "type": "integer", "minimum": 0 }] },
+            },
+        },
+        "BatchResponse": {
+            "type": "object",
+            "required": ["success", "message", "results"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "results": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["collection_id", "id", "status"],
+                        "properties": {
+                            "collection_id": { "type": "string" },
+                            "id": { "type": "string" },
+                            "status": { "type": "string", "enum": ["created", "updated", "deleted"] },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// The whole document, `server` is the URL the API is reached at.
+pub fn document(collections: &[std::sync::Arc<Collection>], server: &str) -> Value {
+    let mut paths = Map::new();
+    static_paths(&mut paths);
+
+    let mut schemas = base_schemas();
+    for collection in collections {
+        collection_schemas(collection, &mut schemas);
+        collection_paths(collection, &mut paths);
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "MooseDB",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "responses": {
+                "Error": {
+                    "description": "Error",
+                    "content": { "application/json": { "schema": schema_ref("Error") } },
+                },
+            },
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+            },
+        },
+    })
+}
+
+#[get("/openapi.json")]
+pub async fn get_openapi(req: HttpRequest, data: web::Data<AppData>) -> HttpResponse {
+    HttpResponse::Ok().json(document(&data.schema.all(), &base_url(&req)))
+}
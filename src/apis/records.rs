@@ -1,122 +1,73 @@
 use crate::AppData;
-use crate::Response;
+use crate::apis::admins::require_write;
+use crate::apis::audit::{AuditContext, snapshot_records};
+use crate::apis::error::ApiError;
+use crate::apis::files::*;
+use crate::db::database::DbError;
+use crate::db::schema::{Collection, Field, Schema};
+use crate::storage::Storage;
+use crate::storage::staged::Staged;
 use crate::utils::random::*;
 
-use actix_web::{HttpResponse, Responder, Result, post, web};
-use serde::{Deserialize, Serialize};
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use moosedb_types::{
+    CreateRecordParams, CreateRecordRequest, CreateRecordResponse, FileUpload, RecordStatus,
+};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Deserialize, Serialize, Debug)]
-struct FileUpload {
-    filename: String,
-    mime_type: String,
-    data: String,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct CreateRecordRequest {
-    collection_id: String,
-    data: serde_json::Map<String, serde_json::Value>,
-}
-
-#[derive(Serialize)]
-struct ValidationErrorResponse {
-    success: bool,
-    message: String,
-    errors: std::collections::HashMap<String, String>,
-}
-
-struct FieldMeta {
-    name: String,
-    field_type: String,
-    nullable: bool,
-    min: Option<i64>,
-    max: Option<i64>,
-    allowed_extensions: Option<String>,
-}
-
-fn slugify(name: &str) -> String {
-    name.chars()
-        .map(|c| {
-            if c.is_alphanumeric() {
-                c.to_ascii_lowercase()
-            } else {
-                '-'
-            }
-        })
-        .collect::<String>()
-        .split('-')
-        .filter(|s| !s.is_empty())
-        .collect::<Vec<_>>()
-        .join("-")
-}
-
-fn save_uploaded_file(upload: &FileUpload) -> Result<String, String> {
-    let uploads_dir = Path::new("uploads");
-    if !uploads_dir.exists() {
-        std::fs::create_dir_all(uploads_dir).map_err(|e| e.to_string())?;
-    }
-
-    let stem = Path::new(&upload.filename)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("file");
-
-    let ext = Path::new(&upload.filename)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("");
-
-    let slug = slugify(stem);
-    let slug = if slug.is_empty() {
-        "file".to_string()
-    } else {
-        slug
+fn prepare_uploads(meta: &Field, value: &serde_json::Value) -> Result<Vec<PreparedUpload>, String> {
+    let items = match value.as_array() {
+        Some(arr) => arr.clone(),
+        None => vec![value.clone()],
     };
 
-    let unique_name = if ext.is_empty() {
-        format!("{}-{}", slug, random_numbers(9))
-    } else {
-        format!("{}-{}.{}", slug, random_numbers(9), ext)
-    };
-
-    let file_path = uploads_dir.join(&unique_name);
-
-    let bytes = base64_decode(&upload.data).map_err(|e| format!("Invalid base64: {}", e))?;
-
-    std::fs::write(&file_path, bytes).map_err(|e| e.to_string())?;
-
-    Ok(file_path.to_string_lossy().to_string())
-}
-
-fn base64_decode(input: &str) -> Result<Vec<u8>, String> {
-    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut lookup = [0u8; 256];
-    for (i, &c) in alphabet.iter().enumerate() {
-        lookup[c as usize] = i as u8;
+    if let Some(max_files) = meta.max_files
+        && items.len() as i64 > max_files
+    {
+        return Err(format!(
+            "'{}' accepts at most {} file{} (got {})",
+            meta.name,
+            max_files,
+            if max_files == 1 { "" } else { "s" },
+            items.len()
+        ));
     }
 
-    let input: Vec<u8> = input.bytes().filter(|&c| c != b'=').collect();
-    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut prepared = Vec::with_capacity(items.len());
+
+    for item in items {
+        let upload: FileUpload = serde_json::from_value(item)
+            .map_err(|err| format!("Invalid file data for '{}': {}", meta.name, err))?;
+
+        let bytes = base64_decode(&upload.data)
+            .map_err(|err| format!("Invalid base64 for '{}': {}", upload.filename, err))?;
+
+        if let Some(max_size) = meta.max_file_size
+            && bytes.len() as i64 > max_size
+        {
+            return Err(format!(
+                "'{}': '{}' is {} bytes, the limit is {} bytes",
+                meta.name,
+                upload.filename,
+                bytes.len(),
+                max_size
+            ));
+        }
 
-    for chunk in input.chunks(4) {
-        let b = chunk
-            .iter()
-            .map(|&c| lookup[c as usize])
-            .collect::<Vec<_>>();
+        let mime_type = detect_mime_type(&upload.filename, &bytes)
+            .map_err(|err| format!("'{}': {}", meta.name, err))?;
 
-        if b.len() >= 2 {
-            output.push((b[0] << 2) | (b[1] >> 4));
-        }
-        if b.len() >= 3 {
-            output.push((b[1] << 4) | (b[2] >> 2));
-        }
-        if b.len() >= 4 {
-            output.push((b[2] << 6) | b[3]);
-        }
+        prepared.push(PreparedUpload {
+            filename: upload.filename,
+            bytes,
+            mime_type,
+        });
     }
 
-    Ok(output)
+    Ok(prepared)
 }
 
 fn json_type_label(v: &serde_json::Value) -> &'static str {
@@ -131,7 +82,7 @@ fn json_type_label(v: &serde_json::Value) -> &'static str {
     }
 }
 
-fn validate_field(meta: &FieldMeta, value: Option<&serde_json::Value>) -> Result<(), String> {
+fn validate_field(meta: &Field, value: Option<&serde_json::Value>) -> Result<(), String> {
     let is_null = value.map(|v| v.is_null()).unwrap_or(true);
 
     if !meta.nullable && is_null {
@@ -241,45 +192,41 @@ fn validate_field(meta: &FieldMeta, value: Option<&serde_json::Value>) -> Result
                 }
             };
 
-            if let Some(min) = meta.min {
-                if n < min as f64 {
-                    return Err(format!(
-                        "'{}' must be at least {} (got {})",
-                        meta.name, min, n
-                    ));
-                }
-            }
-
-            if let Some(max) = meta.max {
-                if n > max as f64 {
-                    return Err(format!(
-                        "'{}' must be at most {} (got {})",
-                        meta.name, max, n
-                    ));
-                }
-            }
-        }
-
-        "BOOLEAN" => {
-            if !value.is_boolean() {
+            if let Some(min) = meta.min
+                && n < min as f64
+            {
                 return Err(format!(
-                    "'{}' must be true or false, got {}",
-                    meta.name,
-                    json_type_label(value)
+                    "'{}' must be at least {} (got {})",
+                    meta.name, min, n
                 ));
             }
-        }
 
-        "DATETIME" | "TIMESTAMP" => {
-            if value.as_str().is_none() {
+            if let Some(max) = meta.max
+                && n > max as f64
+            {
                 return Err(format!(
-                    "'{}' must be a datetime string, got {}",
-                    meta.name,
-                    json_type_label(value)
+                    "'{}' must be at most {} (got {})",
+                    meta.name, max, n
                 ));
             }
         }
 
+        "BOOLEAN" if !value.is_boolean() => {
+            return Err(format!(
+                "'{}' must be true or false, got {}",
+                meta.name,
+                json_type_label(value)
+            ));
+        }
+
+        "DATETIME" | "TIMESTAMP" if value.as_str().is_none() => {
+            return Err(format!(
+                "'{}' must be a datetime string, got {}",
+                meta.name,
+                json_type_label(value)
+            ));
+        }
+
         "FILE" => {
             let uploads = match value.as_array() {
                 Some(arr) => arr.clone(),
@@ -340,182 +287,197 @@ fn validate_field(meta: &FieldMeta, value: Option<&serde_json::Value>) -> Result
     Ok(())
 }
 
+// Result of a record write that runs on the database pool
+pub(crate) enum RecordOutcome {
+    NotFound,
+    RecordNotFound(String),
+    BadRequest(String),
+    Invalid(HashMap<String, String>),
+    Written(String, String, RecordStatus),
+}
+
+impl RecordOutcome {
+    // The table name and record id of a write that went through
+    pub(crate) fn into_result(
+        self,
+        collection_id: &str,
+    ) -> Result<(String, String, RecordStatus), ApiError> {
+        match self {
+            RecordOutcome::Written(table_name, id, status) => Ok((table_name, id, status)),
+            RecordOutcome::NotFound => Err(ApiError::NotFound(format!(
+                "No collection found with id '{}'",
+                collection_id
+            ))),
+            RecordOutcome::RecordNotFound(id) => Err(ApiError::NotFound(format!(
+                "Record '{}' not found in collection '{}'",
+                id, collection_id
+            ))),
+            RecordOutcome::BadRequest(message) => Err(ApiError::BadRequest(message)),
+            RecordOutcome::Invalid(validation_errors) => {
+                Err(ApiError::Validation(validation_errors))
+            }
+        }
+    }
+}
+
 #[post("/create-record")]
 async fn create_record(
+    req: HttpRequest,
+    params: web::Query<CreateRecordParams>,
     request: web::Json<CreateRecordRequest>,
     app_data: web::Data<AppData>,
-) -> Result<impl Responder> {
-    let collection_id = request.collection_id.clone();
-    let mut data = request.data.clone();
-
-    let conn = match app_data.database.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to get database connection: {}", err),
-            }));
+) -> Result<HttpResponse, ApiError> {
+    require_write(&req, &request.collection_id)?;
+    let auditor = AuditContext::from_request(&req);
+    let upsert_on = params.into_inner().upsert_on;
+    let response = create(&app_data, auditor, request.into_inner(), upsert_on).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Validates `request` against its collection and inserts it, or updates the record with the same
+// `upsert_on` value. Shared with the library API.
+pub(crate) async fn create(
+    app_data: &AppData,
+    auditor: AuditContext,
+    request: CreateRecordRequest,
+    upsert_on: Option<String>,
+) -> Result<CreateRecordResponse, ApiError> {
+    let CreateRecordRequest {
+        collection_id,
+        data,
+    } = request;
+    let staged = Arc::new(Staged::new(app_data.storage.clone()));
+    let (schema, storage) = (app_data.schema.clone(), staged.clone());
+    let id = collection_id.clone();
+    let staging = web::block(move || stage_uploads(&schema, storage.as_ref(), &id, data)).await;
+
+    let result = match staging {
+        Ok(Ok(record)) => {
+            let (schema, storage) = (app_data.schema.clone(), staged.clone());
+            let id = collection_id.clone();
+            app_data
+                .database
+                .write(move |conn| {
+                    // Undoes the `_files` rows of the uploads when the record is not written after all
+                    conn.execute_batch("SAVEPOINT record")?;
+                    let outcome = match upsert_on {
+                        Some(on) => upsert_record(
+                            conn,
+                            &schema,
+                            storage.as_ref(),
+                            &auditor,
+                            &id,
+                            record,
+                            &on,
+                        ),
+                        None => insert_record(conn, &schema, &auditor, &id, record),
+                    }?;
+                    match outcome {
+                        RecordOutcome::Written(..) => conn.execute_batch("RELEASE record")?,
+                        _ => conn.execute_batch("ROLLBACK TO record")?,
+                    }
+                    Ok(outcome)
+                })
+                .await
+                .map_err(ApiError::from)
         }
+        Ok(Err(err)) => Err(err),
+        Err(err) => Err(ApiError::Internal(err.to_string())),
     };
 
-    let table_name: Result<String, _> = conn.query_row(
-        "SELECT table_name FROM _database_metadata WHERE table_id = ?1 LIMIT 1",
-        [&collection_id],
-        |row| row.get(0),
-    );
+    // Uploads of a failed write are removed, files replaced by an upsert go once it has committed
+    match &result {
+        Ok(RecordOutcome::Written(..)) => staged.commit(),
+        _ => staged.discard(),
+    }
 
-    let table_name = match table_name {
-        Ok(name) => name,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Ok(HttpResponse::NotFound().json(Response {
-                success: false,
-                message: format!("No collection found with id '{}'", collection_id),
-            }));
-        }
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to query collection: {}", err),
-            }));
-        }
+    let (table_name, id, status) = result?.into_result(&collection_id)?;
+    let action = match status {
+        RecordStatus::Updated => "updated",
+        _ => "created",
     };
+    Ok(CreateRecordResponse {
+        success: true,
+        message: format!("Record {} successfully in '{}'", action, table_name),
+        id,
+        status,
+    })
+}
 
-    let mut stmt = match conn.prepare(
-        "SELECT field_name, field_type, nullable, min, max, allowed_extensions
-         FROM _database_metadata WHERE table_name = ?1 ORDER BY ROWID",
-    ) {
-        Ok(stmt) => stmt,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to prepare metadata query: {}", err),
-            }));
-        }
-    };
+// Values of a record write, with the uploads of its FILE fields already in storage
+#[derive(Default)]
+pub(crate) struct StagedRecord {
+    pub(crate) data: serde_json::Map<String, serde_json::Value>,
+    uploads: HashMap<String, Vec<StoredUpload>>,
+}
 
-    let fields: Result<Vec<FieldMeta>, _> = stmt
-        .query_map([&table_name], |row| {
-            Ok(FieldMeta {
-                name: row.get(0)?,
-                field_type: row.get(1)?,
-                nullable: row.get(2)?,
-                min: row.get(3)?,
-                max: row.get(4)?,
-                allowed_extensions: row.get(5)?,
-            })
-        })
-        .and_then(|mapped_rows| mapped_rows.collect());
-
-    let fields = match fields {
-        Ok(fields) => fields,
-        Err(err) => {
-            return Ok(HttpResponse::InternalServerError().json(Response {
-                success: false,
-                message: format!("Failed to fetch field definitions: {}", err),
-            }));
-        }
+// Checks the FILE fields of `data` and writes their uploads to `storage`. Runs before the writer is
+// taken, so decoding and a slow storage backend don't hold up other writes. The write validates
+// the other fields and records the uploads in `_files`. Data for a collection that does not exist
+// is passed on for the write to report.
+pub(crate) fn stage_uploads(
+    schema: &Schema,
+    storage: &dyn Storage,
+    collection_id: &str,
+    data: serde_json::Map<String, serde_json::Value>,
+) -> Result<StagedRecord, ApiError> {
+    let mut record = StagedRecord {
+        data,
+        uploads: HashMap::new(),
+    };
+    let Some(collection) = schema.get(collection_id) else {
+        return Ok(record);
     };
 
-    let mut validation_errors: std::collections::HashMap<String, String> =
-        std::collections::HashMap::new();
-
-    for meta in &fields {
-        if let Err(msg) = validate_field(meta, data.get(&meta.name)) {
-            validation_errors.insert(meta.name.clone(), msg);
+    let mut validation_errors: HashMap<String, String> = HashMap::new();
+    let mut prepared_uploads: Vec<(String, Vec<PreparedUpload>)> = Vec::new();
+
+    for meta in collection.file_fields() {
+        let Some(value) = record.data.get(&meta.name).filter(|v| !v.is_null()) else {
+            continue;
+        };
+        match validate_field(meta, Some(value)).and_then(|()| prepare_uploads(meta, value)) {
+            Ok(uploads) => prepared_uploads.push((meta.name.clone(), uploads)),
+            Err(msg) => {
+                validation_errors.insert(meta.name.clone(), msg);
+            }
         }
     }
 
     if !validation_errors.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(ValidationErrorResponse {
-            success: false,
-            message: format!(
-                "{} field{} failed validation",
-                validation_errors.len(),
-                if validation_errors.len() == 1 {
-                    ""
-                } else {
-                    "s"
-                }
-            ),
-            errors: validation_errors,
-        }));
+        return Err(ApiError::Validation(validation_errors));
     }
 
-    for meta in &fields {
-        if meta.field_type == "FILE" {
-            if let Some(value) = data.get(&meta.name) {
-                if let Some(arr) = value.as_array() {
-                    let mut saved_paths: Vec<serde_json::Value> = Vec::new();
-
-                    for item in arr {
-                        if item.is_object() {
-                            let upload: FileUpload = match serde_json::from_value(item.clone()) {
-                                Ok(u) => u,
-                                Err(err) => {
-                                    return Ok(HttpResponse::BadRequest().json(Response {
-                                        success: false,
-                                        message: format!(
-                                            "Invalid file data for '{}': {}",
-                                            meta.name, err
-                                        ),
-                                    }));
-                                }
-                            };
-
-                            match save_uploaded_file(&upload) {
-                                Ok(path) => {
-                                    saved_paths.push(serde_json::Value::String(path));
-                                }
-                                Err(err) => {
-                                    return Ok(HttpResponse::InternalServerError().json(
-                                        Response {
-                                            success: false,
-                                            message: format!(
-                                                "Failed to save file for '{}': {}",
-                                                meta.name, err
-                                            ),
-                                        },
-                                    ));
-                                }
-                            }
-                        }
-                    }
+    for (field_name, uploads) in prepared_uploads {
+        let mut stored = Vec::with_capacity(uploads.len());
+        for upload in uploads {
+            let upload = store_upload(storage, upload).map_err(|err| {
+                ApiError::Internal(format!("Failed to save file for '{}': {}", field_name, err))
+            })?;
+            stored.push(upload);
+        }
+        record.uploads.insert(field_name, stored);
+    }
 
-                    data.insert(
-                        meta.name.clone(),
-                        serde_json::Value::String(
-                            serde_json::to_string(&saved_paths).unwrap_or_default(),
-                        ),
-                    );
-                } else if value.is_object() {
-                    let upload: FileUpload = match serde_json::from_value(value.clone()) {
-                        Ok(u) => u,
-                        Err(err) => {
-                            return Ok(HttpResponse::BadRequest().json(Response {
-                                success: false,
-                                message: format!("Invalid file data for '{}': {}", meta.name, err),
-                            }));
-                        }
-                    };
+    Ok(record)
+}
 
-                    match save_uploaded_file(&upload) {
-                        Ok(path) => {
-                            let paths = serde_json::to_string(&vec![path]).unwrap_or_default();
-                            data.insert(meta.name.clone(), serde_json::Value::String(paths));
-                        }
-                        Err(err) => {
-                            return Ok(HttpResponse::InternalServerError().json(Response {
-                                success: false,
-                                message: format!(
-                                    "Failed to save file for '{}': {}",
-                                    meta.name, err
-                                ),
-                            }));
-                        }
-                    }
-                }
-            }
-        }
+pub(crate) fn insert_record(
+    conn: &Connection,
+    schema: &Schema,
+    auditor: &AuditContext,
+    collection_id: &str,
+    mut record: StagedRecord,
+) -> Result<RecordOutcome, DbError> {
+    // Looked up inside the writer's turn, so the collection can't change until the insert is done
+    let Some(collection) = schema.get(collection_id) else {
+        return Ok(RecordOutcome::NotFound);
+    };
+    let table_name = collection.name.clone();
+    let fields = &collection.fields;
+
+    if let Some(failed) = prepare_data(conn, &collection, &mut record, false)? {
+        return Ok(failed);
     }
 
     let generated_id = format!("moo{}", simple_uid(12));
@@ -532,39 +494,266 @@ async fn create_record(
         placeholders.join(", ")
     );
 
-    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(generated_id)];
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(generated_id.clone())];
+    params.extend(
+        fields
+            .iter()
+            .map(|meta| sql_value(meta, record.data.get(&meta.name))),
+    );
 
-    params.extend(fields.iter().map(|meta| {
-        let value = data.get(&meta.name);
+    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    conn.execute(&insert_sql, params_refs.as_slice())?;
+
+    auditor.write(
+        conn,
+        "record.create",
+        collection_id,
+        None,
+        snapshot_records(conn, &table_name, std::slice::from_ref(&generated_id)),
+    );
 
-        match (value, meta.field_type.as_str()) {
-            (Some(v), "INTEGER") if v.is_i64() => {
-                Box::new(v.as_i64().unwrap()) as Box<dyn rusqlite::ToSql>
-            }
-            (Some(v), "BOOLEAN") if v.is_boolean() => {
-                Box::new(if v.as_bool().unwrap() { 1i64 } else { 0i64 }) as Box<dyn rusqlite::ToSql>
-            }
-            (Some(v), "DECIMAL") if v.is_f64() => {
-                Box::new(v.as_f64().unwrap()) as Box<dyn rusqlite::ToSql>
-            }
-            (Some(v), _) if v.is_string() => {
-                Box::new(v.as_str().unwrap().to_string()) as Box<dyn rusqlite::ToSql>
-            }
-            _ => Box::new(rusqlite::types::Null) as Box<dyn rusqlite::ToSql>,
-        }
-    }));
+    Ok(RecordOutcome::Written(
+        table_name,
+        generated_id,
+        RecordStatus::Created,
+    ))
+}
+
+// Changes the fields present in `record` and leaves the others as they are
+pub(crate) fn update_record(
+    conn: &Connection,
+    schema: &Schema,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    collection_id: &str,
+    record_id: &str,
+    record: StagedRecord,
+) -> Result<RecordOutcome, DbError> {
+    let Some(collection) = schema.get(collection_id) else {
+        return Ok(RecordOutcome::NotFound);
+    };
+    update_existing(conn, storage, auditor, &collection, record_id, record, true)
+}
+
+// Updates the record whose unique field `on` has the value in `record`, or creates it
+pub(crate) fn upsert_record(
+    conn: &Connection,
+    schema: &Schema,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    collection_id: &str,
+    record: StagedRecord,
+    on: &str,
+) -> Result<RecordOutcome, DbError> {
+    let Some(collection) = schema.get(collection_id) else {
+        return Ok(RecordOutcome::NotFound);
+    };
+    let Some(meta) = collection.fields.iter().find(|f| f.name == on) else {
+        return Ok(RecordOutcome::BadRequest(format!(
+            "'{}' is not a field of '{}'",
+            on, collection.name
+        )));
+    };
+    if !meta.unique {
+        return Ok(RecordOutcome::BadRequest(format!(
+            "'{}' must be a unique field to upsert on",
+            on
+        )));
+    }
+    let Some(value) = record.data.get(on).filter(|v| !v.is_null()) else {
+        let mut validation_errors = HashMap::new();
+        validation_errors.insert(on.to_string(), format!("'{}' is required to upsert", on));
+        return Ok(RecordOutcome::Invalid(validation_errors));
+    };
+
+    let existing: Option<String> = conn
+        .query_row(
+            &format!(
+                "SELECT id FROM \"{}\" WHERE \"{}\" = ?1",
+                collection.name, on
+            ),
+            [sql_value(meta, Some(value))],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match existing {
+        Some(record_id) => update_existing(
+            conn,
+            storage,
+            auditor,
+            &collection,
+            &record_id,
+            record,
+            false,
+        ),
+        None => insert_record(conn, schema, auditor, collection_id, record),
+    }
+}
+
+// With `partial` only the fields in `record` are validated, otherwise it must be a whole record
+fn update_existing(
+    conn: &Connection,
+    storage: &dyn Storage,
+    auditor: &AuditContext,
+    collection: &Collection,
+    record_id: &str,
+    mut record: StagedRecord,
+    partial: bool,
+) -> Result<RecordOutcome, DbError> {
+    let table_name = collection.name.clone();
+    let fields: Vec<&Field> = collection
+        .fields
+        .iter()
+        .filter(|f| record.data.contains_key(&f.name))
+        .collect();
+    if fields.is_empty() {
+        return Ok(RecordOutcome::BadRequest(format!(
+            "No fields of '{}' to update",
+            table_name
+        )));
+    }
+
+    let exists = conn
+        .query_row(
+            &format!("SELECT 1 FROM \"{}\" WHERE id = ?1", table_name),
+            [record_id],
+            |_| Ok(()),
+        )
+        .optional()?;
+    if exists.is_none() {
+        return Ok(RecordOutcome::RecordNotFound(record_id.to_string()));
+    }
+    let record_ids = [record_id.to_string()];
+    let before = snapshot_records(conn, &table_name, &record_ids);
+
+    // Files that are replaced, read before the uploads overwrite their column in `record`
+    let replaced: Vec<String> = fields
+        .iter()
+        .filter(|f| f.field_type == "FILE")
+        .filter_map(|f| {
+            conn.query_row(
+                &format!(
+                    "SELECT \"{}\" FROM \"{}\" WHERE id = ?1",
+                    f.name, table_name
+                ),
+                [record_id],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .ok()
+            .flatten()
+        })
+        .flat_map(|raw| file_references(&raw))
+        .collect();
+
+    if let Some(failed) = prepare_data(conn, collection, &mut record, partial)? {
+        return Ok(failed);
+    }
+
+    let assignments: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| format!("\"{}\" = ?{}", f.name, i + 1))
+        .collect();
+    let update_sql = format!(
+        "UPDATE \"{}\" SET {}, updated_at = CURRENT_TIMESTAMP WHERE id = ?{}",
+        table_name,
+        assignments.join(", "),
+        fields.len() + 1
+    );
+
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = fields
+        .iter()
+        .map(|meta| sql_value(meta, record.data.get(&meta.name)))
+        .collect();
+    params.push(Box::new(record_id.to_string()));
 
     let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    conn.execute(&update_sql, params_refs.as_slice())?;
 
-    if let Err(err) = conn.execute(&insert_sql, params_refs.as_slice()) {
-        return Ok(HttpResponse::InternalServerError().json(Response {
-            success: false,
-            message: format!("Failed to insert record: {}", err),
-        }));
+    for reference in &replaced {
+        remove_uploaded_file(conn, storage, reference);
     }
 
-    Ok(HttpResponse::Ok().json(Response {
-        success: true,
-        message: format!("Record created successfully in '{}'", table_name),
-    }))
+    auditor.write(
+        conn,
+        "record.update",
+        &collection.id,
+        before,
+        snapshot_records(conn, &table_name, &record_ids),
+    );
+
+    Ok(RecordOutcome::Written(
+        table_name,
+        record_id.to_string(),
+        RecordStatus::Updated,
+    ))
+}
+
+// Validates the data of `record` and records its staged uploads in `_files`, each upload is
+// replaced by the ids of its stored files. Returns the outcome to report when that failed.
+fn prepare_data(
+    conn: &Connection,
+    collection: &Collection,
+    record: &mut StagedRecord,
+    partial: bool,
+) -> Result<Option<RecordOutcome>, DbError> {
+    let mut validation_errors: HashMap<String, String> = HashMap::new();
+
+    for meta in &collection.fields {
+        let value = record.data.get(&meta.name);
+        if partial && value.is_none() {
+            continue;
+        }
+
+        if let Err(msg) = validate_field(meta, value) {
+            validation_errors.insert(meta.name.clone(), msg);
+        }
+    }
+
+    // A field that became a FILE field, or stopped being one, after the uploads were staged
+    for meta in &collection.fields {
+        let has_files =
+            meta.field_type == "FILE" && record.data.get(&meta.name).is_some_and(|v| !v.is_null());
+        if has_files != record.uploads.contains_key(&meta.name) {
+            validation_errors.insert(
+                meta.name.clone(),
+                format!(
+                    "'{}' changed while its files were stored, try again",
+                    meta.name
+                ),
+            );
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return Ok(Some(RecordOutcome::Invalid(validation_errors)));
+    }
+
+    for (field_name, uploads) in &record.uploads {
+        let mut file_ids: Vec<serde_json::Value> = Vec::new();
+        for upload in uploads {
+            let file_id = record_upload(conn, upload, &collection.id, field_name)?;
+            file_ids.push(serde_json::Value::String(file_id));
+        }
+
+        record.data.insert(
+            field_name.clone(),
+            serde_json::Value::String(serde_json::to_string(&file_ids).unwrap_or_default()),
+        );
+    }
+
+    Ok(None)
+}
+
+fn sql_value(meta: &Field, value: Option<&serde_json::Value>) -> Box<dyn rusqlite::ToSql> {
+    match (value, meta.field_type.as_str()) {
+        (Some(v), "INTEGER") if v.is_i64() => Box::new(v.as_i64().unwrap()),
+        (Some(v), "BOOLEAN") if v.is_boolean() => {
+            Box::new(if v.as_bool().unwrap() { 1i64 } else { 0i64 })
+        }
+        (Some(v), "DECIMAL") if v.is_number() => Box::new(v.as_f64().unwrap()),
+        (Some(v), _) if v.is_string() => Box::new(v.as_str().unwrap().to_string()),
+        _ => Box::new(rusqlite::types::Null),
+    }
 }
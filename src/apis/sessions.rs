@@ -0,0 +1,285 @@
+use crate::AppData;
+use crate::Claims;
+use crate::apis::admins::{Role, current_admin};
+use crate::apis::audit::AuditContext;
+use crate::apis::error::ApiError;
+use crate::utils::random::simple_uid;
+
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, post, web};
+use moosedb_types::Response;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn hash_token(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+// Refresh tokens look like `{session_id}.{secret}`, only a hash of the secret is stored
+fn new_refresh_token(session_id: &str) -> (String, String) {
+    let secret = simple_uid(48);
+    (format!("{}.{}", session_id, secret), hash_token(&secret))
+}
+
+pub fn create_session(
+    conn: &Connection,
+    email: &str,
+    user_agent: &str,
+    ip: &str,
+    ttl: i64,
+) -> rusqlite::Result<(String, String)> {
+    let session_id = format!("ses{}", simple_uid(16));
+    let (refresh_token, refresh_hash) = new_refresh_token(&session_id);
+
+    conn.execute(
+        "INSERT INTO _sessions (session_id, email, refresh_token_hash, user_agent, ip, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            session_id,
+            email,
+            refresh_hash,
+            user_agent,
+            ip,
+            unix_now() + ttl
+        ],
+    )?;
+
+    Ok((session_id, refresh_token))
+}
+
+// Swap a refresh token for a new one. Returns the session id, the admin's email and the new token,
+// or `None` if the token is unknown, expired or revoked. Presenting an already rotated token
+// revokes the whole session since it means the token has leaked.
+pub fn rotate_refresh_token(
+    conn: &Connection,
+    refresh_token: &str,
+    ttl: i64,
+) -> rusqlite::Result<Option<(String, String, String)>> {
+    let Some((session_id, secret)) = refresh_token.split_once('.') else {
+        return Ok(None);
+    };
+
+    let session: Option<(String, String, i64)> = conn
+        .query_row(
+            "SELECT email, refresh_token_hash, expires_at FROM _sessions
+             WHERE session_id = ?1 AND revoked_at IS NULL",
+            [session_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    let Some((email, stored_hash, expires_at)) = session else {
+        return Ok(None);
+    };
+
+    if expires_at < unix_now() {
+        return Ok(None);
+    }
+
+    if stored_hash != hash_token(secret) {
+        revoke_session(conn, session_id)?;
+        return Ok(None);
+    }
+
+    let (new_token, new_hash) = new_refresh_token(session_id);
+    conn.execute(
+        "UPDATE _sessions SET refresh_token_hash = ?1, expires_at = ?2, last_used_at = CURRENT_TIMESTAMP
+         WHERE session_id = ?3",
+        params![new_hash, unix_now() + ttl, session_id],
+    )?;
+
+    Ok(Some((session_id.to_string(), email, new_token)))
+}
+
+pub fn session_is_active(conn: &Connection, session_id: &str) -> bool {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM _sessions WHERE session_id = ?1 AND revoked_at IS NULL AND expires_at > ?2)",
+        params![session_id, unix_now()],
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+pub fn revoke_session(conn: &Connection, session_id: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE _sessions SET revoked_at = CURRENT_TIMESTAMP WHERE session_id = ?1 AND revoked_at IS NULL",
+        [session_id],
+    )
+}
+
+// Revoke every session of an admin, optionally keeping the one making the request
+pub fn revoke_admin_sessions(
+    conn: &Connection,
+    email: &str,
+    except: Option<&str>,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE _sessions SET revoked_at = CURRENT_TIMESTAMP
+         WHERE email = ?1 AND revoked_at IS NULL AND session_id != ?2",
+        params![email, except.unwrap_or("")],
+    )
+}
+
+#[derive(Deserialize)]
+struct SessionsQuery {
+    email: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    session_id: String,
+    email: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: String,
+    last_used_at: Option<String>,
+    expires_at: i64,
+    current: bool,
+}
+
+#[derive(Serialize)]
+struct SessionsResponse {
+    success: bool,
+    message: String,
+    sessions: Vec<SessionInfo>,
+}
+
+// Active sessions of every admin, or of a single admin with `?email=`
+#[get("/sessions")]
+pub async fn get_sessions(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    query: web::Query<SessionsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let current = req
+        .extensions()
+        .get::<Claims>()
+        .map(|c| c.jti.clone())
+        .unwrap_or_default();
+
+    // Only admins can look at the sessions of other accounts
+    let email = match current_admin(&req) {
+        Ok(admin) if admin.role >= Role::Admin => query.email.clone(),
+        Ok(admin) => Some(admin.email),
+        Err(err) => return Err(err),
+    };
+
+    let sessions: Result<Vec<SessionInfo>, _> = data
+        .database
+        .run(move |conn| {
+            let sessions = conn
+                .prepare(
+                    "SELECT session_id, email, user_agent, ip, created_at, last_used_at, expires_at FROM _sessions
+                     WHERE revoked_at IS NULL AND expires_at > ?1 AND (?2 IS NULL OR email = ?2)
+                     ORDER BY COALESCE(last_used_at, created_at) DESC",
+                )?
+                .query_map(params![unix_now(), email], |row| {
+                    let session_id: String = row.get(0)?;
+                    Ok(SessionInfo {
+                        current: session_id == current,
+                        session_id,
+                        email: row.get(1)?,
+                        user_agent: row.get(2)?,
+                        ip: row.get(3)?,
+                        created_at: row.get(4)?,
+                        last_used_at: row.get(5)?,
+                        expires_at: row.get(6)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(sessions)
+        })
+        .await;
+
+    match sessions {
+        Ok(sessions) => Ok(HttpResponse::Ok().json(SessionsResponse {
+            success: true,
+            message: format!("{} active sessions", sessions.len()),
+            sessions,
+        })),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeSessionRequest {
+    session_id: Option<String>,
+    email: Option<String>,
+}
+
+// Revoke a single session, or all sessions of an admin
+#[post("/revoke-session")]
+pub async fn revoke_session_func(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<RevokeSessionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let admin = current_admin(&req)?;
+
+    if request.session_id.is_none() && request.email.is_none() {
+        return Err(ApiError::BadRequest(
+            "session_id or email is required".to_string(),
+        ));
+    }
+
+    let auditor = AuditContext::from_request(&req);
+    let revoked = data
+        .database
+        .write(move |conn| {
+            // Editors and viewers can only end their own sessions
+            let owns = |session_id: &str| {
+                conn.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM _sessions WHERE session_id = ?1 AND email = ?2)",
+                    params![session_id, admin.email],
+                    |row| row.get::<_, bool>(0),
+                )
+                .unwrap_or(false)
+            };
+            let allowed = admin.role >= Role::Admin
+                || match (&request.session_id, &request.email) {
+                    (Some(session_id), _) => owns(session_id),
+                    (None, Some(email)) => *email == admin.email,
+                    (None, None) => true,
+                };
+            if !allowed {
+                return Ok(None);
+            }
+
+            let count = match (&request.session_id, &request.email) {
+                (Some(session_id), _) => revoke_session(conn, session_id)?,
+                (None, Some(email)) => revoke_admin_sessions(conn, email, None)?,
+                (None, None) => 0,
+            };
+
+            if count > 0 {
+                let target = request
+                    .session_id
+                    .clone()
+                    .or_else(|| request.email.clone())
+                    .unwrap_or_default();
+                auditor.write(conn, "session.revoke", &target, None, None);
+            }
+            Ok(Some(count))
+        })
+        .await;
+
+    match revoked {
+        Ok(None) => Err(ApiError::Forbidden(
+            "You do not have permission to do this".to_string(),
+        )),
+        Ok(Some(0)) => Err(ApiError::NotFound("No active session found".to_string())),
+        Ok(Some(count)) => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!("{} session(s) revoked", count),
+        })),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -1,8 +1,15 @@
 use crate::AppData;
-use crate::db::connection::update_setting;
+use crate::apis::admins::{Role, require_role};
+use crate::apis::audit::{AuditContext, mask_setting};
+use crate::apis::error::ApiError;
+use crate::config::Config;
+use crate::db::connection::{load_configs, update_setting};
+use crate::settings::{self, SETTINGS, SettingKind};
 
-use actix_web::{Responder, Result, post, web};
+use actix_web::{HttpRequest, HttpResponse, get, post, web};
+use moosedb_types::Response;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 #[derive(Deserialize)]
 struct GetSetting {
@@ -22,66 +29,228 @@ struct UpdateSetting {
     value: String,
 }
 
-#[derive(Serialize)]
-pub struct Response {
-    pub success: bool,
-    pub message: String,
-}
-
 #[post("/get-setting")]
 async fn get_setting(
+    req: HttpRequest,
     data: web::Data<AppData>,
     request: web::Json<GetSetting>,
-) -> Result<impl Responder> {
-    let configs = data.configs.read().unwrap();
-    if &request.key != "secret" {
-        match configs.get(&request.key) {
-            Some(value) => Ok(web::Json(SendSetting {
-                success: true,
-                value: value.clone(),
-                message: "Value has been found".to_string(),
-            })),
-            None => Ok(web::Json(SendSetting {
-                success: false,
-                value: String::new(),
-                message: "Value does not exist! creating new value.".to_string(),
-            })),
-        }
-    } else {
-        Ok(web::Json(SendSetting {
-            success: false,
-            value: String::new(),
-            message: "Action is not allowed!".to_string(),
-        }))
+) -> Result<HttpResponse, ApiError> {
+    let setting = settings::find(&request.key);
+    if !setting.is_some_and(|setting| setting.public) {
+        require_role(&req, Role::Admin)?;
+    }
+
+    if setting.is_some_and(|setting| setting.hidden()) {
+        return Err(ApiError::Forbidden("Action is not allowed".to_string()));
+    }
+
+    match data.configs.read().unwrap().get(&request.key) {
+        Some(value) => Ok(HttpResponse::Ok().json(SendSetting {
+            success: true,
+            value: value.clone(),
+            message: "Value has been found".to_string(),
+        })),
+        None => Err(ApiError::NotFound(format!(
+            "Setting '{}' does not exist",
+            request.key
+        ))),
     }
 }
 
 #[post("/update-setting")]
 async fn update_setting_func(
+    req: HttpRequest,
     data: web::Data<AppData>,
     request: web::Json<UpdateSetting>,
-) -> Result<impl Responder> {
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
     let key = request.key.to_string();
-    if key != "secret".to_string() {
-        match update_setting(key.clone(), request.value.to_string()) {
-            Ok(_) => {
-                let mut configs = data.configs.write().unwrap();
-                configs.insert(key.clone(), request.value.to_string());
-
-                Ok(web::Json(Response {
-                    success: true,
-                    message: "Setting updated successfully".to_string(),
-                }))
+
+    let setting = match settings::find(&key) {
+        Some(setting) => setting,
+        None => {
+            return Err(ApiError::BadRequest(format!("Unknown setting '{}'", key)));
+        }
+    };
+    if matches!(setting.kind, SettingKind::Secret) {
+        return Err(ApiError::Forbidden("Action is not allowed".to_string()));
+    }
+    let value = setting
+        .validate(&request.value)
+        .map_err(ApiError::BadRequest)?;
+
+    let before = data.configs.read().unwrap().get(&key).cloned();
+    let auditor = AuditContext::from_request(&req);
+    let (target, stored) = (key.clone(), value.clone());
+    let updated = data
+        .database
+        .write(move |conn| {
+            update_setting(conn, &target, &stored)?;
+            auditor.write(
+                conn,
+                "setting.update",
+                &target,
+                before.map(|before| json!({ "value": mask_setting(&target, &before) })),
+                Some(json!({ "value": mask_setting(&target, &stored) })),
+            );
+            Ok(())
+        })
+        .await;
+
+    updated?;
+
+    // The stored value only takes effect once the config no longer overrides it
+    let overridden = data
+        .config
+        .overridden_settings()
+        .into_iter()
+        .any(|(overridden, _)| overridden == key);
+    if !overridden {
+        data.configs.write().unwrap().insert(key, value);
+    }
+
+    Ok(HttpResponse::Ok().json(Response {
+        success: true,
+        message: if overridden {
+            "Setting saved, the server config overrides it while the server runs".to_string()
+        } else if setting.restart {
+            "Setting updated, restart the server to apply it".to_string()
+        } else {
+            "Setting updated successfully".to_string()
+        },
+    }))
+}
+
+#[derive(Serialize)]
+struct SettingInfo {
+    key: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    // Current value, left out for sensitive settings
+    value: Option<String>,
+    default: Option<&'static str>,
+    min: Option<i64>,
+    max: Option<i64>,
+    options: Option<&'static [&'static str]>,
+    description: &'static str,
+    restart: bool,
+    sensitive: bool,
+    is_set: bool,
+    // Pinned by the config file or environment, `value` is the one in effect
+    overridden: bool,
+}
+
+#[derive(Serialize)]
+struct SettingsResponse {
+    success: bool,
+    message: String,
+    settings: Vec<SettingInfo>,
+}
+
+// Every registered setting with its schema and current value
+#[get("/settings")]
+async fn get_settings(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let overridden = data.config.overridden_settings();
+    let configs = data.configs.read().unwrap();
+    let settings: Vec<SettingInfo> = SETTINGS
+        .iter()
+        .filter(|setting| !matches!(setting.kind, SettingKind::Secret))
+        .map(|setting| {
+            let value = configs.get(setting.key);
+            let (min, max) = match setting.kind {
+                SettingKind::Integer { min, max } => (Some(min), Some(max)),
+                _ => (None, None),
+            };
+            SettingInfo {
+                key: setting.key,
+                kind: setting.type_name(),
+                value: value.filter(|_| !setting.hidden()).cloned(),
+                default: Some(setting.default).filter(|_| !setting.hidden()),
+                min,
+                max,
+                options: match setting.kind {
+                    SettingKind::Choice(options) => Some(options),
+                    _ => None,
+                },
+                description: setting.description,
+                restart: setting.restart,
+                sensitive: setting.sensitive,
+                is_set: value.is_some_and(|value| !value.is_empty()),
+                overridden: overridden.iter().any(|(key, _)| *key == setting.key),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(SettingsResponse {
+        success: true,
+        message: format!("{} settings", settings.len()),
+        settings,
+    }))
+}
+
+// Load `_configs` again after it was changed outside of the API, settings that need a restart
+// keep their value until then
+#[post("/settings/reload")]
+async fn reload_settings(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    let loaded = data.database.run(|conn| Ok(load_configs(conn)?)).await?;
+
+    let mut invalid = Vec::new();
+    let mut configs = data.configs.write().unwrap();
+    for (key, value) in loaded {
+        match settings::find(&key) {
+            Some(setting) if setting.restart => continue,
+            Some(setting) if setting.validate(&value).is_err() => invalid.push(key),
+            _ => {
+                configs.insert(key, value);
             }
-            Err(err) => Ok(web::Json(Response {
-                success: false,
-                message: err.to_string(),
-            })),
         }
+    }
+    for (key, value) in data.config.overridden_settings() {
+        configs.insert(key.to_string(), value);
+    }
+
+    if invalid.is_empty() {
+        Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: "Settings have been reloaded".to_string(),
+        }))
     } else {
-        Ok(web::Json(Response {
-            success: false,
-            message: "Action is not allowed".to_string(),
+        invalid.sort();
+        Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: format!(
+                "Settings have been reloaded, invalid values were skipped: {}",
+                invalid.join(", ")
+            ),
         }))
     }
 }
+
+#[derive(Serialize)]
+struct ConfigResponse<'a> {
+    success: bool,
+    message: String,
+    config: &'a Config,
+}
+
+// Startup configuration the server is running with, same as `moosedb config show`
+#[get("/config")]
+async fn get_config(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    require_role(&req, Role::Admin)?;
+
+    Ok(HttpResponse::Ok().json(ConfigResponse {
+        success: true,
+        message: "Effective configuration".to_string(),
+        config: &data.config,
+    }))
+}
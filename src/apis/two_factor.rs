@@ -0,0 +1,495 @@
+use crate::AppData;
+use crate::Claims;
+use crate::apis::audit::AuditContext;
+use crate::apis::auth::start_session;
+use crate::apis::error::ApiError;
+use crate::apis::login_attempts::{
+    LoginPolicy, client_info, lockout_remaining, record_login_attempt,
+};
+use crate::utils::random::simple_uid;
+use crate::utils::totp;
+
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, post, web};
+use bcrypt::verify;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use moosedb_types::{ChallengeResponse, Response, TwoFactorLoginRequest};
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const CHALLENGE_TTL: usize = 300;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// Issued after a correct password when the admin has two-factor enabled. It can only be
+// exchanged at `/auth/login/2fa`, never used as an access token.
+#[derive(Serialize, Deserialize)]
+struct ChallengeClaims {
+    sub: String,
+    exp: usize,
+    purpose: String,
+}
+
+pub fn two_factor_challenge(email: &str, secret: &str) -> Result<HttpResponse, ApiError> {
+    let claims = ChallengeClaims {
+        sub: email.to_string(),
+        exp: crate::apis::sessions::unix_now() as usize + CHALLENGE_TTL,
+        purpose: "2fa".to_string(),
+    };
+
+    match encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_ref()),
+    ) {
+        Ok(challenge_token) => Ok(HttpResponse::Ok().json(ChallengeResponse {
+            success: true,
+            message: "Enter the code from your authenticator app".to_string(),
+            two_factor_required: true,
+            challenge_token,
+        })),
+        Err(err) => Err(ApiError::Internal(format!(
+            "Failed to create token: {}",
+            err
+        ))),
+    }
+}
+
+fn verify_challenge(token: &str, secret: &str) -> Option<String> {
+    decode::<ChallengeClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+    .filter(|claims| claims.purpose == "2fa")
+    .map(|claims| claims.sub)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+// Returns the codes to show the admin once, and the hashes to store
+fn generate_recovery_codes() -> (Vec<String>, String) {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = simple_uid(10).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    (codes, serde_json::to_string(&hashes).unwrap_or_default())
+}
+
+// Check an authenticator or recovery code for an admin with two-factor enabled. A matching
+// TOTP step is remembered so the code can't be replayed, a recovery code is used up.
+fn check_code(conn: &Connection, email: &str, code: &str) -> rusqlite::Result<bool> {
+    let row: Option<(Option<String>, Option<i64>, Option<String>)> = conn
+        .query_row(
+            "SELECT totp_secret, totp_last_step, recovery_codes FROM _super_admins
+             WHERE email = ?1 AND totp_enabled = 1",
+            [email],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    let Some((Some(secret), last_step, recovery_codes)) = row else {
+        return Ok(false);
+    };
+
+    if let Some(step) = totp::verify(&secret, code, last_step) {
+        let updated = conn.execute(
+            "UPDATE _super_admins SET totp_last_step = ?1
+             WHERE email = ?2 AND (totp_last_step IS NULL OR totp_last_step < ?1)",
+            params![step, email],
+        )?;
+        return Ok(updated == 1);
+    }
+
+    let mut hashes: Vec<String> = recovery_codes
+        .and_then(|codes| serde_json::from_str(&codes).ok())
+        .unwrap_or_default();
+    let code_hash = hash_recovery_code(code);
+    let Some(index) = hashes.iter().position(|h| *h == code_hash) else {
+        return Ok(false);
+    };
+    hashes.remove(index);
+
+    conn.execute(
+        "UPDATE _super_admins SET recovery_codes = ?1 WHERE email = ?2",
+        params![serde_json::to_string(&hashes).unwrap_or_default(), email],
+    )?;
+    Ok(true)
+}
+
+// Second step of the login for admins with two-factor enabled
+pub async fn login_two_factor(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<TwoFactorLoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(email) = verify_challenge(&request.challenge_token, &data.jwt_secret) else {
+        return Err(ApiError::Unauthorized(
+            "Login has expired, please sign in again".to_string(),
+        ));
+    };
+
+    let (user_agent, ip) = client_info(&req);
+    let policy = LoginPolicy::from_configs(&data.configs.read().unwrap());
+    let owner = email.clone();
+    let checked = data
+        .database
+        .write(move |conn| {
+            let retry_after = lockout_remaining(conn, &policy, &owner, &ip)?;
+            if retry_after > 0 {
+                return Ok(Err(retry_after));
+            }
+            let valid = check_code(conn, &owner, &request.code)?;
+            let _ = record_login_attempt(conn, &owner, &ip, &user_agent, valid);
+            Ok(Ok(valid))
+        })
+        .await;
+
+    match checked {
+        Ok(Err(retry_after)) => Err(ApiError::Locked(retry_after)),
+        Ok(Ok(true)) => start_session(&req, &data, &email).await,
+        Ok(Ok(false)) => Err(ApiError::Unauthorized(
+            "Invalid authentication code".to_string(),
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn current_email(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<Claims>().map(|c| c.email.clone())
+}
+
+#[derive(Serialize)]
+struct TwoFactorStatus {
+    success: bool,
+    message: String,
+    enabled: bool,
+    recovery_codes_left: usize,
+}
+
+#[get("/2fa")]
+pub async fn two_factor_status(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(email) = current_email(&req) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let status: Result<(bool, Option<String>), _> = data
+        .database
+        .run(move |conn| {
+            Ok(conn.query_row(
+                "SELECT totp_enabled, recovery_codes FROM _super_admins WHERE email = ?1",
+                [&email],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?)
+        })
+        .await;
+
+    match status {
+        Ok((enabled, recovery_codes)) => {
+            let recovery_codes_left = recovery_codes
+                .and_then(|codes| serde_json::from_str::<Vec<String>>(&codes).ok())
+                .map(|codes| codes.len())
+                .unwrap_or(0);
+            Ok(HttpResponse::Ok().json(TwoFactorStatus {
+                success: true,
+                message: if enabled {
+                    "Two-factor authentication is enabled".to_string()
+                } else {
+                    "Two-factor authentication is disabled".to_string()
+                },
+                enabled,
+                recovery_codes_left,
+            }))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Serialize)]
+struct SetupResponse {
+    success: bool,
+    message: String,
+    secret: String,
+    uri: String,
+    qr_svg: String,
+}
+
+// Start enrollment with a new secret. It only takes effect once confirmed at `/2fa/enable`.
+#[post("/2fa/setup")]
+pub async fn two_factor_setup(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(email) = current_email(&req) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let secret = totp::generate_secret();
+    let (owner, stored) = (email.clone(), secret.clone());
+    let updated = data
+        .database
+        .write(move |conn| {
+            Ok(conn.execute(
+                "UPDATE _super_admins SET totp_secret = ?1, totp_last_step = NULL
+                 WHERE email = ?2 AND totp_enabled = 0",
+                params![stored, owner],
+            )?)
+        })
+        .await;
+
+    match updated {
+        Ok(1) => {}
+        Ok(_) => {
+            return Err(ApiError::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+        Err(err) => {
+            return Err(err.into());
+        }
+    }
+
+    let issuer = data
+        .configs
+        .read()
+        .unwrap()
+        .get("appname")
+        .cloned()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "MooseDB".to_string());
+    let uri = totp::provisioning_uri(&issuer, &email, &secret);
+    let qr_svg = QrCode::new(uri.as_bytes())
+        .map(|code| code.render::<svg::Color>().min_dimensions(200, 200).build())
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(SetupResponse {
+        success: true,
+        message: "Scan the QR code with your authenticator app".to_string(),
+        secret,
+        uri,
+        qr_svg,
+    }))
+}
+
+#[derive(Deserialize)]
+struct EnableRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct EnableResponse {
+    success: bool,
+    message: String,
+    recovery_codes: Vec<String>,
+}
+
+#[post("/2fa/enable")]
+pub async fn two_factor_enable(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<EnableRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(email) = current_email(&req) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let auditor = AuditContext::from_request(&req);
+    let enabled = data
+        .database
+        .write(move |conn| {
+            let secret: Option<String> = conn
+                .query_row(
+                    "SELECT totp_secret FROM _super_admins WHERE email = ?1 AND totp_enabled = 0",
+                    [&email],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .flatten();
+
+            let Some(secret) = secret else {
+                return Ok(Err("Start the two-factor setup first"));
+            };
+
+            let Some(step) = totp::verify(&secret, &request.code, None) else {
+                return Ok(Err("Invalid authentication code"));
+            };
+
+            let (recovery_codes, hashes) = generate_recovery_codes();
+            conn.execute(
+                "UPDATE _super_admins SET totp_enabled = 1, totp_last_step = ?1, recovery_codes = ?2,
+                 updated_at = CURRENT_TIMESTAMP WHERE email = ?3",
+                params![step, hashes, email],
+            )?;
+            auditor.write(conn, "admin.2fa_enable", &email, None, None);
+            Ok(Ok(recovery_codes))
+        })
+        .await;
+
+    match enabled {
+        Ok(Ok(recovery_codes)) => Ok(HttpResponse::Ok().json(EnableResponse {
+            success: true,
+            message: "Two-factor authentication has been enabled. Store the recovery codes somewhere safe, they are only shown once".to_string(),
+            recovery_codes,
+        })),
+        Ok(Err(message)) => Err(ApiError::BadRequest(message.to_string())),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct DisableRequest {
+    password: String,
+    code: String,
+}
+
+// Result of the part of `two_factor_disable` that runs as the writer
+enum DisableOutcome {
+    InvalidCode,
+    Disabled,
+}
+
+#[post("/2fa/disable")]
+pub async fn two_factor_disable(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    request: web::Json<DisableRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(email) = current_email(&req) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+    let DisableRequest { password, code } = request.into_inner();
+
+    let lookup = email.clone();
+    let hashed = data
+        .database
+        .run(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT password FROM _super_admins WHERE email = ?1",
+                    [&lookup],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?)
+        })
+        .await?;
+
+    // Checked before taking a turn as the writer, bcrypt is the slow part
+    let password_ok = match hashed {
+        Some(hashed) => web::block(move || verify(&password, &hashed).unwrap_or(false))
+            .await
+            .map_err(|err| ApiError::Internal(err.to_string()))?,
+        None => false,
+    };
+    if !password_ok {
+        return Err(ApiError::Unauthorized("Password is incorrect".to_string()));
+    }
+
+    let auditor = AuditContext::from_request(&req);
+    let disabled = data
+        .database
+        .write(move |conn| {
+            if !check_code(conn, &email, &code)? {
+                return Ok(DisableOutcome::InvalidCode);
+            }
+
+            conn.execute(
+                "UPDATE _super_admins SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL,
+                 recovery_codes = NULL, updated_at = CURRENT_TIMESTAMP WHERE email = ?1",
+                [&email],
+            )?;
+            auditor.write(conn, "admin.2fa_disable", &email, None, None);
+            Ok(DisableOutcome::Disabled)
+        })
+        .await?;
+
+    match disabled {
+        DisableOutcome::Disabled => Ok(HttpResponse::Ok().json(Response {
+            success: true,
+            message: "Two-factor authentication has been disabled".to_string(),
+        })),
+        DisableOutcome::InvalidCode => Err(ApiError::BadRequest(
+            "Invalid authentication code".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "ada@example.com";
+
+    // Admin with two-factor enabled, only the columns `check_code` reads
+    fn admin(secret: &str, recovery_codes: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE _super_admins (
+                email TEXT NOT NULL,
+                totp_secret TEXT,
+                totp_enabled BOOLEAN NOT NULL DEFAULT 0,
+                totp_last_step INTEGER,
+                recovery_codes TEXT
+            )",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO _super_admins (email, totp_secret, totp_enabled, recovery_codes)
+             VALUES (?1, ?2, 1, ?3)",
+            params![EMAIL, secret, recovery_codes],
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn totp_code_is_accepted_once() {
+        let secret = totp::generate_secret();
+        let conn = admin(&secret, "[]");
+        let code = totp::current_code(&secret);
+
+        assert!(check_code(&conn, EMAIL, &code).unwrap());
+        assert!(!check_code(&conn, EMAIL, &code).unwrap());
+    }
+
+    #[test]
+    fn recovery_codes_are_used_up() {
+        let (codes, hashes) = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let conn = admin(&totp::generate_secret(), &hashes);
+
+        assert!(check_code(&conn, EMAIL, &codes[0]).unwrap());
+        assert!(!check_code(&conn, EMAIL, &codes[0]).unwrap());
+
+        // Case and dashes don't matter when typing one in
+        let typed = codes[1].replace('-', "").to_uppercase();
+        assert!(check_code(&conn, EMAIL, &typed).unwrap());
+        assert!(!check_code(&conn, EMAIL, &codes[1]).unwrap());
+
+        let left: String = conn
+            .query_row("SELECT recovery_codes FROM _super_admins", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let left: Vec<String> = serde_json::from_str(&left).unwrap();
+        assert_eq!(left.len(), RECOVERY_CODE_COUNT - 2);
+
+        assert!(!check_code(&conn, EMAIL, "aaaaa-bbbbb").unwrap());
+        assert!(!check_code(&conn, "grace@example.com", &codes[2]).unwrap());
+    }
+}
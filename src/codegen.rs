@@ -0,0 +1,545 @@
+use crate::apis::openapi::type_name;
+use crate::db::schema::{Collection, Field};
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Source files for `moosedb gen types`, typed records and a client for `/api/records`
+
+const TS_HEADER: &str = r#"// Generated by `moosedb gen types`, do not edit.
+// Run the command again after changing a collection.
+
+export interface StoredFile {
+  id: string;
+  stored_name: string;
+  original_name: string;
+  size: number;
+  mime_type: string;
+  sha256: string | null;
+  uploaded_at: string | null;
+  url: string;
+  protected: boolean;
+}
+
+export interface Pagination {
+  current_page: number;
+  items_per_page: number;
+  total_records: number;
+  total_pages: number;
+  records_shown: number;
+  has_next_page: boolean;
+  has_prev_page: boolean;
+  next_page: string | null;
+  prev_page: string | null;
+}
+
+export interface Page<T> {
+  success: boolean;
+  message: string;
+  records: T[];
+  pagination: Pagination;
+}
+
+export interface Single<T> {
+  success: boolean;
+  message: string;
+  record: T;
+}
+
+export interface PageParams {
+  page?: number;
+  items?: number;
+}
+
+export class MooseDbError extends Error {
+  constructor(
+    public status: number,
+    public code: string,
+    message: string,
+    public details?: Record<string, unknown>,
+  ) {
+    super(message);
+  }
+}
+
+async function request<T>(fetcher: typeof fetch, url: string): Promise<T> {
+  const response = await fetcher(url);
+  const body = await response.json();
+  if (!response.ok) {
+    throw new MooseDbError(response.status, body.code ?? "unknown", body.message ?? response.statusText, body.details);
+  }
+  return body as T;
+}
+"#;
+
+const TS_CLIENT: &str = r#"
+export function createClient(baseUrl: string, fetcher: typeof fetch = fetch) {
+  const base = baseUrl.replace(/\/+$/, "");
+
+  async function list<T>(id: string, decode: (record: any) => T, params: PageParams = {}): Promise<Page<T>> {
+    const query = new URLSearchParams();
+    if (params.page !== undefined) query.set("page", String(params.page));
+    if (params.items !== undefined) query.set("items", String(params.items));
+    const suffix = query.toString() ? `?${query}` : "";
+    const page = await request<Page<any>>(fetcher, `${base}/api/records/${id}${suffix}`);
+    return { ...page, records: page.records.map(decode) };
+  }
+
+  async function get<T>(id: string, decode: (record: any) => T, recordId: string): Promise<Single<T>> {
+    const single = await request<Single<any>>(fetcher, `${base}/api/records/${id}/${encodeURIComponent(recordId)}`);
+    return { ...single, record: decode(single.record) };
+  }
+
+  return {
+"#;
+
+const RUST_HEADER: &str = r#"//! Generated by `moosedb gen types`, do not edit.
+//! Run the command again after changing a collection. Needs `serde` with the `derive` feature,
+//! `serde_json` and `reqwest` with the `json` feature.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub id: String,
+    pub stored_name: String,
+    pub original_name: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub sha256: Option<String>,
+    pub uploaded_at: Option<String>,
+    pub url: String,
+    pub protected: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pagination {
+    pub current_page: u32,
+    pub items_per_page: u32,
+    pub total_records: i64,
+    pub total_pages: u32,
+    pub records_shown: usize,
+    pub has_next_page: bool,
+    pub has_prev_page: bool,
+    pub next_page: Option<String>,
+    pub prev_page: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub success: bool,
+    pub message: String,
+    pub records: Vec<T>,
+    pub pagination: Pagination,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Single<T> {
+    pub success: bool,
+    pub message: String,
+    pub record: T,
+}
+
+/// Body of every error response
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    Api { status: u16, error: ApiError },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(err) => write!(f, "{}", err),
+            Error::Api { status, error } => write!(f, "{} ({}): {}", status, error.code, error.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+"#;
+
+const RUST_BOOL: &str = r#"
+// BOOLEAN fields are stored as 0 or 1
+fn int_bool<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(i64::deserialize(deserializer)? != 0)
+}
+
+fn int_bool_opt<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    Ok(Option::<i64>::deserialize(deserializer)?.map(|value| value != 0))
+}
+"#;
+
+const RUST_CLIENT: &str = r#"
+/// Client for the public `/api/records` endpoints
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Client::with_http(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_http(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Client {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, path: &str, query: &[(&str, u32)]) -> Result<T, Error> {
+        let response = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let error = response.json::<ApiError>().await?;
+            return Err(Error::Api { status: status.as_u16(), error });
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn list<T: DeserializeOwned>(&self, id: &str, page: u32, items: Option<u32>) -> Result<Page<T>, Error> {
+        let mut query = vec![("page", page)];
+        query.extend(items.map(|items| ("items", items)));
+        self.fetch(&format!("/api/records/{}", id), &query).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, id: &str, record_id: &str) -> Result<Single<T>, Error> {
+        self.fetch(&format!("/api/records/{}/{}", id, encode(record_id)), &[]).await
+    }
+"#;
+
+const RUST_FOOTER: &str = r#"}
+
+// Percent-encode a path segment
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+"#;
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+    "abstract", "become", "box", "do", "final", "gen", "macro", "override", "priv", "try",
+    "typeof", "unsized", "virtual", "yield",
+];
+
+// `self`, `super`, `crate` and `Self` can't be raw identifiers
+const RUST_RESERVED: &[&str] = &["self", "super", "crate", "Self"];
+
+/// `BlogPosts` becomes `blog_posts`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+fn camel_case(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+// Field names are column names, so they can hold anything. Turns one into a Rust identifier.
+fn rust_ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert_str(0, "field_");
+    }
+    if RUST_RESERVED.contains(&ident.as_str()) {
+        ident.push('_');
+    } else if RUST_KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    ident
+}
+
+fn ts_key(name: &str) -> String {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if valid {
+        name.to_string()
+    } else {
+        serde_json::to_string(name).unwrap_or_default()
+    }
+}
+
+fn ts_type(field: &Field) -> String {
+    let base = match field.field_type.as_str() {
+        "INTEGER" | "DECIMAL" => "number",
+        "BOOLEAN" => "boolean",
+        "FILE" => "StoredFile[]",
+        _ => "string",
+    };
+    if field.nullable {
+        format!("{} | null", base)
+    } else {
+        base.to_string()
+    }
+}
+
+fn rust_type(field: &Field) -> String {
+    let base = match field.field_type.as_str() {
+        "INTEGER" => "i64",
+        "DECIMAL" => "f64",
+        "BOOLEAN" => "bool",
+        "FILE" => "Vec<StoredFile>",
+        _ => "String",
+    };
+    if field.nullable {
+        format!("Option<{}>", base)
+    } else {
+        base.to_string()
+    }
+}
+
+fn typescript_record(out: &mut String, collection: &Collection) {
+    let name = type_name(&collection.name);
+
+    let _ = writeln!(
+        out,
+        "\n/** Record of the '{}' collection */",
+        collection.name
+    );
+    let _ = writeln!(out, "export interface {} {{", name);
+    let _ = writeln!(out, "  id: string;");
+    for field in &collection.fields {
+        let _ = writeln!(out, "  {}: {};", ts_key(&field.name), ts_type(field));
+    }
+    let _ = writeln!(out, "  created_at: string;");
+    let _ = writeln!(out, "  updated_at: string;");
+    let _ = writeln!(out, "}}");
+
+    // BOOLEAN fields are stored as 0 or 1
+    let booleans: Vec<&Field> = collection
+        .fields
+        .iter()
+        .filter(|field| field.field_type == "BOOLEAN")
+        .collect();
+    let _ = writeln!(out, "\nfunction decode{}(record: any): {} {{", name, name);
+    if booleans.is_empty() {
+        let _ = writeln!(out, "  return record;");
+    } else {
+        let _ = writeln!(out, "  return {{");
+        let _ = writeln!(out, "    ...record,");
+        for field in booleans {
+            let key = serde_json::to_string(&field.name).unwrap_or_default();
+            if field.nullable {
+                let _ = writeln!(
+                    out,
+                    "    {}: record[{}] === null ? null : Boolean(record[{}]),",
+                    ts_key(&field.name),
+                    key,
+                    key
+                );
+            } else {
+                let _ = writeln!(
+                    out,
+                    "    {}: Boolean(record[{}]),",
+                    ts_key(&field.name),
+                    key
+                );
+            }
+        }
+        let _ = writeln!(out, "  }};");
+    }
+    let _ = writeln!(out, "}}");
+}
+
+/// Collections whose names only differ in punctuation would get the same type.
+pub fn check_names(collections: &[Arc<Collection>]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    for collection in collections {
+        if !names.insert(type_name(&collection.name)) {
+            return Err(format!(
+                "More than one collection is named like '{}'",
+                collection.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn typescript(collections: &[Arc<Collection>]) -> String {
+    let mut out = TS_HEADER.to_string();
+
+    for collection in collections {
+        typescript_record(&mut out, collection);
+    }
+
+    let _ = writeln!(out, "\nexport const collectionIds = {{");
+    for collection in collections {
+        let _ = writeln!(
+            out,
+            "  {}: \"{}\",",
+            type_name(&collection.name),
+            collection.id
+        );
+    }
+    let _ = writeln!(out, "}} as const;");
+
+    out.push_str(TS_CLIENT);
+    for collection in collections {
+        let name = type_name(&collection.name);
+        let _ = writeln!(out, "    {}: {{", camel_case(&name));
+        let _ = writeln!(
+            out,
+            "      list: (params?: PageParams) => list(collectionIds.{}, decode{}, params),",
+            name, name
+        );
+        let _ = writeln!(
+            out,
+            "      get: (recordId: string) => get(collectionIds.{}, decode{}, recordId),",
+            name, name
+        );
+        let _ = writeln!(out, "    }},");
+    }
+    out.push_str("  };\n}\n");
+    out
+}
+
+fn rust_record(out: &mut String, collection: &Collection) {
+    let name = type_name(&collection.name);
+
+    let _ = writeln!(out, "\n/// Record of the `{}` collection", collection.name);
+    let _ = writeln!(out, "#[derive(Debug, Clone, Serialize, Deserialize)]");
+    let _ = writeln!(out, "pub struct {} {{", name);
+    let _ = writeln!(out, "    pub id: String,");
+
+    let mut used: HashSet<String> = ["id", "created_at", "updated_at"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    for field in &collection.fields {
+        let mut ident = rust_ident(&field.name);
+        while !used.insert(ident.clone()) {
+            ident.push('_');
+        }
+
+        if ident.trim_start_matches("r#") != field.name {
+            let _ = writeln!(out, "    #[serde(rename = {:?})]", field.name);
+        }
+        match (field.field_type.as_str(), field.nullable) {
+            ("BOOLEAN", false) => {
+                let _ = writeln!(out, "    #[serde(deserialize_with = \"int_bool\")]");
+            }
+            ("BOOLEAN", true) => {
+                let _ = writeln!(out, "    #[serde(deserialize_with = \"int_bool_opt\")]");
+            }
+            _ => {}
+        }
+        let _ = writeln!(out, "    pub {}: {},", ident, rust_type(field));
+    }
+
+    let _ = writeln!(out, "    pub created_at: String,");
+    let _ = writeln!(out, "    pub updated_at: String,");
+    let _ = writeln!(out, "}}");
+
+    let _ = writeln!(out, "\nimpl {} {{", name);
+    let _ = writeln!(
+        out,
+        "    pub const COLLECTION_ID: &'static str = \"{}\";",
+        collection.id
+    );
+    let _ = writeln!(out, "}}");
+}
+
+pub fn rust(collections: &[Arc<Collection>]) -> String {
+    let mut out = RUST_HEADER.to_string();
+
+    let has_booleans = collections
+        .iter()
+        .flat_map(|collection| &collection.fields)
+        .any(|field| field.field_type == "BOOLEAN");
+    if has_booleans {
+        out.push_str(RUST_BOOL);
+    }
+
+    for collection in collections {
+        rust_record(&mut out, collection);
+    }
+
+    out.push_str(RUST_CLIENT);
+    for collection in collections {
+        let name = type_name(&collection.name);
+        let function = snake_case(&name);
+        let _ = writeln!(
+            out,
+            "\n    /// A page of `{}` records, `items` defaults to the records_per_page setting",
+            collection.name
+        );
+        let _ = writeln!(
+            out,
+            "    pub async fn list_{}(&self, page: u32, items: Option<u32>) -> Result<Page<{}>, Error> {{",
+            function, name
+        );
+        let _ = writeln!(
+            out,
+            "        self.list({}::COLLECTION_ID, page, items).await",
+            name
+        );
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(
+            out,
+            "\n    pub async fn get_{}(&self, record_id: &str) -> Result<Single<{}>, Error> {{",
+            function, name
+        );
+        let _ = writeln!(
+            out,
+            "        self.get({}::COLLECTION_ID, record_id).await",
+            name
+        );
+        let _ = writeln!(out, "    }}");
+    }
+    out.push_str(RUST_FOOTER);
+    out
+}
@@ -0,0 +1,301 @@
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file that is picked up from the working directory when `--config` is not
+/// given.
+pub const DEFAULT_CONFIG_FILE: &str = "moosedb.toml";
+
+/// Startup configuration shared by the server and the CLI subcommands. Values are resolved from
+/// the defaults, then the config file, then `MOOSEDB_*` environment variables, then command line
+/// flags.
+#[derive(Clone, Debug, Serialize)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub database: PathBuf,
+    pub uploads_dir: PathBuf,
+    pub host: String,
+    pub port: u16,
+    // Largest accepted JSON body in bytes
+    pub json_limit: usize,
+    pub log_level: String,
+    // Size of the connection pool, also the number of queries that run at once
+    pub db_max_connections: u32,
+    // Seconds a query may wait for a connection, and a read may then run, before it fails. Writes
+    // that have started are never cut off.
+    pub db_timeout: u64,
+    // Milliseconds SQLite retries a locked database before it gives up
+    pub db_busy_timeout: u64,
+    // `PRAGMA synchronous` of every connection: off, normal, full or extra
+    pub db_synchronous: String,
+    // Page cache of each connection in KiB
+    pub db_cache_size: u64,
+    // Overrides the `access_token_ttl` setting while the server runs
+    pub access_token_ttl: Option<i64>,
+    // Reverse proxies whose X-Forwarded-For and Forwarded headers name the client, requests from
+    // anywhere else are attributed to the address they come from
+    pub trusted_proxies: Vec<IpAddr>,
+    // Config file that was read, if any
+    pub file: Option<PathBuf>,
+}
+
+const KEYS: [&str; 14] = [
+    "data_dir",
+    "database",
+    "uploads_dir",
+    "host",
+    "port",
+    "json_limit",
+    "log_level",
+    "db_max_connections",
+    "db_timeout",
+    "db_busy_timeout",
+    "db_synchronous",
+    "db_cache_size",
+    "access_token_ttl",
+    "trusted_proxies",
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("."),
+            database: PathBuf::from("database.sqlite"),
+            uploads_dir: PathBuf::from("uploads"),
+            host: "127.0.0.1".to_string(),
+            port: 8855,
+            json_limit: 50 * 1024 * 1024,
+            log_level: "error".to_string(),
+            db_max_connections: 10,
+            db_timeout: 30,
+            db_busy_timeout: 5000,
+            db_synchronous: "normal".to_string(),
+            db_cache_size: 8192,
+            access_token_ttl: None,
+            trusted_proxies: Vec::new(),
+            file: None,
+        }
+    }
+}
+
+/// Command line values that take precedence over the file and the environment.
+#[derive(Default)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Config {
+    pub fn load(overrides: Overrides) -> Result<Self, String> {
+        let mut config = Config::default();
+
+        let file = match overrides
+            .config
+            .or_else(|| std::env::var_os("MOOSEDB_CONFIG").map(PathBuf::from))
+        {
+            Some(path) => Some(path),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        };
+        if let Some(path) = file {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            for (line, key, value) in
+                parse_file(&contents).map_err(|e| format!("{}: {}", path.display(), e))?
+            {
+                config
+                    .set(&key, &value)
+                    .map_err(|e| format!("{} line {}: {}", path.display(), line, e))?;
+            }
+            config.file = Some(path);
+        }
+
+        for key in KEYS {
+            let name = format!("MOOSEDB_{}", key.to_uppercase());
+            if let Ok(value) = std::env::var(&name) {
+                config
+                    .set(key, &value)
+                    .map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+
+        if let Some(data_dir) = overrides.data_dir {
+            config.data_dir = data_dir;
+        }
+        if let Some(host) = overrides.host {
+            config.host = host;
+        }
+        if let Some(port) = overrides.port {
+            config.port = port;
+        }
+
+        // Relative paths live inside the data directory
+        config.database = config.data_dir.join(&config.database);
+        config.uploads_dir = config.data_dir.join(&config.uploads_dir);
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| {
+            value
+                .replace('_', "")
+                .parse::<i64>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or_else(|| format!("'{}' must be a positive number", key))
+        };
+
+        match key {
+            "data_dir" => self.data_dir = PathBuf::from(value),
+            "database" => self.database = PathBuf::from(value),
+            "uploads_dir" => self.uploads_dir = PathBuf::from(value),
+            "host" => self.host = value.to_string(),
+            "port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a valid port", value))?
+            }
+            "json_limit" => self.json_limit = number(value)? as usize,
+            "log_level" => self.log_level = value.to_string(),
+            "db_max_connections" => self.db_max_connections = number(value)? as u32,
+            "db_timeout" => self.db_timeout = number(value)? as u64,
+            "db_busy_timeout" => self.db_busy_timeout = number(value)? as u64,
+            "db_synchronous" => {
+                let mode = value.to_lowercase();
+                if !["off", "normal", "full", "extra"].contains(&mode.as_str()) {
+                    return Err(format!(
+                        "'{}' must be off, normal, full or extra, got '{}'",
+                        key, value
+                    ));
+                }
+                self.db_synchronous = mode;
+            }
+            "db_cache_size" => self.db_cache_size = number(value)? as u64,
+            "access_token_ttl" => self.access_token_ttl = Some(number(value)?),
+            "trusted_proxies" => {
+                self.trusted_proxies = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|ip| !ip.is_empty())
+                    .map(|ip| {
+                        ip.parse()
+                            .map_err(|_| format!("'{}' is not a valid IP address", ip))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            other => return Err(format!("Unknown option '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// Settings the configuration pins while the server runs, they win over `_configs` every
+    /// time the settings are loaded.
+    pub fn overridden_settings(&self) -> Vec<(&'static str, String)> {
+        let mut overridden = Vec::new();
+        if let Some(ttl) = self.access_token_ttl {
+            overridden.push(("access_token_ttl", ttl.to_string()));
+        }
+        overridden
+    }
+
+    /// The effective configuration in the config file format.
+    pub fn to_toml(&self) -> String {
+        let quote = |path: &Path| format!("{:?}", path.display().to_string());
+        let mut out = format!(
+            "data_dir = {}\ndatabase = {}\nuploads_dir = {}\nhost = {:?}\nport = {}\njson_limit = {}\nlog_level = {:?}\ndb_max_connections = {}\ndb_timeout = {}\ndb_busy_timeout = {}\ndb_synchronous = {:?}\ndb_cache_size = {}\n",
+            quote(&self.data_dir),
+            quote(&self.database),
+            quote(&self.uploads_dir),
+            self.host,
+            self.port,
+            self.json_limit,
+            self.log_level,
+            self.db_max_connections,
+            self.db_timeout,
+            self.db_busy_timeout,
+            self.db_synchronous,
+            self.db_cache_size,
+        );
+        if let Some(ttl) = self.access_token_ttl {
+            out.push_str(&format!("access_token_ttl = {}\n", ttl));
+        }
+        if !self.trusted_proxies.is_empty() {
+            let proxies: Vec<String> = self
+                .trusted_proxies
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect();
+            out.push_str(&format!("trusted_proxies = {:?}\n", proxies.join(", ")));
+        }
+        out
+    }
+}
+
+// Reads `key = value` lines of a flat TOML file. Values are basic or literal strings, numbers or
+// booleans; tables and arrays are not used by the config and are rejected.
+fn parse_file(contents: &str) -> Result<Vec<(usize, String, String)>, String> {
+    let mut entries = Vec::new();
+
+    for (index, raw) in contents.lines().enumerate() {
+        let line = index + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            return Err(format!("line {}: tables are not supported", line));
+        }
+
+        let (key, value) = trimmed
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", line))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", line, e))?;
+        entries.push((line, key, value));
+    }
+
+    Ok(entries)
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return trailing(&rest[end + 1..]).map(|_| rest[..end].to_string());
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return trailing(&rest[i + 1..]).map(|_| out),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    _ => return Err("unsupported escape sequence".to_string()),
+                },
+                c => out.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    let bare = value.split('#').next().unwrap_or("").trim();
+    if bare.is_empty() || bare.starts_with('[') || bare.starts_with('{') {
+        return Err(format!("unsupported value '{}'", value));
+    }
+    Ok(bare.to_string())
+}
+
+// Only a comment may follow a quoted value
+fn trailing(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{}' after value", rest))
+    }
+}
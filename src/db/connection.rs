@@ -1,79 +1,37 @@
+use crate::apis::audit::write_audit;
+use crate::config::Config;
+use crate::db::database::{DbError, open_pool};
+use crate::db::migrations::migrate;
+use crate::settings::SETTINGS;
 use crate::utils::random::generate_secret;
 use bcrypt::{DEFAULT_COST, hash};
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Error as RusqliteError;
 use rusqlite::{Connection, Result, params};
 use std::collections::HashMap;
 
-#[allow(dead_code)]
+pub type DbConnection = PooledConnection<SqliteConnectionManager>;
 
-pub fn initialize_db(conn: &Connection, create_new_db: bool) -> Result<()> {
-    if create_new_db == true {
+// Insert every registered setting that is not in _configs yet with its default value
+fn add_missing_configs(conn: &Connection) -> Result<()> {
+    for setting in SETTINGS {
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS _logs (
-            id INTEGER PRIMARY KEY,
-            method VARCHAR(10) NOT NULL,
-            url TEXT NOT NULL,
-            ip VARCHAR(45),
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        )",
-            [],
+            "INSERT INTO _configs (key, value)
+            SELECT ?1, ?2 WHERE NOT EXISTS (SELECT 1 FROM _configs WHERE key = ?1)",
+            params![setting.key, setting.default_value()],
         )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS _configs (
-                id INTEGER PRIMARY KEY,
-                key VARCHAR(255) NOT NULL,
-                value TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-            [],
-        )?;
-        conn.execute(
-            "INSERT INTO _configs (key, value) 
-            VALUES (?1, ?2)",
-            ["secret".to_string(), generate_secret()],
-        )
-        .unwrap();
-        conn.execute(
-            "INSERT INTO _configs (key, value) 
-            VALUES (?1, ?2)",
-            ["appname".to_string(), "MooseDB".to_string()],
-        )
-        .unwrap();
-        conn.execute(
-            "INSERT INTO _configs (key, value) 
-            VALUES (?1, ?2)",
-            ["records_per_page".to_string(), "100".to_string()],
-        )
-        .unwrap();
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS _super_admins (
-                id INTEGER PRIMARY KEY,
-                name VARCHAR(255) NOT NULL,
-                email VARCHAR(255) NOT NULL,
-                password VARCHAR(255) NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-            [],
-        )?;
-
-        let hashed_password = hash("moosedb", DEFAULT_COST).unwrap();
-        conn.execute(
-            "INSERT INTO _super_admins (name, email, password) 
-            VALUES (?1, ?2, ?3)",
-            [
-                "Admin".to_string(),
-                "admin@moosedb.com".to_string(),
-                hashed_password,
-            ],
-        )
-        .unwrap();
     }
     Ok(())
 }
 
+// Bring the system tables up to date and add settings that are new in this version
+pub fn upgrade_db(conn: &Connection) -> Result<()> {
+    migrate(conn)?;
+    add_missing_configs(conn)?;
+    Ok(())
+}
+
 // Load configs
 pub fn load_configs(conn: &Connection) -> Result<HashMap<String, String>> {
     let mut stmt = conn.prepare("SELECT key, value FROM _configs")?;
@@ -91,9 +49,23 @@ pub fn load_configs(conn: &Connection) -> Result<HashMap<String, String>> {
     Ok(configs)
 }
 
+// Connection to the database file of `config` for a CLI command, migrating it first. It is set up
+// like the server's so both can use the database at the same time.
+pub fn open_db(config: &Config) -> std::result::Result<DbConnection, DbError> {
+    let conn = open_pool(config, 1)?.get()?;
+    upgrade_db(&conn)?;
+    Ok(conn)
+}
+
 // Update super admin
-pub fn update_super_user(email: String, new_password: String) -> Result<()> {
-    let conn = Connection::open("database.sqlite")?;
+pub fn update_super_user(
+    config: &Config,
+    email: String,
+    new_password: String,
+) -> std::result::Result<(), DbError> {
+    let hashed_password = hash(new_password, DEFAULT_COST)
+        .map_err(|_| RusqliteError::InvalidParameterName("Failed to hash password".to_string()))?;
+    let conn = open_db(config)?;
 
     let exists: bool = conn.query_row(
         "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
@@ -106,7 +78,6 @@ pub fn update_super_user(email: String, new_password: String) -> Result<()> {
         return Ok(());
     }
 
-    let hashed_password = hash(new_password, DEFAULT_COST).unwrap();
     let updated = conn.execute(
         "UPDATE _super_admins SET password = ?1, updated_at = CURRENT_TIMESTAMP WHERE email = ?2",
         params![hashed_password, email],
@@ -116,23 +87,49 @@ pub fn update_super_user(email: String, new_password: String) -> Result<()> {
         eprintln!("Unexpected: {} rows updated", updated);
     }
 
+    conn.execute(
+        "UPDATE _sessions SET revoked_at = CURRENT_TIMESTAMP WHERE email = ?1 AND revoked_at IS NULL",
+        [&email],
+    )?;
+    write_audit(&conn, "cli", "", "", "admin.password", &email, None, None)?;
+
     Ok(())
 }
 
-// Create new super admin
+// Turn off two-factor authentication for a super admin who lost their authenticator
+pub fn disable_two_factor(config: &Config, email: String) -> std::result::Result<bool, DbError> {
+    let conn = open_db(config)?;
+
+    let updated = conn.execute(
+        "UPDATE _super_admins SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL,
+         recovery_codes = NULL, updated_at = CURRENT_TIMESTAMP WHERE email = ?1",
+        [&email],
+    )?;
+    if updated == 1 {
+        write_audit(
+            &conn,
+            "cli",
+            "",
+            "",
+            "admin.2fa_disable",
+            &email,
+            None,
+            None,
+        )?;
+    }
+
+    Ok(updated == 1)
+}
+
+// Create new super admin, the password is hashed by the caller before it takes the writer
 pub fn create_super_admin(
+    conn: &Connection,
     name: String,
     email: String,
-    password: String,
-    confirm_password: String,
+    hashed_password: &str,
+    role: &str,
+    permissions: Option<String>,
 ) -> rusqlite::Result<()> {
-    if password != confirm_password {
-        return Err(RusqliteError::InvalidParameterName(
-            "Passwords do not match".to_string(),
-        ));
-    }
-
-    let conn = Connection::open("database.sqlite")?;
     let exists: bool = conn.query_row(
         "SELECT EXISTS(SELECT 1 FROM _super_admins WHERE email = ?1)",
         params![email],
@@ -146,34 +143,32 @@ pub fn create_super_admin(
         )));
     }
 
-    let hashed_password = hash(password, DEFAULT_COST)
-        .map_err(|_| RusqliteError::InvalidParameterName("Failed to hash password".to_string()))?;
-
     conn.execute(
-        "INSERT INTO _super_admins (name, email, password) VALUES (?1, ?2, ?3)",
-        params![name, email, hashed_password],
+        "INSERT INTO _super_admins (name, email, password, role, permissions) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, email, hashed_password, role, permissions],
     )?;
     Ok(())
 }
 
-// Update setting
-pub fn update_setting(key: String, new_value: String) -> Result<()> {
-    let conn = Connection::open("database.sqlite")?;
-
+// Update a setting, inserting it when the database does not have it yet
+pub fn update_setting(conn: &Connection, key: &str, new_value: &str) -> Result<()> {
     let updated = conn.execute(
         "UPDATE _configs SET value = ?1, updated_at = CURRENT_TIMESTAMP WHERE key = ?2",
         params![new_value, key],
     )?;
 
-    if updated != 1 {
-        eprintln!("Unexpected: {} rows updated", updated);
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO _configs (key, value) VALUES (?1, ?2)",
+            params![key, new_value],
+        )?;
     }
     Ok(())
 }
 
 // Update Secret key
-pub fn update_secret_key() -> Result<()> {
-    let conn = Connection::open("database.sqlite")?;
+pub fn update_secret_key(config: &Config) -> std::result::Result<(), DbError> {
+    let conn = open_db(config)?;
 
     let updated = conn.execute(
         "UPDATE _configs SET value = ?1, updated_at = CURRENT_TIMESTAMP WHERE key = 'secret'",
@@ -0,0 +1,166 @@
+use crate::config::Config;
+use actix_web::web;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+
+/// Pool of up to `max_size` connections to the database of `config`. Every connection is set up
+/// with the same pragmas, WAL lets reads go on while a write is running.
+pub fn open_pool(config: &Config, max_size: u32) -> Result<DbPool, r2d2::Error> {
+    let busy_timeout = config.db_busy_timeout;
+    let synchronous = config.db_synchronous.clone();
+    let cache_size = config.db_cache_size;
+
+    let manager = SqliteConnectionManager::file(&config.database).with_init(move |conn| {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "busy_timeout", busy_timeout)?;
+        conn.pragma_update(None, "synchronous", &synchronous)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        // Negative sizes are in KiB instead of pages
+        conn.pragma_update(None, "cache_size", -(cache_size as i64))?;
+        Ok(())
+    });
+
+    Pool::builder()
+        .max_size(max_size)
+        .connection_timeout(Duration::from_secs(config.db_timeout))
+        .build(manager)
+}
+
+/// Why a query sent through [`Database::run`] did not produce a result.
+#[derive(Debug)]
+pub enum DbError {
+    // Every blocking slot stayed taken for the whole timeout
+    Busy,
+    // A read was still running when the timeout ran out
+    Timeout,
+    Pool(String),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Busy => write!(f, "The database is busy, try again later"),
+            DbError::Timeout => write!(f, "The database query timed out"),
+            DbError::Pool(err) => write!(f, "Failed to get database connection: {}", err),
+            DbError::Sqlite(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(err: rusqlite::Error) -> Self {
+        DbError::Sqlite(err)
+    }
+}
+
+impl From<r2d2::Error> for DbError {
+    fn from(err: r2d2::Error) -> Self {
+        DbError::Pool(err.to_string())
+    }
+}
+
+/// Connection pool whose queries run on actix's blocking threads instead of the worker that
+/// handles the request. At most one query per pooled connection runs at a time, later ones wait
+/// for a free slot up to `timeout`. Writes also queue for a single writer, so they never race
+/// each other for SQLite's lock.
+///
+/// A read that runs longer than `timeout` fails with [`DbError::Timeout`]. A write only times out
+/// while it waits for its turn: once it has started it is awaited until it commits or rolls back,
+/// so the caller never reports a failure for a write that went through.
+#[derive(Clone)]
+pub struct Database {
+    pool: DbPool,
+    slots: Arc<Semaphore>,
+    writer: Arc<Mutex<()>>,
+    timeout: Duration,
+}
+
+impl Database {
+    pub fn new(pool: DbPool, timeout: Duration) -> Self {
+        let slots = Arc::new(Semaphore::new(pool.max_size() as usize));
+        Database {
+            pool,
+            slots,
+            writer: Arc::new(Mutex::new(())),
+            timeout,
+        }
+    }
+
+    /// Run `query` as the only writer, inside a transaction that is rolled back when it fails.
+    /// Writers take their turn in the order they arrived.
+    pub async fn write<T, F>(&self, query: F) -> Result<T, DbError>
+    where
+        F: FnOnce(&Connection) -> Result<T, DbError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let turn = tokio::time::timeout(self.timeout, self.writer.clone().lock_owned())
+            .await
+            .map_err(|_| DbError::Busy)?;
+
+        self.execute(false, move |conn| {
+            let _turn = turn;
+            conn.execute_batch("BEGIN IMMEDIATE")?;
+            match query(conn) {
+                Ok(result) => match conn.execute_batch("COMMIT") {
+                    Ok(()) => Ok(result),
+                    Err(err) => {
+                        let _ = conn.execute_batch("ROLLBACK");
+                        Err(err.into())
+                    }
+                },
+                Err(err) => {
+                    let _ = conn.execute_batch("ROLLBACK");
+                    Err(err)
+                }
+            }
+        })
+        .await
+    }
+
+    /// Run `query` with a pooled connection on a blocking thread. Meant for reads, see
+    /// [`Database::write`] for anything that changes the database.
+    pub async fn run<T, F>(&self, query: F) -> Result<T, DbError>
+    where
+        F: FnOnce(&Connection) -> Result<T, DbError> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.execute(true, query).await
+    }
+
+    // With `time_limit` the caller stops waiting for a query that runs longer than the timeout,
+    // the query itself carries on until it is done.
+    async fn execute<T, F>(&self, time_limit: bool, query: F) -> Result<T, DbError>
+    where
+        F: FnOnce(&Connection) -> Result<T, DbError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = tokio::time::timeout(self.timeout, self.slots.clone().acquire_owned())
+            .await
+            .map_err(|_| DbError::Busy)?
+            .map_err(|_| DbError::Busy)?;
+
+        let pool = self.pool.clone();
+        let task = web::block(move || {
+            // Held until the query is done, even when the caller has stopped waiting for it
+            let _slot = slot;
+            let conn = pool.get().map_err(|e| DbError::Pool(e.to_string()))?;
+            query(&conn)
+        });
+
+        let finished = match time_limit {
+            true => tokio::time::timeout(self.timeout, task)
+                .await
+                .map_err(|_| DbError::Timeout)?,
+            false => task.await,
+        };
+        finished.map_err(|_| DbError::Busy)?
+    }
+}
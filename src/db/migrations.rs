@@ -0,0 +1,303 @@
+use bcrypt::{DEFAULT_COST, hash};
+use rusqlite::{Connection, Result, ffi};
+
+type Migration = fn(&Connection) -> Result<()>;
+
+// Migrations in the order they are applied, the schema version of a database is the number of
+// migrations it has run. Append new ones at the end and never change one that has been released.
+// The first ones are written with `IF NOT EXISTS` so they also fit databases that were upgraded
+// before versions were tracked.
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("create system tables", create_system_tables),
+    ("add file metadata", add_files),
+    ("add sessions and auth tokens", add_sessions),
+    ("add login attempts", add_login_attempts),
+    (
+        "add admin verification, two-factor and roles",
+        add_admin_columns,
+    ),
+    ("add audit log", add_audit_log),
+    ("add file field options", add_file_field_options),
+    ("add password reset requests", add_password_reset_requests),
+];
+
+/// Schema version this build creates and understands.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+// Run every migration newer than the database's `user_version` in one transaction. A database
+// written by a newer build is refused instead of being changed.
+pub fn migrate(conn: &Connection) -> Result<()> {
+    let current = schema_version(conn)?;
+    if current > SCHEMA_VERSION {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_ERROR),
+            Some(format!(
+                "database schema version {} is newer than the supported version {}, upgrade MooseDB to open it",
+                current, SCHEMA_VERSION
+            )),
+        ));
+    }
+    if current == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    for (version, (name, migration)) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        migration(&tx).map_err(|err| match err {
+            rusqlite::Error::SqliteFailure(code, message) => rusqlite::Error::SqliteFailure(
+                code,
+                Some(format!(
+                    "migration {} ({}) failed: {}",
+                    version + 1,
+                    name,
+                    message.unwrap_or_default()
+                )),
+            ),
+            other => other,
+        })?;
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()
+}
+
+// Add any of `columns` that `table` is missing. Does nothing if the table does not exist.
+fn add_missing_columns(conn: &Connection, table: &str, columns: &[(&str, &str)]) -> Result<()> {
+    let existing: Vec<String> = conn
+        .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+
+    if existing.is_empty() {
+        return Ok(());
+    }
+
+    for (column, definition) in columns {
+        if !existing.iter().any(|c| c == column) {
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Tables of the first release, plus the default admin of a new database
+fn create_system_tables(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _logs (
+            id INTEGER PRIMARY KEY,
+            method VARCHAR(10) NOT NULL,
+            url TEXT NOT NULL,
+            ip VARCHAR(45),
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _configs (
+            id INTEGER PRIMARY KEY,
+            key VARCHAR(255) NOT NULL,
+            value TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _super_admins (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            email VARCHAR(255) NOT NULL,
+            password VARCHAR(255) NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    // Used to be created with the first collection
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _database_metadata (
+            table_id TEXT NOT NULL,
+            table_name TEXT NOT NULL,
+            field_name TEXT NOT NULL,
+            field_type TEXT NOT NULL,
+            unique_field BOOLEAN NOT NULL,
+            nullable BOOLEAN NOT NULL,
+            min INTEGER,
+            max INTEGER,
+            allowed_extensions TEXT,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            updated_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (table_name, field_name)
+        )",
+        [],
+    )?;
+
+    let admins: i64 = conn.query_row("SELECT COUNT(*) FROM _super_admins", [], |row| row.get(0))?;
+    if admins == 0 {
+        let hashed_password = hash("moosedb", DEFAULT_COST).unwrap();
+        conn.execute(
+            "INSERT INTO _super_admins (name, email, password) VALUES (?1, ?2, ?3)",
+            ["Admin", "admin@moosedb.com", &hashed_password],
+        )?;
+    }
+    Ok(())
+}
+
+fn add_files(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _files (
+            id INTEGER PRIMARY KEY,
+            file_id TEXT NOT NULL,
+            stored_name TEXT NOT NULL UNIQUE,
+            original_name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            sha256 TEXT,
+            collection_id TEXT,
+            field_name TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS _files_file_id ON _files (file_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_sessions(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _sessions (
+            id INTEGER PRIMARY KEY,
+            session_id TEXT NOT NULL UNIQUE,
+            email VARCHAR(255) NOT NULL,
+            refresh_token_hash TEXT NOT NULL,
+            user_agent TEXT,
+            ip VARCHAR(45),
+            expires_at INTEGER NOT NULL,
+            revoked_at TIMESTAMP,
+            last_used_at TIMESTAMP,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _auth_tokens (
+            id INTEGER PRIMARY KEY,
+            email VARCHAR(255) NOT NULL,
+            purpose VARCHAR(50) NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            expires_at INTEGER NOT NULL,
+            used_at TIMESTAMP,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_login_attempts(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _login_attempts (
+            id INTEGER PRIMARY KEY,
+            email VARCHAR(255) NOT NULL,
+            ip VARCHAR(45) NOT NULL,
+            user_agent TEXT,
+            success BOOLEAN NOT NULL,
+            attempted_at INTEGER NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _login_attempts_email ON _login_attempts (email, attempted_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _login_attempts_ip ON _login_attempts (ip, attempted_at)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_admin_columns(conn: &Connection) -> Result<()> {
+    add_missing_columns(
+        conn,
+        "_super_admins",
+        &[
+            ("email_verified_at", "TIMESTAMP"),
+            ("totp_secret", "TEXT"),
+            ("totp_enabled", "BOOLEAN NOT NULL DEFAULT 0"),
+            ("totp_last_step", "INTEGER"),
+            ("recovery_codes", "TEXT"),
+            // Accounts created before roles existed keep their full access
+            ("role", "TEXT NOT NULL DEFAULT 'owner'"),
+            ("permissions", "TEXT"),
+            ("disabled", "BOOLEAN NOT NULL DEFAULT 0"),
+        ],
+    )
+}
+
+fn add_audit_log(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _audit_log (
+            id INTEGER PRIMARY KEY,
+            actor VARCHAR(255) NOT NULL,
+            action VARCHAR(50) NOT NULL,
+            target TEXT NOT NULL,
+            before TEXT,
+            after TEXT,
+            ip VARCHAR(45),
+            user_agent TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _audit_log_target ON _audit_log (target)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_file_field_options(conn: &Connection) -> Result<()> {
+    add_missing_columns(
+        conn,
+        "_database_metadata",
+        &[
+            ("max_file_size", "INTEGER"),
+            ("max_files", "INTEGER"),
+            ("thumb_sizes", "TEXT"),
+            ("protected", "BOOLEAN NOT NULL DEFAULT 0"),
+        ],
+    )
+}
+
+fn add_password_reset_requests(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS _password_reset_requests (
+            id INTEGER PRIMARY KEY,
+            email VARCHAR(255) NOT NULL,
+            ip VARCHAR(45) NOT NULL,
+            requested_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _password_reset_requests_email ON _password_reset_requests (email, requested_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _password_reset_requests_ip ON _password_reset_requests (ip, requested_at)",
+        [],
+    )?;
+    Ok(())
+}
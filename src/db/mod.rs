@@ -1 +1,5 @@
 pub mod connection;
+pub mod database;
+pub mod migrations;
+pub mod records;
+pub mod schema;
@@ -0,0 +1,55 @@
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OptionalExtension, Result, Row};
+use serde_json::Value;
+
+// Queries on collection tables, meant to run inside `Database::run`
+
+pub fn value_to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(v) => serde_json::json!(v),
+        ValueRef::Real(v) => serde_json::json!(v),
+        ValueRef::Text(v) => Value::String(String::from_utf8_lossy(v).to_string()),
+        ValueRef::Blob(_) => Value::Null,
+    }
+}
+
+pub fn row_to_json(row: &Row, columns: &[String]) -> Result<Value> {
+    let mut record = serde_json::Map::new();
+    for (i, column) in columns.iter().enumerate() {
+        record.insert(column.clone(), value_to_json(row.get_ref(i)?));
+    }
+    Ok(Value::Object(record))
+}
+
+pub fn count_records(conn: &Connection, table_name: &str) -> Result<i64> {
+    conn.query_row(
+        &format!("SELECT COUNT(*) FROM \"{}\"", table_name),
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Records of a table, most recently updated first. `None` returns every record.
+pub fn list_records(
+    conn: &Connection,
+    table_name: &str,
+    page: Option<(u32, u32)>,
+) -> Result<Vec<Value>> {
+    let mut sql = format!("SELECT * FROM \"{}\" ORDER BY updated_at DESC", table_name);
+    if let Some((limit, offset)) = page {
+        sql.push_str(&format!(" LIMIT {} OFFSET {}", limit, offset));
+    }
+
+    let mut stmt = conn.prepare(&sql)?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    stmt.query_map([], |row| row_to_json(row, &columns))?
+        .collect()
+}
+
+pub fn find_record(conn: &Connection, table_name: &str, id: &str) -> Result<Option<Value>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM \"{}\" WHERE id = ?1", table_name))?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    stmt.query_row([id], |row| row_to_json(row, &columns))
+        .optional()
+}
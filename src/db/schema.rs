@@ -0,0 +1,134 @@
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Collection definitions from `_database_metadata`, kept in memory so requests don't have to
+// read them back on every call. Reloaded by whatever changes `_database_metadata`.
+
+/// One field of a collection, with the column names of `_database_metadata`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Field {
+    #[serde(rename = "field_name")]
+    pub name: String,
+    pub field_type: String,
+    #[serde(rename = "unique_field")]
+    pub unique: bool,
+    pub nullable: bool,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub allowed_extensions: Option<String>,
+    pub max_file_size: Option<i64>,
+    pub max_files: Option<i64>,
+    pub thumb_sizes: Option<String>,
+    pub protected: bool,
+}
+
+#[derive(Debug)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+    // Fields in the order they were created
+    pub fields: Vec<Field>,
+    // Order of the collection among the others, by creation
+    position: i64,
+}
+
+impl Collection {
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    pub fn file_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .filter(|field| field.field_type == "FILE")
+    }
+}
+
+/// Cache of every collection, keyed by `table_id`.
+#[derive(Clone, Default)]
+pub struct Schema {
+    collections: Arc<RwLock<HashMap<String, Arc<Collection>>>>,
+}
+
+impl Schema {
+    pub fn load(conn: &Connection) -> Result<Self> {
+        let schema = Schema::default();
+        schema.reload(conn)?;
+        Ok(schema)
+    }
+
+    /// Read `_database_metadata` again. Called on the connection that changed it, so the cache
+    /// is updated while the writer still holds its turn.
+    pub fn reload(&self, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare(
+            "SELECT rowid, table_id, table_name, field_name, field_type, unique_field, nullable, min,
+                    max, allowed_extensions, max_file_size, max_files, thumb_sizes, protected
+             FROM _database_metadata ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let field = Field {
+                name: row.get(3)?,
+                field_type: row.get(4)?,
+                unique: row.get(5)?,
+                nullable: row.get(6)?,
+                min: row.get(7)?,
+                max: row.get(8)?,
+                allowed_extensions: row.get(9)?,
+                max_file_size: row.get(10)?,
+                max_files: row.get(11)?,
+                thumb_sizes: row.get(12)?,
+                protected: row.get(13)?,
+            };
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                field,
+            ))
+        })?;
+
+        let mut collections: HashMap<String, Collection> = HashMap::new();
+        for row in rows {
+            let (rowid, id, name, field) = row?;
+            collections
+                .entry(id.clone())
+                .or_insert_with(|| Collection {
+                    id,
+                    name,
+                    fields: Vec::new(),
+                    position: rowid,
+                })
+                .fields
+                .push(field);
+        }
+
+        *self.collections.write().unwrap() = collections
+            .into_iter()
+            .map(|(id, collection)| (id, Arc::new(collection)))
+            .collect();
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Collection>> {
+        self.collections.read().unwrap().get(id).cloned()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Arc<Collection>> {
+        self.collections
+            .read()
+            .unwrap()
+            .values()
+            .find(|collection| collection.name == name)
+            .cloned()
+    }
+
+    /// Every collection, oldest first.
+    pub fn all(&self) -> Vec<Arc<Collection>> {
+        let mut collections: Vec<Arc<Collection>> =
+            self.collections.read().unwrap().values().cloned().collect();
+        collections.sort_by_key(|collection| collection.position);
+        collections
+    }
+}
@@ -0,0 +1,446 @@
+//! MooseDB as a library, see [`MooseDb`]. The `moosedb` binary is a command line around it.
+
+mod apis;
+pub mod codegen;
+pub mod config;
+pub mod db;
+mod mailer;
+mod settings;
+pub mod storage;
+mod utils;
+
+use apis::account::*;
+use apis::admins::*;
+use apis::audit::*;
+use apis::auth::*;
+use apis::batch::run_batch;
+use apis::collections::*;
+use apis::error::{json_error, path_error, query_error};
+use apis::files::*;
+use apis::login_attempts::*;
+use apis::openapi::get_openapi;
+use apis::public::*;
+use apis::records::*;
+use apis::sessions::*;
+use apis::settings::*;
+use apis::two_factor::*;
+use db::connection::*;
+
+pub use apis::error::ApiError;
+
+use actix_web::dev::Server;
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer, Responder, Result, Scope, get, middleware, web,
+};
+use actix_web_httpauth::middleware::HttpAuthentication;
+use config::{Config, Overrides};
+use db::database::{Database, open_pool};
+use db::schema::Schema;
+use mime_guess::from_path;
+use moosedb_types::{
+    BatchOperation, BatchResponse, CollectionSummary, CreateRecordRequest, PaginationParams,
+    RecordStatus, RecordsResponse,
+};
+use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use storage::Storage;
+
+#[derive(RustEmbed)]
+#[folder = "ui/dist"]
+struct Asset;
+
+#[derive(Serialize)]
+struct Info {
+    version: f32,
+    actix_web: f32,
+    actix_files: f32,
+    rusqlite: f32,
+    r2d2: f32,
+    r2d2_sqlite: f32,
+    serde: f32,
+    serde_json: f32,
+    rust_embed: f32,
+    mime_guess: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: usize,
+    iat: usize,
+    email: String,
+    jti: String,
+}
+
+#[derive(Clone)]
+struct AppData {
+    config: Arc<Config>,
+    database: Database,
+    jwt_secret: String,
+    configs: Arc<RwLock<HashMap<String, String>>>,
+    storage: Arc<dyn Storage>,
+    schema: Schema,
+    // Path MooseDB is mounted under inside another app, empty when it serves on its own
+    prefix: String,
+}
+
+/// Options of [`MooseDb::builder`]. Anything left unset comes from the config file and the
+/// MOOSEDB_* environment variables like it does for the binary.
+#[derive(Default)]
+pub struct Builder {
+    overrides: Overrides,
+    config: Option<Config>,
+}
+
+impl Builder {
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.overrides.config = Some(path.into());
+        self
+    }
+
+    pub fn data_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.overrides.data_dir = Some(path.into());
+        self
+    }
+
+    /// Host for [`MooseDb::serve`], not used when mounted into another app.
+    pub fn host(mut self, host: &str) -> Self {
+        self.overrides.host = Some(host.to_string());
+        self
+    }
+
+    /// Port for [`MooseDb::serve`], 0 picks a free one.
+    pub fn port(mut self, port: u16) -> Self {
+        self.overrides.port = Some(port);
+        self
+    }
+
+    /// Use a configuration that was loaded already, the other options are ignored.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Open the data directory, creating and upgrading its database when needed.
+    pub fn build(self) -> std::result::Result<MooseDb, String> {
+        let config = match self.config {
+            Some(config) => config,
+            None => Config::load(self.overrides)?,
+        };
+
+        std::fs::create_dir_all(&config.data_dir)
+            .map_err(|e| format!("Data directory could not be created: {}", e))?;
+
+        let pool = open_pool(&config, config.db_max_connections)
+            .map_err(|e| format!("Database could not be opened: {}", e))?;
+        let conn = pool
+            .get()
+            .map_err(|e| format!("Database could not be opened: {}", e))?;
+        upgrade_db(&conn).map_err(|e| format!("Database could not be upgraded: {}", e))?;
+
+        let schema =
+            Schema::load(&conn).map_err(|e| format!("Collections could not be loaded: {}", e))?;
+
+        let mut loaded =
+            load_configs(&conn).map_err(|e| format!("Settings could not be loaded: {}", e))?;
+        drop(conn);
+        for (key, value) in config.overridden_settings() {
+            loaded.insert(key.to_string(), value);
+        }
+        let jwt_secret = loaded
+            .get("secret")
+            .cloned()
+            .ok_or("Secret token is missing from the settings")?;
+        let storage = storage::from_configs(&loaded, &config.uploads_dir)
+            .map_err(|e| format!("Storage backend could not be initialized: {}", e))?;
+
+        Ok(MooseDb {
+            data: AppData {
+                database: Database::new(pool, Duration::from_secs(config.db_timeout)),
+                config: Arc::new(config),
+                jwt_secret,
+                configs: Arc::new(RwLock::new(loaded)),
+                storage,
+                schema,
+                prefix: String::new(),
+            },
+        })
+    }
+}
+
+/// An opened data directory. It can serve HTTP on its own, be mounted into another actix-web
+/// app, or be used as a record store from the same process. Clones share the database.
+#[derive(Clone)]
+pub struct MooseDb {
+    data: AppData,
+}
+
+impl MooseDb {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.data.config
+    }
+
+    /// Bind the HTTP server to the configured host and port. Nothing is served until the
+    /// returned server is awaited, the addresses are the ones it listens on.
+    pub fn serve(&self) -> std::result::Result<(Server, Vec<SocketAddr>), String> {
+        let data = self.data.clone();
+        let (host, port) = (data.config.host.clone(), data.config.port);
+
+        HttpServer::new(move || {
+            App::new()
+                .wrap(middleware::Logger::default())
+                .configure(routes(data.clone()))
+        })
+        .bind((host.as_str(), port))
+        .map(|server| {
+            let addrs = server.addrs();
+            (server.run(), addrs)
+        })
+        .map_err(|e| format!("Could not listen on {}:{}: {}", host, port, e))
+    }
+
+    /// Every route of the server under `prefix`, for `App::service` of another app. File URLs
+    /// and the OpenAPI document include the prefix.
+    pub fn scope(&self, prefix: &str) -> Scope {
+        let prefix = prefix.trim_end_matches('/');
+        let data = AppData {
+            prefix: prefix.to_string(),
+            ..self.data.clone()
+        };
+        web::scope(prefix).configure(routes(data))
+    }
+
+    /// Collections the way `/admin/api/collections` lists them.
+    pub fn collections(&self) -> Vec<CollectionSummary> {
+        self.data
+            .schema
+            .all()
+            .iter()
+            .map(|c| CollectionSummary {
+                table_id: c.id.clone(),
+                table_name: c.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Create a record with the same validation and file handling as `create-record`, returning
+    /// its id. The audit log names `system` as the actor.
+    pub async fn create_record(
+        &self,
+        collection_id: &str,
+        data: Map<String, Value>,
+    ) -> std::result::Result<String, ApiError> {
+        let request = CreateRecordRequest {
+            collection_id: collection_id.to_string(),
+            data,
+        };
+        let response =
+            apis::records::create(&self.data, AuditContext::system(), request, None).await?;
+        Ok(response.id)
+    }
+
+    /// Update the record whose unique field `on` has the value in `data`, or create one when
+    /// there is none. Returns the id and which of the two happened.
+    pub async fn upsert_record(
+        &self,
+        collection_id: &str,
+        on: &str,
+        data: Map<String, Value>,
+    ) -> std::result::Result<(String, RecordStatus), ApiError> {
+        let request = CreateRecordRequest {
+            collection_id: collection_id.to_string(),
+            data,
+        };
+        let upsert_on = Some(on.to_string());
+        let response =
+            apis::records::create(&self.data, AuditContext::system(), request, upsert_on).await?;
+        Ok((response.id, response.status))
+    }
+
+    /// A page of records like `/api/records/{collection_id}` returns it. File URLs are relative
+    /// unless the app_url setting is set.
+    pub async fn list_records(
+        &self,
+        collection_id: &str,
+        page: u32,
+        items: Option<u32>,
+    ) -> std::result::Result<RecordsResponse, ApiError> {
+        let params = PaginationParams {
+            page: Some(page),
+            items,
+        };
+        records_page(
+            &self.data,
+            self.base_url(),
+            collection_id.to_string(),
+            params,
+        )
+        .await
+    }
+
+    pub async fn get_record(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> std::result::Result<Value, ApiError> {
+        let response = single_record(
+            &self.data,
+            self.base_url(),
+            collection_id.to_string(),
+            record_id.to_string(),
+        )
+        .await?;
+        Ok(response.record)
+    }
+
+    /// Delete records and their files, returning how many were removed.
+    pub async fn delete_records(
+        &self,
+        collection_id: &str,
+        record_ids: Vec<String>,
+    ) -> std::result::Result<usize, ApiError> {
+        let response = delete_records(
+            &self.data,
+            AuditContext::system(),
+            collection_id.to_string(),
+            record_ids,
+        )
+        .await?;
+        Ok(response.deleted_count)
+    }
+
+    /// Run create, update, delete and upsert operations in one transaction, see `/api/batch`.
+    pub async fn batch(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> std::result::Result<BatchResponse, ApiError> {
+        apis::batch::batch(&self.data, AuditContext::system(), operations).await
+    }
+
+    fn base_url(&self) -> String {
+        let app_url = self.data.configs.read().unwrap().get("app_url").cloned();
+        app_url
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| self.data.prefix.clone())
+    }
+}
+
+// Routes, extractor settings and shared state of the server, for an `App` or a `Scope`
+fn routes(data: AppData) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        let auth = HttpAuthentication::with_fn(validator);
+        cfg.app_data(
+            web::JsonConfig::default()
+                .limit(data.config.json_limit)
+                .error_handler(json_error),
+        )
+        .app_data(web::PathConfig::default().error_handler(path_error))
+        .app_data(web::QueryConfig::default().error_handler(query_error))
+        .app_data(web::Data::new(data))
+        .service(index)
+        .route("/auth/login", web::post().to(login))
+        .route("/auth/login/2fa", web::post().to(login_two_factor))
+        .route("/auth/refresh", web::post().to(refresh))
+        .route("/auth/forgot-password", web::post().to(forgot_password))
+        .route("/auth/reset-password", web::post().to(reset_password))
+        .route("/auth/verify-email", web::post().to(verify_email))
+        .route("/uploads/{filename}", web::get().to(serve_upload))
+        .route("/uploads/{filename}", web::head().to(serve_upload))
+        .service(
+            web::scope("/admin/api")
+                .wrap(auth.clone())
+                .service(get_version)
+                .service(get_setting)
+                .service(update_setting_func)
+                .service(get_settings)
+                .service(reload_settings)
+                .service(get_config)
+                .service(create_collection)
+                .service(get_collections)
+                .service(delete_collection)
+                .service(get_collection_records)
+                .service(create_super_admin_func)
+                .service(get_super_admins)
+                .service(get_me)
+                .service(update_admin)
+                .service(disable_admin)
+                .service(delete_admin)
+                .service(create_record)
+                .service(create_file_token)
+                .service(update_your_password)
+                .service(logout)
+                .service(get_sessions)
+                .service(revoke_session_func)
+                .service(get_login_attempts)
+                .service(get_audit_log)
+                .service(export_audit_log)
+                .service(send_test_email)
+                .service(two_factor_status)
+                .service(two_factor_setup)
+                .service(two_factor_enable)
+                .service(two_factor_disable)
+                .service(delete_collection_records)
+                .service(update_collection),
+        )
+        .service(
+            web::scope("/api")
+                .service(get_version)
+                .service(get_openapi)
+                .service(
+                    web::resource("/batch")
+                        .wrap(auth)
+                        .route(web::post().to(run_batch)),
+                )
+                .service(get_collection_data)
+                .service(get_single_record),
+        )
+        .default_service(web::route().to(static_files));
+    }
+}
+
+#[get("/")]
+async fn index() -> Result<impl Responder> {
+    Ok(web::Json(Info {
+        version: 0.1,
+        actix_web: 4.0,
+        actix_files: 0.6,
+        rusqlite: 0.37,
+        r2d2: 0.8,
+        r2d2_sqlite: 0.31,
+        serde: 1.0,
+        serde_json: 1.0,
+        rust_embed: 8.0,
+        mime_guess: 2.0,
+    }))
+}
+
+async fn static_files(req: HttpRequest) -> HttpResponse {
+    // Relative to the scope when mounted under a prefix
+    let path = req.match_info().unprocessed().trim_start_matches('/');
+    let file_path = if path.is_empty() { "index.html" } else { path };
+
+    match Asset::get(file_path) {
+        Some(content) => {
+            let body = content.data.into_owned();
+            let mime = from_path(file_path).first_or_octet_stream();
+            HttpResponse::Ok().content_type(mime.as_ref()).body(body)
+        }
+        None => match Asset::get("index.html") {
+            Some(index_file) => HttpResponse::Ok()
+                .content_type("text/html")
+                .body(index_file.data.into_owned()),
+            None => HttpResponse::NotFound().body("404 Not Found"),
+        },
+    }
+}
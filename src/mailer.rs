@@ -0,0 +1,196 @@
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const DEFAULT_RESET_SUBJECT: &str = "Reset your {{app_name}} password";
+pub const DEFAULT_RESET_TEXT: &str = "Hi {{name}},
+
+Someone asked to reset the password of your {{app_name}} account ({{email}}).
+Open the link below to choose a new password. It expires in {{expires_in}} minutes.
+
+{{url}}
+
+If you did not ask for this you can ignore this email.";
+pub const DEFAULT_RESET_HTML: &str = "<p>Hi {{name}},</p>
+<p>Someone asked to reset the password of your {{app_name}} account ({{email}}).
+Click the button below to choose a new password. It expires in {{expires_in}} minutes.</p>
+<p><a href=\"{{url}}\" style=\"display:inline-block;padding:10px 18px;border-radius:8px;background:#ef9143;color:#fff;text-decoration:none\">Reset password</a></p>
+<p>If you did not ask for this you can ignore this email.</p>";
+
+pub const DEFAULT_VERIFY_SUBJECT: &str = "Verify your {{app_name}} email address";
+pub const DEFAULT_VERIFY_TEXT: &str = "Hi {{name}},
+
+An admin account for {{email}} has been created on {{app_name}}.
+Open the link below to verify your email address. It expires in {{expires_in}} minutes.
+
+{{url}}";
+pub const DEFAULT_VERIFY_HTML: &str = "<p>Hi {{name}},</p>
+<p>An admin account for {{email}} has been created on {{app_name}}.
+Click the button below to verify your email address. It expires in {{expires_in}} minutes.</p>
+<p><a href=\"{{url}}\" style=\"display:inline-block;padding:10px 18px;border-radius:8px;background:#ef9143;color:#fff;text-decoration:none\">Verify email</a></p>";
+
+/// A rendered email ready to be sent.
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+pub struct Mailer {
+    host: String,
+    port: u16,
+    tls: String,
+    username: String,
+    password: String,
+    from: String,
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Replace `{{name}}` (or `{{ name }}`) placeholders with their values
+pub fn render(template: &str, vars: &[(&str, &str)], html: bool) -> String {
+    let mut rendered = template.to_string();
+    for (name, value) in vars {
+        let value = if html {
+            escape_html(value)
+        } else {
+            value.to_string()
+        };
+        rendered = rendered
+            .replace(&format!("{{{{{}}}}}", name), &value)
+            .replace(&format!("{{{{ {} }}}}", name), &value);
+    }
+    rendered
+}
+
+// Render the `mail_{template}_*` settings into an email
+pub fn render_template(
+    configs: &HashMap<String, String>,
+    template: &str,
+    to: &str,
+    vars: &[(&str, &str)],
+) -> Email {
+    let (subject, text, html) = match template {
+        "reset" => (
+            DEFAULT_RESET_SUBJECT,
+            DEFAULT_RESET_TEXT,
+            DEFAULT_RESET_HTML,
+        ),
+        _ => (
+            DEFAULT_VERIFY_SUBJECT,
+            DEFAULT_VERIFY_TEXT,
+            DEFAULT_VERIFY_HTML,
+        ),
+    };
+    let setting = |part: &str, default: &str| {
+        configs
+            .get(&format!("mail_{}_{}", template, part))
+            .filter(|v| !v.trim().is_empty())
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    };
+
+    Email {
+        to: to.to_string(),
+        subject: render(&setting("subject", subject), vars, false),
+        text: render(&setting("text", text), vars, false),
+        html: render(&setting("html", html), vars, true),
+    }
+}
+
+impl Mailer {
+    /// Reads the `smtp_*` settings. Fails when no SMTP host has been set.
+    pub fn from_configs(configs: &HashMap<String, String>) -> Result<Self, String> {
+        let get = |key: &str| {
+            configs
+                .get(key)
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        let host = get("smtp_host");
+        if host.is_empty() {
+            return Err("Email is not configured, set smtp_host first".to_string());
+        }
+
+        let tls = match get("smtp_tls").as_str() {
+            "" => "starttls".to_string(),
+            mode @ ("tls" | "starttls" | "none") => mode.to_string(),
+            other => {
+                return Err(format!(
+                    "Invalid smtp_tls '{}'. Expected tls, starttls or none",
+                    other
+                ));
+            }
+        };
+
+        let port = match get("smtp_port").as_str() {
+            "" if tls == "tls" => 465,
+            "" => 587,
+            port => port
+                .parse()
+                .map_err(|_| format!("Invalid smtp_port '{}'", port))?,
+        };
+
+        Ok(Mailer {
+            host,
+            port,
+            tls,
+            username: get("smtp_username"),
+            password: get("smtp_password"),
+            from: get("smtp_from"),
+        })
+    }
+
+    pub fn send(&self, email: &Email) -> Result<(), String> {
+        let from: Mailbox = self
+            .from
+            .parse()
+            .map_err(|_| format!("Invalid smtp_from address '{}'", self.from))?;
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|_| format!("Invalid recipient address '{}'", email.to))?;
+
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(&email.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                email.text.clone(),
+                email.html.clone(),
+            ))
+            .map_err(|e| e.to_string())?;
+
+        let mut transport = match self.tls.as_str() {
+            "tls" => SmtpTransport::relay(&self.host).map_err(|e| e.to_string())?,
+            "starttls" => SmtpTransport::starttls_relay(&self.host).map_err(|e| e.to_string())?,
+            _ => SmtpTransport::builder_dangerous(&self.host),
+        }
+        .port(self.port)
+        .timeout(Some(Duration::from_secs(15)));
+
+        if !self.username.is_empty() {
+            transport = transport.credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ));
+        }
+
+        transport
+            .build()
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| format!("Email could not be sent: {}", e))
+    }
+}
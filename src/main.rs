@@ -1,39 +1,25 @@
-mod apis;
-mod db;
-mod utils;
-
-use apis::auth::*;
-use apis::collections::*;
-use apis::public::*;
-use apis::records::*;
-use apis::settings::*;
-use db::connection::*;
-
-use actix_web::{
-    App, HttpRequest, HttpResponse, HttpServer, Responder, Result, get, middleware, web,
-};
-
-use actix_web_httpauth::middleware::HttpAuthentication;
 use clap::{Parser, Subcommand};
 use env_logger::Builder;
-use mime_guess::from_path;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rust_embed::RustEmbed;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-// use std::fs::OpenOptions;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
-
-#[derive(RustEmbed)]
-#[folder = "ui/dist"]
-struct Asset;
+use moosedb::MooseDb;
+use moosedb::codegen;
+use moosedb::config::{Config, Overrides};
+use moosedb::db::connection::*;
+use moosedb::db::schema::Schema;
+use moosedb::storage;
+use std::path::{Path, PathBuf};
 
 /// MooseDB CLI
 #[derive(Parser, Debug)]
 #[command(about, long_about = None)]
 struct Args {
+    /// Config file to read, defaults to moosedb.toml when it exists (Optional)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Directory that holds the database and uploads (Optional)
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -42,13 +28,13 @@ struct Args {
 enum Commands {
     /// Start the MooseDB server
     Serve {
-        /// Option to change the host (Optional)
-        #[arg(long, default_value = "127.0.0.1")]
-        host: String,
+        /// Option to change the host, 127.0.0.1 by default (Optional)
+        #[arg(long)]
+        host: Option<String>,
 
-        /// Option to change the port (Optional)
-        #[arg(long, default_value_t = 8855)]
-        port: u16,
+        /// Option to change the port, 8855 by default (Optional)
+        #[arg(long)]
+        port: Option<u16>,
     },
     /// Update super admin credientials
     Upsuper {
@@ -67,171 +53,199 @@ enum Commands {
     },
     /// Update the system’s secret token.
     Upsecret,
+    /// Turn off two-factor authentication for a super admin
+    Disable2fa {
+        /// Email of the super admin (Required)
+        #[arg(long = "email", short = 'e', required = true, value_name = "EMAIL")]
+        email: String,
+    },
+    /// Copy uploaded files from one storage backend to another
+    MigrateStorage {
+        /// Backend to copy from: local or s3 (Required)
+        #[arg(long, required = true, value_name = "BACKEND")]
+        from: String,
+
+        /// Backend to copy to: local or s3 (Required)
+        #[arg(long, required = true, value_name = "BACKEND")]
+        to: String,
+
+        /// Remove each file from the source after it has been copied (Optional)
+        #[arg(long, default_value_t = false)]
+        delete_source: bool,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Generate code from the collections
+    Gen {
+        #[command(subcommand)]
+        command: GenCommands,
+    },
 }
 
-#[derive(Serialize)]
-struct Info {
-    version: f32,
-    actix_web: f32,
-    actix_files: f32,
-    rusqlite: f32,
-    r2d2: f32,
-    r2d2_sqlite: f32,
-    serde: f32,
-    serde_json: f32,
-    rust_embed: f32,
-    mime_guess: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
-    iat: usize,
-    email: String,
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the effective configuration after the file, environment and flags are applied
+    Show,
 }
 
-type DbPool = Pool<SqliteConnectionManager>;
-struct AppData {
-    database: DbPool,
-    jwt_secret: String,
-    configs: Arc<RwLock<HashMap<String, String>>>,
+#[derive(Subcommand, Debug)]
+enum GenCommands {
+    /// Write record types and a client for /api/records
+    Types {
+        /// Language of the generated file: ts or rust (Required)
+        #[arg(long, required = true, value_parser = ["ts", "rust"])]
+        lang: String,
+
+        /// Directory the file is written to (Required)
+        #[arg(long, required = true, value_name = "DIR")]
+        out: PathBuf,
+    },
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
 
+    let (host, port) = match &args.command {
+        Some(Commands::Serve { host, port }) => (host.clone(), *port),
+        _ => (None, None),
+    };
+    let config = match Config::load(Overrides {
+        config: args.config,
+        data_dir: args.data_dir,
+        host,
+        port,
+    }) {
+        Ok(config) => config,
+        Err(error) => {
+            println!("Configuration could not be loaded: {}", error);
+            return Ok(());
+        }
+    };
+
     match args.command {
         None => {
-            Args::parse_from(&["moosedb", "--help"]);
+            Args::parse_from(["moosedb", "--help"]);
             Ok(())
         }
         Some(Commands::Upsecret) => {
-            match update_secret_key() {
+            match update_secret_key(&config) {
                 Ok(_) => println!("Secret token has been updated!"),
                 Err(error) => println!("Secret update failed! Reason: {}", error),
             }
             Ok(())
         }
         Some(Commands::Upsuper { email, password }) => {
-            match update_super_user(email, password) {
+            match update_super_user(&config, email, password) {
                 Ok(_) => println!("Super admin's password has been updated!"),
-                Err(_) => println!("Password update failed!"),
+                Err(error) => println!("Password update failed! Reason: {}", error),
             }
             Ok(())
         }
-        Some(Commands::Serve { host, port }) => {
-            let mut create_new_db = false;
-            let file_exists = Path::new("database.sqlite").exists();
-
-            Builder::from_env(env_logger::Env::new().default_filter_or("error")).init();
-
-            if !file_exists {
-                create_new_db = true;
+        Some(Commands::Disable2fa { email }) => {
+            match disable_two_factor(&config, email.clone()) {
+                Ok(true) => println!("Two-factor authentication has been disabled for {}", email),
+                Ok(false) => println!("Super admin with email '{}' not found.", email),
+                Err(error) => println!(
+                    "Disabling two-factor authentication failed! Reason: {}",
+                    error
+                ),
+            }
+            Ok(())
+        }
+        Some(Commands::MigrateStorage {
+            from,
+            to,
+            delete_source,
+        }) => {
+            match migrate_storage(&config, &from, &to, delete_source) {
+                Ok(copied) => println!("{} files have been copied from {} to {}", copied, from, to),
+                Err(error) => println!("Storage migration failed! Reason: {}", error),
+            }
+            Ok(())
+        }
+        Some(Commands::Config {
+            command: ConfigCommands::Show,
+        }) => {
+            match &config.file {
+                Some(file) => println!("# Read from {}", file.display()),
+                None => println!("# No config file, using defaults"),
+            }
+            print!("{}", config.to_toml());
+            Ok(())
+        }
+        Some(Commands::Gen {
+            command: GenCommands::Types { lang, out },
+        }) => {
+            match gen_types(&config, &lang, &out) {
+                Ok(path) => println!("Types have been written to {}", path.display()),
+                Err(error) => println!("Type generation failed! Reason: {}", error),
             }
-            let manager = SqliteConnectionManager::file("database.sqlite");
-            let pool = Pool::new(manager).expect("Failed to create pool");
-            let conn = pool.get().expect("Failed to get connection");
-            if let Err(e) = initialize_db(&conn, create_new_db) {
-                println!("Database could not be created: {}", e);
+            Ok(())
+        }
+        Some(Commands::Serve { .. }) => {
+            if let Err(e) = std::fs::create_dir_all(&config.data_dir) {
+                println!("Data directory could not be created: {}", e);
                 return Ok(());
             }
-
-            let configs = Arc::new(RwLock::new(load_configs(&conn).unwrap()));
-            let jwt_secret = configs.read().unwrap().get("secret").unwrap().clone();
-
-            println!("🚀 Listening at http://{}:{}", host, port);
-
-            HttpServer::new(move || {
-                let auth = HttpAuthentication::bearer(validator);
-                App::new()
-                    .app_data(web::Data::new(AppData {
-                        database: pool.clone(),
-                        jwt_secret: jwt_secret.clone(),
-                        configs: configs.clone(),
-                    }))
-                    .app_data(web::JsonConfig::default().limit(50 * 1024 * 1024))
-                    .wrap(middleware::Logger::default())
-                    .service(index)
-                    .route("/auth/login", web::post().to(login))
-                    .route("/uploads/{filename}", web::get().to(serve_upload))
-                    .service(
-                        web::scope("/admin/api")
-                            .wrap(auth.clone())
-                            .service(get_version)
-                            .service(get_setting)
-                            .service(update_setting_func)
-                            .service(create_collection)
-                            .service(get_collections)
-                            .service(delete_collection)
-                            .service(get_collection_records)
-                            .service(create_super_admin_func)
-                            .service(get_super_admins)
-                            .service(create_record)
-                            .service(update_your_password)
-                            .service(delete_collection_records)
-                            .service(update_collection),
-                    )
-                    .service(
-                        web::scope("/api")
-                            .service(get_version)
-                            .service(get_collection_data)
-                            .service(get_single_record),
-                    )
-                    .default_service(web::route().to(static_files))
-            })
-            .bind((host, port))?
-            .run()
-            .await
+            Builder::from_env(env_logger::Env::new().default_filter_or(&config.log_level)).init();
+
+            let started = MooseDb::builder()
+                .config(config)
+                .build()
+                .and_then(|db| db.serve());
+            let (server, addrs) = match started {
+                Ok(started) => started,
+                Err(e) => {
+                    println!("{}", e);
+                    return Ok(());
+                }
+            };
+            for addr in addrs {
+                println!("🚀 Listening at http://{}", addr);
+            }
+            server.await
         }
     }
 }
 
-#[get("/")]
-async fn index() -> Result<impl Responder> {
-    Ok(web::Json(Info {
-        version: 0.1,
-        actix_web: 4.0,
-        actix_files: 0.6,
-        rusqlite: 0.37,
-        r2d2: 0.8,
-        r2d2_sqlite: 0.31,
-        serde: 1.0,
-        serde_json: 1.0,
-        rust_embed: 8.0,
-        mime_guess: 2.0,
-    }))
-}
+fn migrate_storage(
+    config: &Config,
+    from: &str,
+    to: &str,
+    delete_source: bool,
+) -> Result<usize, String> {
+    if from == to {
+        return Err("Source and target backends must differ".to_string());
+    }
 
-async fn serve_upload(req: HttpRequest) -> HttpResponse {
-    let filename = req.match_info().get("filename").unwrap_or("");
-    let file_path = Path::new("uploads").join(filename);
+    let conn = open_db(config).map_err(|e| e.to_string())?;
+    let configs = load_configs(&conn).map_err(|e| e.to_string())?;
 
-    match std::fs::read(&file_path) {
-        Ok(bytes) => {
-            let mime = from_path(&file_path).first_or_octet_stream();
-            HttpResponse::Ok().content_type(mime.as_ref()).body(bytes)
-        }
-        Err(_) => HttpResponse::NotFound().body("File not found"),
-    }
+    let source = storage::build(from, &configs, &config.uploads_dir)?;
+    let target = storage::build(to, &configs, &config.uploads_dir)?;
+    let copied = storage::migrate(&conn, source.as_ref(), target.as_ref(), delete_source)?;
+
+    update_setting(&conn, "storage_backend", to).map_err(|e| e.to_string())?;
+    Ok(copied)
 }
 
-async fn static_files(req: HttpRequest) -> HttpResponse {
-    let path = req.path().trim_start_matches('/');
-    let file_path = if path.is_empty() { "index.html" } else { path };
+fn gen_types(config: &Config, lang: &str, out: &Path) -> Result<PathBuf, String> {
+    let conn = open_db(config).map_err(|e| e.to_string())?;
+    let collections = Schema::load(&conn).map_err(|e| e.to_string())?.all();
 
-    match Asset::get(file_path) {
-        Some(content) => {
-            let body = content.data.into_owned();
-            let mime = from_path(file_path).first_or_octet_stream();
-            HttpResponse::Ok().content_type(mime.as_ref()).body(body)
-        }
-        None => match Asset::get("index.html") {
-            Some(index_file) => HttpResponse::Ok()
-                .content_type("text/html")
-                .body(index_file.data.into_owned()),
-            None => HttpResponse::NotFound().body("404 Not Found"),
-        },
-    }
+    codegen::check_names(&collections)?;
+
+    let (file, source) = match lang {
+        "ts" => ("moosedb.ts", codegen::typescript(&collections)),
+        _ => ("moosedb.rs", codegen::rust(&collections)),
+    };
+
+    std::fs::create_dir_all(out).map_err(|e| e.to_string())?;
+    let path = out.join(file);
+    std::fs::write(&path, source).map_err(|e| e.to_string())?;
+    Ok(path)
 }
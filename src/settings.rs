@@ -0,0 +1,375 @@
+use crate::mailer::*;
+use crate::utils::random::generate_secret;
+use SettingKind::*;
+use std::collections::HashMap;
+
+/// Type of a setting's value, checked before it is written to `_configs`.
+pub enum SettingKind {
+    Text,
+    Integer { min: i64, max: i64 },
+    Boolean,
+    Choice(&'static [&'static str]),
+    Url,
+    // Generated on first start, never read or written through the API
+    Secret,
+}
+
+/// Schema of one key in `_configs`.
+pub struct Setting {
+    pub key: &'static str,
+    pub kind: SettingKind,
+    pub default: &'static str,
+    pub description: &'static str,
+    // Only read when the server starts
+    pub restart: bool,
+    // Can be written but its value is never sent back
+    pub sensitive: bool,
+    // Readable by every role, the dashboard needs it
+    pub public: bool,
+}
+
+impl Setting {
+    const fn new(
+        key: &'static str,
+        kind: SettingKind,
+        default: &'static str,
+        description: &'static str,
+    ) -> Self {
+        Setting {
+            key,
+            kind,
+            default,
+            description,
+            restart: false,
+            sensitive: false,
+            public: false,
+        }
+    }
+
+    const fn restart(mut self) -> Self {
+        self.restart = true;
+        self
+    }
+
+    const fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self
+    }
+
+    const fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.kind {
+            SettingKind::Text => "text",
+            SettingKind::Integer { .. } => "integer",
+            SettingKind::Boolean => "boolean",
+            SettingKind::Choice(_) => "choice",
+            SettingKind::Url => "url",
+            SettingKind::Secret => "secret",
+        }
+    }
+
+    /// Whether the value is never sent back through the API.
+    pub fn hidden(&self) -> bool {
+        self.sensitive || matches!(self.kind, SettingKind::Secret)
+    }
+
+    /// Value stored for a new database.
+    pub fn default_value(&self) -> String {
+        match self.kind {
+            SettingKind::Secret => generate_secret(),
+            _ => self.default.to_string(),
+        }
+    }
+
+    /// Checks `value` against the schema and returns it the way it is stored. Settings with an
+    /// empty default, like `smtp_port`, can be cleared with an empty value.
+    pub fn validate(&self, value: &str) -> Result<String, String> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() && self.default.is_empty() {
+            return Ok(String::new());
+        }
+
+        match self.kind {
+            SettingKind::Text => Ok(value.to_string()),
+            SettingKind::Integer { min, max } => match trimmed.parse::<i64>() {
+                Ok(number) if (min..=max).contains(&number) => Ok(number.to_string()),
+                _ => Err(format!(
+                    "'{}' must be a whole number between {} and {}",
+                    self.key, min, max
+                )),
+            },
+            SettingKind::Boolean => match trimmed.to_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Ok("true".to_string()),
+                "false" | "0" | "no" | "off" => Ok("false".to_string()),
+                _ => Err(format!("'{}' must be true or false", self.key)),
+            },
+            SettingKind::Choice(options) => {
+                if options.contains(&trimmed) {
+                    Ok(trimmed.to_string())
+                } else {
+                    Err(format!(
+                        "'{}' must be one of {}",
+                        self.key,
+                        options.join(", ")
+                    ))
+                }
+            }
+            SettingKind::Url => {
+                if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+                    Ok(trimmed.trim_end_matches('/').to_string())
+                } else {
+                    Err(format!("'{}' must be an http:// or https:// URL", self.key))
+                }
+            }
+            SettingKind::Secret => Err(format!("'{}' can not be changed", self.key)),
+        }
+    }
+}
+
+const DAY: i64 = 24 * 3600;
+
+pub static SETTINGS: &[Setting] = &[
+    Setting::new("secret", Secret, "", "Key used to sign tokens").restart(),
+    Setting::new(
+        "appname",
+        Text,
+        "MooseDB",
+        "Name shown in the dashboard and emails",
+    )
+    .public(),
+    Setting::new(
+        "records_per_page",
+        Integer { min: 1, max: 10000 },
+        "100",
+        "Records returned per page when a request does not ask for a page size",
+    )
+    .public(),
+    Setting::new(
+        "batch_max_operations",
+        Integer { min: 1, max: 10000 },
+        "100",
+        "Operations accepted in one /api/batch request",
+    ),
+    Setting::new(
+        "app_url",
+        Url,
+        "",
+        "Public URL of the server, needed to email password reset and verification links",
+    ),
+    Setting::new(
+        "storage_backend",
+        Choice(&["local", "s3"]),
+        "local",
+        "Where uploaded files are stored",
+    )
+    .restart(),
+    Setting::new("s3_endpoint", Url, "", "S3 endpoint URL").restart(),
+    Setting::new("s3_region", Text, "us-east-1", "S3 region").restart(),
+    Setting::new("s3_bucket", Text, "", "S3 bucket").restart(),
+    Setting::new("s3_access_key", Text, "", "S3 access key").restart(),
+    Setting::new("s3_secret_key", Text, "", "S3 secret key")
+        .restart()
+        .sensitive(),
+    Setting::new(
+        "s3_path_style",
+        Boolean,
+        "true",
+        "Put the bucket in the path instead of the host name",
+    )
+    .restart(),
+    Setting::new(
+        "s3_read_mode",
+        Choice(&["proxy", "redirect"]),
+        "proxy",
+        "Serve files through the server or redirect to a presigned URL",
+    )
+    .restart(),
+    Setting::new(
+        "s3_url_expiry",
+        Integer {
+            min: 1,
+            max: 7 * DAY,
+        },
+        "300",
+        "Seconds a presigned S3 URL stays valid",
+    )
+    .restart(),
+    Setting::new(
+        "file_token_expiry",
+        Integer {
+            min: 1,
+            max: 7 * DAY,
+        },
+        "300",
+        "Seconds a protected file token stays valid",
+    ),
+    Setting::new(
+        "uploads_cache_control",
+        Text,
+        "public, max-age=86400",
+        "Cache-Control header of public files",
+    ),
+    Setting::new(
+        "access_token_ttl",
+        Integer { min: 60, max: DAY },
+        "900",
+        "Seconds an access token stays valid",
+    ),
+    Setting::new(
+        "refresh_token_ttl",
+        Integer {
+            min: 300,
+            max: 365 * DAY,
+        },
+        "2592000",
+        "Seconds a session can be refreshed without logging in",
+    ),
+    Setting::new(
+        "smtp_host",
+        Text,
+        "",
+        "SMTP server, email is off when empty",
+    ),
+    Setting::new(
+        "smtp_port",
+        Integer { min: 1, max: 65535 },
+        "",
+        "SMTP port, 465 for tls and 587 otherwise when empty",
+    ),
+    Setting::new(
+        "smtp_tls",
+        Choice(&["starttls", "tls", "none"]),
+        "starttls",
+        "SMTP encryption",
+    ),
+    Setting::new("smtp_username", Text, "", "SMTP username"),
+    Setting::new("smtp_password", Text, "", "SMTP password").sensitive(),
+    Setting::new(
+        "smtp_from",
+        Text,
+        "MooseDB <no-reply@localhost>",
+        "Sender of outgoing emails",
+    ),
+    Setting::new(
+        "password_reset_ttl",
+        Integer {
+            min: 60,
+            max: 7 * DAY,
+        },
+        "3600",
+        "Seconds a password reset link stays valid",
+    ),
+    Setting::new(
+        "email_verification_ttl",
+        Integer {
+            min: 60,
+            max: 30 * DAY,
+        },
+        "86400",
+        "Seconds an email verification link stays valid",
+    ),
+    Setting::new(
+        "mail_reset_subject",
+        Text,
+        DEFAULT_RESET_SUBJECT,
+        "Subject of the password reset email",
+    ),
+    Setting::new(
+        "mail_reset_text",
+        Text,
+        DEFAULT_RESET_TEXT,
+        "Plain text body of the password reset email",
+    ),
+    Setting::new(
+        "mail_reset_html",
+        Text,
+        DEFAULT_RESET_HTML,
+        "HTML body of the password reset email",
+    ),
+    Setting::new(
+        "mail_verify_subject",
+        Text,
+        DEFAULT_VERIFY_SUBJECT,
+        "Subject of the email verification email",
+    ),
+    Setting::new(
+        "mail_verify_text",
+        Text,
+        DEFAULT_VERIFY_TEXT,
+        "Plain text body of the email verification email",
+    ),
+    Setting::new(
+        "mail_verify_html",
+        Text,
+        DEFAULT_VERIFY_HTML,
+        "HTML body of the email verification email",
+    ),
+    Setting::new(
+        "login_max_attempts",
+        Integer { min: 1, max: 1000 },
+        "5",
+        "Failed logins of one email before it is locked out",
+    ),
+    Setting::new(
+        "login_max_attempts_per_ip",
+        Integer {
+            min: 1,
+            max: 100000,
+        },
+        "20",
+        "Failed logins from one IP before it is locked out",
+    ),
+    Setting::new(
+        "login_attempt_window",
+        Integer { min: 1, max: DAY },
+        "900",
+        "Seconds failed logins are counted for, also the longest lockout",
+    ),
+    Setting::new(
+        "login_lockout_seconds",
+        Integer { min: 1, max: DAY },
+        "60",
+        "Seconds of the first lockout, doubled for each further failure",
+    ),
+    Setting::new(
+        "password_reset_max_requests",
+        Integer { min: 1, max: 1000 },
+        "3",
+        "Password reset emails one email can ask for within the window",
+    ),
+    Setting::new(
+        "password_reset_max_requests_per_ip",
+        Integer {
+            min: 1,
+            max: 100000,
+        },
+        "10",
+        "Password reset emails one IP can ask for within the window",
+    ),
+    Setting::new(
+        "password_reset_window",
+        Integer { min: 1, max: DAY },
+        "3600",
+        "Seconds password reset requests are counted for",
+    ),
+];
+
+pub fn find(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == key)
+}
+
+/// Integer setting from the loaded configs, falls back to the registry default when the stored
+/// value is missing or does not validate.
+pub fn int(configs: &HashMap<String, String>, key: &str) -> i64 {
+    let setting = find(key).expect("unknown integer setting");
+    configs
+        .get(key)
+        .and_then(|value| setting.validate(value).ok())
+        .and_then(|value| value.parse().ok())
+        .or_else(|| setting.default.parse().ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,60 @@
+use super::Storage;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<(), String> {
+        if !self.root.exists() {
+            std::fs::create_dir_all(&self.root).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(self.root.join(key), bytes).map_err(|e| e.to_string())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, String> {
+        Ok(self.root.join(key).is_file())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match std::fs::remove_file(self.root.join(key)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        Ok(entries
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with(prefix))
+            .collect())
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.root.join(key))
+    }
+}
@@ -0,0 +1,129 @@
+pub mod local;
+pub mod s3;
+pub mod staged;
+
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Response to a client read that was passed on to the backend, see [`Storage::fetch`].
+pub struct FetchedObject {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Where uploaded files and their thumbnails are kept. Keys are the stored
+/// file names from `_files`, e.g. `photo-123456789.jpg`.
+pub trait Storage: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), String>;
+
+    /// Returns `Ok(None)` when the key does not exist.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    fn exists(&self, key: &str) -> Result<bool, String>;
+
+    fn delete(&self, key: &str) -> Result<(), String>;
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String>;
+
+    /// A URL clients can be redirected to instead of proxying the bytes
+    /// through the server. `None` means reads must be proxied.
+    fn read_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+
+    /// Path of the object on the local filesystem. When set, files are
+    /// streamed from disk instead of going through [`Storage::fetch`].
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Read an object on behalf of an HTTP client. `headers` holds the
+    /// client's range and conditional headers, which backends that support
+    /// them should honour and reflect in the returned status and headers.
+    fn fetch(
+        &self,
+        _method: &str,
+        key: &str,
+        _headers: &[(String, String)],
+    ) -> Result<Option<FetchedObject>, String> {
+        Ok(self.get(key)?.map(|body| FetchedObject {
+            status: 200,
+            headers: Vec::new(),
+            body,
+        }))
+    }
+}
+
+pub fn build(
+    backend: &str,
+    configs: &HashMap<String, String>,
+    uploads_dir: &Path,
+) -> Result<Arc<dyn Storage>, String> {
+    match backend {
+        "local" => Ok(Arc::new(local::LocalStorage::new(uploads_dir))),
+        "s3" => Ok(Arc::new(s3::S3Storage::from_configs(configs)?)),
+        other => Err(format!(
+            "Unknown storage backend '{}'. Expected local or s3",
+            other
+        )),
+    }
+}
+
+// Build the backend selected by the `storage_backend` setting
+pub fn from_configs(
+    configs: &HashMap<String, String>,
+    uploads_dir: &Path,
+) -> Result<Arc<dyn Storage>, String> {
+    let backend = configs
+        .get("storage_backend")
+        .map(|s| s.as_str())
+        .unwrap_or("local");
+    build(backend, configs, uploads_dir)
+}
+
+// Copy the files recorded in `_files` and their thumbnails from one backend to another, returns
+// the number of copied objects. Other objects in the source are left where they are.
+pub fn migrate(
+    conn: &Connection,
+    from: &dyn Storage,
+    to: &dyn Storage,
+    delete_source: bool,
+) -> Result<usize, String> {
+    let files: Vec<(String, String)> = conn
+        .prepare("SELECT stored_name, mime_type FROM _files ORDER BY id")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        })
+        .map_err(|e| e.to_string())?;
+    let mut copied = 0;
+
+    for (stored_name, mime_type) in files {
+        let mut objects = vec![(stored_name.clone(), mime_type)];
+        // Thumbnails are not in `_files`, their extension is the format they were encoded in
+        for thumbnail in from.list(&format!("{}.thumb-", stored_name))? {
+            let content_type = mime_guess::from_path(&thumbnail).first_or_octet_stream();
+            objects.push((thumbnail, content_type.to_string()));
+        }
+
+        for (key, content_type) in objects {
+            let bytes = match from.get(&key)? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+
+            to.put(&key, &bytes, &content_type)?;
+            if delete_source {
+                from.delete(&key)?;
+            }
+
+            copied += 1;
+            println!("Copied {}", key);
+        }
+    }
+
+    Ok(copied)
+}
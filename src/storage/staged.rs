@@ -0,0 +1,80 @@
+use super::{FetchedObject, Storage};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Storage handed to a database write: uploads are written straight away and removed again by
+/// [`Staged::discard`] when the write fails, deletions wait for [`Staged::commit`] once it has
+/// been committed.
+pub struct Staged {
+    inner: Arc<dyn Storage>,
+    written: Mutex<Vec<String>>,
+    deleted: Mutex<Vec<String>>,
+}
+
+impl Staged {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Staged {
+            inner,
+            written: Mutex::new(Vec::new()),
+            deleted: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn commit(&self) {
+        for key in self.deleted.lock().unwrap().iter() {
+            if let Err(err) = self.inner.delete(key) {
+                log::warn!("Failed to delete '{}' after a write: {}", key, err);
+            }
+        }
+    }
+
+    pub fn discard(&self) {
+        for key in self.written.lock().unwrap().iter() {
+            if let Err(err) = self.inner.delete(key) {
+                log::warn!("Failed to delete '{}' of a failed write: {}", key, err);
+            }
+        }
+    }
+}
+
+impl Storage for Staged {
+    fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), String> {
+        self.inner.put(key, bytes, content_type)?;
+        self.written.lock().unwrap().push(key.to_string());
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        self.inner.get(key)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, String> {
+        self.inner.exists(key)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.deleted.lock().unwrap().push(key.to_string());
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        self.inner.list(prefix)
+    }
+
+    fn read_url(&self, key: &str) -> Option<String> {
+        self.inner.read_url(key)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.inner.local_path(key)
+    }
+
+    fn fetch(
+        &self,
+        method: &str,
+        key: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<FetchedObject>, String> {
+        self.inner.fetch(method, key, headers)
+    }
+}
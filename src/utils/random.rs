@@ -1,7 +1,6 @@
 use rand::Rng;
 
 #[allow(dead_code)]
-
 pub fn generate_uid(len: usize) -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                             abcdefghijklmnopqrstuvwxyz\
@@ -0,0 +1,213 @@
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    output
+}
+
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+fn url_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn current_step() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / STEP_SECONDS
+}
+
+// RFC 4226 HOTP value for `counter`
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    code % 10u32.pow(DIGITS)
+}
+
+/// A new random 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 20] = rand::rng().random();
+    base32_encode(&bytes)
+}
+
+/// `otpauth://` URI that authenticator apps read from a QR code.
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        url_encode(issuer),
+        url_encode(account),
+        secret,
+        url_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Checks `code` against the previous, current and next time step. Returns the
+/// matching step, which must be greater than `last_step` so a code can't be replayed.
+pub fn verify(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    verify_at(secret, code, last_step, current_step())
+}
+
+fn verify_at(secret: &str, code: &str, last_step: Option<i64>, now: u64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let code: u32 = code.trim().replace(' ', "").parse().ok()?;
+
+    [now - 1, now, now + 1]
+        .into_iter()
+        .find(|step| hotp(&key, *step) == code)
+        .map(|step| step as i64)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+}
+
+// Code an authenticator app would show right now
+#[cfg(test)]
+pub(crate) fn current_code(secret: &str) -> String {
+    let key = base32_decode(secret).unwrap();
+    format!("{:06}", hotp(&key, current_step()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 secret of RFC 6238 appendix B, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn code_at(secret: &str, step: u64) -> String {
+        format!("{:06}", hotp(&base32_decode(secret).unwrap(), step))
+    }
+
+    #[test]
+    fn rfc_6238_sha1_vectors() {
+        // Unix time and the 8 digit code of the RFC, of which we show the last 6 digits
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        let key = base32_decode(RFC_SECRET).unwrap();
+        for (time, code) in vectors {
+            assert_eq!(
+                hotp(&key, time / STEP_SECONDS),
+                code % 1_000_000,
+                "at {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        assert_eq!(base32_encode(b"12345678901234567890"), RFC_SECRET);
+        for len in 0..=20 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            assert_eq!(base32_decode(&base32_encode(&bytes)).unwrap(), bytes);
+        }
+
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_decode(&secret).unwrap().len(), 20);
+
+        // Authenticator apps show secrets in lower case, grouped and padded
+        assert_eq!(
+            base32_decode("gezd gnbv gy3t qojq gezd gnbv gy3t qojq====").unwrap(),
+            b"12345678901234567890"
+        );
+        assert_eq!(base32_decode("GEZD1"), None);
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift() {
+        let now = 1_000_000;
+        for step in [now - 1, now, now + 1] {
+            let code = code_at(RFC_SECRET, step);
+            assert_eq!(verify_at(RFC_SECRET, &code, None, now), Some(step as i64));
+        }
+        for step in [now - 2, now + 2] {
+            let code = code_at(RFC_SECRET, step);
+            assert_eq!(verify_at(RFC_SECRET, &code, None, now), None);
+        }
+        assert_eq!(verify_at(RFC_SECRET, "not a code", None, now), None);
+    }
+
+    #[test]
+    fn rejects_a_reused_step() {
+        let now = 1_000_000;
+        let code = code_at(RFC_SECRET, now);
+        let step = verify_at(RFC_SECRET, &code, None, now).unwrap();
+
+        assert_eq!(verify_at(RFC_SECRET, &code, Some(step), now), None);
+        assert_eq!(verify_at(RFC_SECRET, &code, Some(step + 1), now), None);
+
+        // An older code of the window is no good once a newer one has been used
+        let previous = code_at(RFC_SECRET, now - 1);
+        assert_eq!(verify_at(RFC_SECRET, &previous, Some(step), now), None);
+
+        let next = code_at(RFC_SECRET, now + 1);
+        assert_eq!(
+            verify_at(RFC_SECRET, &next, Some(step), now),
+            Some(step + 1)
+        );
+    }
+}
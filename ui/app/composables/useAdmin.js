@@ -0,0 +1,31 @@
+// Role and collection permissions of the logged in admin, fetched once from /admin/api/me
+export const useAdmin = () => {
+    const admin = useState('admin', () => null);
+    const { authFetch } = useAuthFetch();
+
+    const fetchAdmin = async (force = false) => {
+        if (admin.value && !force) return admin.value;
+        try {
+            const data = await authFetch('/admin/api/me');
+            if (data.success == true) {
+                admin.value = data;
+            }
+        } catch (error) {
+            console.error('Failed to fetch:', error);
+        }
+        return admin.value;
+    };
+
+    const isAdmin = computed(() => ['owner', 'admin'].includes(admin.value?.role));
+    const isOwner = computed(() => admin.value?.role == 'owner');
+
+    const canWrite = (collection_id) => {
+        const role = admin.value?.role;
+        if (role == 'owner' || role == 'admin') return true;
+        if (role != 'editor') return false;
+        const permissions = admin.value?.permissions;
+        return !permissions || permissions[collection_id] == 'write';
+    };
+
+    return { admin, fetchAdmin, isAdmin, isOwner, canWrite };
+};
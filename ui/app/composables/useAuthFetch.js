@@ -1,6 +1,6 @@
 export const useAuthFetch = () => {
-    const { getToken } = useAuthToken();
-    const authFetch = async (url, options = {}) => {
+    const { getToken, refresh } = useAuthToken();
+    const request = (url, options) => {
         const token = getToken();
         const defaultOptions = {
             headers: {
@@ -11,10 +11,16 @@ export const useAuthFetch = () => {
             },
             ...options
         };
+        return $fetch(url, defaultOptions);
+    };
+    const authFetch = async (url, options = {}) => {
         try {
-            return await $fetch(url, defaultOptions);
+            return await request(url, options);
         } catch (error) {
             if (error.status === 401) {
+                if (await refresh()) {
+                    return await request(url, options);
+                }
                 console.log(error);
                 const { removeToken } = useAuthToken();
                 removeToken();
@@ -24,4 +30,4 @@ export const useAuthFetch = () => {
         }
     };
     return { authFetch };
-};
\ No newline at end of file
+};
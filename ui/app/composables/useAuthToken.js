@@ -1,24 +1,56 @@
 export const useAuthToken = () => {
+    const secure = import.meta.client ? window.location.protocol === 'https:' : true;
     const token = useCookie('moose_auth_token', {
         maxAge: 60 * 60,
         sameSite: 'strict',
-        secure: import.meta.client ? window.location.protocol === 'https:' : true,
+        secure,
+        path: '/'
+    });
+    const refreshToken = useCookie('moose_refresh_token', {
+        maxAge: 30 * 24 * 60 * 60,
+        sameSite: 'strict',
+        secure,
         path: '/'
     });
     
-    const setToken = (newToken) => {
+    const setToken = (newToken, newRefreshToken) => {
         token.value = newToken;
+        if (newRefreshToken) {
+            refreshToken.value = newRefreshToken;
+        }
     };
     const removeToken = () => {
         token.value = null;
+        refreshToken.value = null;
+        useState('admin').value = null;
     };
     const getToken = () => {
         return token.value;
     };
+    const getRefreshToken = () => {
+        return refreshToken.value;
+    };
+    // Swap the refresh token for a new pair, returns false when the session is gone
+    const refresh = async () => {
+        if (!refreshToken.value) return false;
+        try {
+            const data = await $fetch('/auth/refresh', {
+                method: 'POST',
+                body: { refresh_token: refreshToken.value }
+            });
+            setToken(data.token, data.refresh_token);
+            return true;
+        } catch (error) {
+            removeToken();
+            return false;
+        }
+    };
     return {
         token,
         setToken,
         removeToken,
-        getToken
+        getToken,
+        getRefreshToken,
+        refresh
     };
-};
\ No newline at end of file
+};
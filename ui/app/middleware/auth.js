@@ -1,8 +1,8 @@
-export default defineNuxtRouteMiddleware((to, from) => {
-    const { getToken } = useAuthToken();
+export default defineNuxtRouteMiddleware(async (to, from) => {
+    const { getToken, getRefreshToken, refresh } = useAuthToken();
     const token = getToken();
     
-    if (!token) {
+    if (!token && !(getRefreshToken() && await refresh())) {
         return navigateTo('/_/auth/login');
     }
-});
\ No newline at end of file
+});